@@ -1,22 +1,40 @@
 use std::error::Error;
 use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 mod command_resolver;
+mod fair_log;
 use command_resolver::CommandResolver;
+use common::protocol::{BoardInfo, Item, Notification, ServerMessage};
+use fair_log::Verification;
 
 const INSERT_KEY: char = 't';
+const INSERT_MANY_KEY: char = 'm';
 const ASK_KEY: char = 'y';
+const JACKPOT_KEY: char = 'g';
+const WALLET_KEY: char = 'w';
+const INVENTORY_KEY: char = 'i';
+const LIST_MACHINES_KEY: char = 'l';
+const JOIN_MACHINE_KEY: char = 'j';
+const LEAVE_MACHINE_KEY: char = 'x';
+const INSERT_AT_KEY: char = 'd';
+const BOARD_KEY: char = 'o';
+const PLAY_FAIR_KEY: char = 'p';
+const VERIFY_KEY: char = 'v';
 const QUIT_KEY: char = 'q';
 
 /// Procesador de argumentos del cliente
 pub struct ClientConfig {
     hostname: String,
     servicename: String,
+    name: Option<String>,
+    token: Option<String>,
 }
 
 impl ClientConfig {
     /// Crea la instancia.
-    /// Se asume que el primer argumento es el path del ejecutable.
+    /// Se asume que el primer argumento es el path del ejecutable,
+    /// seguido de host, puerto y opcionalmente nombre y token del jugador.
     pub fn build(mut args: impl Iterator<Item = String>) -> Result<ClientConfig, &'static str> {
         // skip first arg
         args.next();
@@ -31,32 +49,81 @@ impl ClientConfig {
             None => return Err("Could not get the servicename of the server"),
         };
 
+        // Opcionales: si faltan se piden por stdin
+        let name = args.next();
+        let token = args.next();
+
         Ok(ClientConfig {
             hostname,
             servicename,
+            name,
+            token,
         })
     }
 }
 
 pub fn run(config: ClientConfig) -> Result<(), Box<dyn Error>> {
-    let mut resolver = CommandResolver::new(config.hostname, config.servicename)?;
+    let mut resolver = CommandResolver::new(config.hostname, config.servicename, print_broadcast)?;
+
+    let name = match config.name {
+        Some(name) => name,
+        None => read_line("Player name:")?,
+    };
+    let token = match config.token {
+        Some(token) => token,
+        None => read_line("Token (used to recognize you when you come back):")?,
+    };
+    let session_id = resolver.login(name.clone(), token)?;
+    println!("Welcome {name}! (session {session_id})\n");
 
     loop {
         let option = read_option()?;
         match option {
             QUIT_KEY => return handle_quit(&mut resolver),
             INSERT_KEY => handle_insert(&mut resolver)?,
+            INSERT_MANY_KEY => handle_insert_many(&mut resolver)?,
             ASK_KEY => handle_ask(&mut resolver)?,
+            JACKPOT_KEY => handle_jackpot(&mut resolver)?,
+            WALLET_KEY => handle_wallet(&mut resolver)?,
+            INVENTORY_KEY => handle_inventory(&mut resolver)?,
+            LIST_MACHINES_KEY => handle_list_machines(&mut resolver)?,
+            JOIN_MACHINE_KEY => handle_join_machine(&mut resolver)?,
+            LEAVE_MACHINE_KEY => handle_leave_machine(&mut resolver)?,
+            INSERT_AT_KEY => handle_insert_at(&mut resolver)?,
+            BOARD_KEY => handle_board(&mut resolver)?,
+            PLAY_FAIR_KEY => handle_play_fair(&mut resolver)?,
+            VERIFY_KEY => handle_verify(&mut resolver)?,
             other => println!("[{other}] is not a valid option\n"),
         }
     }
 }
 
+fn read_line(prompt: &str) -> Result<String, Box<dyn Error>> {
+    println!("{prompt}");
+
+    let mut input = String::new();
+
+    io::stdin().read_line(&mut input)?;
+
+    Ok(input.trim().to_string())
+}
+
 fn read_option() -> Result<char, Box<dyn Error>> {
     loop {
         println!("Choose an action:");
         println!(" {INSERT_KEY} : Insert coin");
+        println!(" {INSERT_MANY_KEY} : Insert many coins");
         println!(" {ASK_KEY} : Check coins");
+        println!(" {JACKPOT_KEY} : Check jackpot");
+        println!(" {WALLET_KEY} : Check wallet");
+        println!(" {INVENTORY_KEY} : Show inventory");
+        println!(" {LIST_MACHINES_KEY} : List machines");
+        println!(" {JOIN_MACHINE_KEY} : Join a machine");
+        println!(" {LEAVE_MACHINE_KEY} : Leave the machine");
+        println!(" {INSERT_AT_KEY} : Drop a coin on a lane");
+        println!(" {BOARD_KEY} : Show the board");
+        println!(" {PLAY_FAIR_KEY} : Play provably fair");
+        println!(" {VERIFY_KEY} : Verify fair plays");
         println!(" {QUIT_KEY} : Quit");
 
         let mut input = String::new();
@@ -77,13 +144,21 @@ fn read_option() -> Result<char, Box<dyn Error>> {
 }
 
 fn handle_quit(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
+    // Al terminar, el servidor revela la semilla de las últimas jugadas
+    handle_verify(resolver)?;
     resolver.leave();
     println!("Closing the application...");
     Ok(())
 }
 
 fn handle_insert(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
-    let fell = resolver.insert_coin()?;
+    let fell = match resolver.insert_coin() {
+        Ok(fell) => fell,
+        Err(e) => {
+            println!("{e}\n");
+            return Ok(());
+        }
+    };
 
     if fell == 0 {
         println!("No coins fell. Bad luck.\n");
@@ -94,10 +169,261 @@ fn handle_insert(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+fn handle_insert_many(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
+    let n = read_amount()?;
+
+    let (outcomes, total) = match resolver.insert_coins(n) {
+        Ok(result) => result,
+        Err(e) => {
+            println!("{e}\n");
+            return Ok(());
+        }
+    };
+
+    for (i, fell) in outcomes.iter().enumerate() {
+        println!(" Coin {}: {fell} coins fell", i + 1);
+    }
+
+    if total == 0 {
+        println!("No coins fell. Bad luck.\n");
+    } else {
+        println!("Congrats! You won {total} coins!\n");
+    }
+
+    Ok(())
+}
+
+fn handle_insert_at(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
+    let lane = loop {
+        match read_line("Which lane?")?.parse::<u32>() {
+            Ok(lane) => break lane,
+            Err(_) => println!("Should be a lane number\n"),
+        }
+    };
+
+    match resolver.insert_coin_at(lane) {
+        Ok((landed, 0)) => println!("The coin landed on lane {landed}. No coins fell.\n"),
+        Ok((landed, fell)) => {
+            println!("The coin landed on lane {landed}. Congrats! You won {fell} coins!\n")
+        }
+        Err(e) => println!("{e}\n"),
+    }
+
+    Ok(())
+}
+
+fn handle_board(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
+    match resolver.consult_board() {
+        Ok(board) => print_board(&board),
+        Err(e) => println!("{e}\n"),
+    }
+
+    Ok(())
+}
+
+/// Dibuja el estante con el empujador arriba y el borde abajo.
+fn print_board(board: &BoardInfo) {
+    println!("Machine {} (pusher)", board.machine);
+    let lanes: String = (0..board.width).map(|lane| format!("{lane:>4}")).collect();
+    println!("  {lanes}");
+    for row in board.cells.chunks(board.width.max(1) as usize) {
+        let cells: String = row.iter().map(|coins| format!("{coins:>4}")).collect();
+        println!("  {cells}");
+    }
+    println!("  {} (edge)\n", "-".repeat(4 * board.width as usize));
+}
+
+fn handle_play_fair(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
+    // Las monedas jugadas con la semilla anterior se revisan antes de cambiarla
+    handle_verify(resolver)?;
+    let mut seed = read_line("Your seed (empty for a random one):")?;
+    if seed.is_empty() {
+        seed = random_seed();
+    }
+
+    match resolver.play_fair(seed.clone()) {
+        Ok(commitment) => println!(
+            "Playing with your seed {seed}.\nServer seed hash: {commitment}\n\
+             Use [{VERIFY_KEY}] to check your coins\n"
+        ),
+        Err(e) => println!("{e}\n"),
+    }
+
+    Ok(())
+}
+
+fn handle_verify(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
+    match resolver.verify() {
+        Ok(Verification {
+            fair: 0,
+            unverifiable: 0,
+        }) => {}
+        Ok(verification) => {
+            if verification.fair > 0 {
+                println!("All {} coins were fair", verification.fair);
+            }
+            if verification.unverifiable > 0 {
+                println!(
+                    "{} coins could not be verified: they fell on a board, \
+                     or the server did not publish how the machine pays",
+                    verification.unverifiable
+                );
+            }
+            println!();
+        }
+        Err(e) => println!("Verification failed: {e}\n"),
+    }
+
+    Ok(())
+}
+
+/// Semilla para quien no quiere elegir una. No necesita ser secreta:
+/// alcanza con que el servidor no la conozca de antemano.
+fn random_seed() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos());
+    format!("{:x}{:x}", nanos, std::process::id())
+}
+
+fn read_amount() -> Result<u32, Box<dyn Error>> {
+    loop {
+        println!("How many coins?");
+
+        let mut input = String::new();
+
+        io::stdin().read_line(&mut input)?;
+
+        match input.trim().parse::<u32>() {
+            Ok(n) if n > 0 => return Ok(n),
+            _ => println!("Should be a positive number\n"),
+        }
+    }
+}
+
 fn handle_ask(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
-    let pool = resolver.consult_pool()?;
+    let pool = match resolver.consult_pool() {
+        Ok(pool) => pool,
+        Err(e) => {
+            println!("{e}\n");
+            return Ok(());
+        }
+    };
 
     println!("There are {pool} coins in the machine\n");
 
     Ok(())
 }
+
+fn handle_jackpot(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
+    match resolver.consult_jackpot() {
+        Ok(0) => println!("The machine has no jackpot right now\n"),
+        Ok(coins) => println!("The jackpot is {coins} coins\n"),
+        Err(e) => println!("{e}\n"),
+    }
+
+    Ok(())
+}
+
+fn handle_wallet(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
+    let balance = resolver.consult_balance()?;
+
+    println!("You have {balance} coins in your wallet\n");
+
+    Ok(())
+}
+
+fn handle_inventory(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
+    for (item, count) in resolver.consult_inventory()? {
+        println!(" {count} x {}", item_name(item));
+    }
+    println!();
+
+    Ok(())
+}
+
+fn item_name(item: Item) -> &'static str {
+    match item {
+        Item::Multiplier => "multiplier token",
+        Item::FreeDrop => "free drop",
+        Item::Badge => "badge",
+    }
+}
+
+fn handle_list_machines(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
+    for machine in resolver.list_machines()? {
+        println!(
+            " Machine {}: {} coins, {} players",
+            machine.id, machine.pool, machine.players
+        );
+    }
+    println!();
+
+    Ok(())
+}
+
+fn handle_join_machine(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
+    let id = loop {
+        match read_line("Which machine?")?.parse::<u32>() {
+            Ok(id) => break id,
+            Err(_) => println!("Should be a machine number\n"),
+        }
+    };
+
+    match resolver.join_machine(id) {
+        Ok(None) => println!("You are now playing at machine {id}\n"),
+        Ok(Some(position)) => println!(
+            "Machine {id} is full. You are number {position} in the queue; \
+             you will be seated when someone leaves\n"
+        ),
+        Err(e) => println!("{e}\n"),
+    }
+
+    Ok(())
+}
+
+fn handle_leave_machine(resolver: &mut CommandResolver) -> Result<(), Box<dyn Error>> {
+    match resolver.leave_machine() {
+        Ok(id) => println!("You left machine {id}\n"),
+        Err(e) => println!("{e}\n"),
+    }
+
+    Ok(())
+}
+
+fn print_broadcast(msg: ServerMessage) {
+    match msg {
+        ServerMessage::Notification(notification) => print_notification(notification),
+        ServerMessage::Jackpot {
+            machine,
+            player,
+            coins,
+        } => println!("[!] JACKPOT! {player} won {coins} coins at machine {machine}"),
+        ServerMessage::ItemsWon { machine, items } => {
+            for item in items {
+                println!(
+                    "[!] You won a prize at machine {machine}: {}",
+                    item_name(item)
+                )
+            }
+        }
+        ServerMessage::ShuttingDown(secs) => {
+            println!("[!] The server is shutting down in {secs} seconds")
+        }
+        _ => (),
+    }
+}
+
+fn print_notification(notification: Notification) {
+    match notification {
+        Notification::PoolChanged { machine, pool } => {
+            println!("[!] There are now {pool} coins in machine {machine}")
+        }
+        Notification::CoinsFell { player, coins } => println!("[!] {player} won {coins} coins"),
+        Notification::PlayerJoined(player) => println!("[!] {player} joined the game"),
+        Notification::PlayerLeft(player) => println!("[!] {player} left the game"),
+        Notification::Seated(machine) => {
+            println!("[!] A seat is free: you are now playing at machine {machine}")
+        }
+    }
+}
@@ -1,44 +1,336 @@
 use std::error::Error;
 use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
 
-use common::protocol::{ClientMessage, ServerMessage, StreamToServer};
+use common::protocol::{
+    BoardInfo, ClientMessage, Item, MachineInfo, ProtocolErrorKind, ServerMessage, StreamToServer,
+};
+
+use super::fair_log::{FairLog, Verification};
 
 pub struct CommandResolver {
     stream: StreamToServer,
+    responses: Receiver<ServerMessage>,
+    // Solo mientras se juega de manera verificable
+    fair_log: Option<FairLog>,
 }
 
 impl CommandResolver {
-    pub fn new(hostname: String, servicename: String) -> Result<CommandResolver, Box<dyn Error>> {
+    /// Se conecta al servidor y lanza un thread que recibe sus mensajes.
+    /// Las notificaciones y los anuncios de pozo se pasan a `on_broadcast`
+    /// apenas llegan; el resto son respuestas a los comandos.
+    pub fn new<F>(
+        hostname: String,
+        servicename: String,
+        on_broadcast: F,
+    ) -> Result<CommandResolver, Box<dyn Error>>
+    where
+        F: Fn(ServerMessage) + Send + 'static,
+    {
         let mut addr = hostname.clone();
         addr.push(':');
         addr.push_str(&servicename);
 
         let tcp_stream = TcpStream::connect(addr)?;
-        let stream = StreamToServer::new(tcp_stream);
-        Ok(CommandResolver { stream })
+        let mut stream = StreamToServer::new(tcp_stream);
+        stream.handshake()?;
+
+        let reader = stream.try_clone()?;
+        let (sender, responses) = mpsc::channel();
+        thread::spawn(move || listen(reader, sender, on_broadcast));
+
+        Ok(CommandResolver {
+            stream,
+            responses,
+            fair_log: None,
+        })
+    }
+
+    /// Identifica al jugador. Devuelve el id de la sesión.
+    pub fn login(&mut self, name: String, token: String) -> Result<u64, Box<dyn Error>> {
+        self.stream
+            .send_message(ClientMessage::Hello { name, token })?;
+
+        let response = self.recv_response();
+
+        match response {
+            Ok(ServerMessage::Welcome(session_id)) => Ok(session_id),
+            Ok(ServerMessage::LoginRejected(reason)) => {
+                Err(format!("Login rejected: {reason}").into())
+            }
+            Ok(_) => Err("Unexpected server response".into()),
+            Err(e) => Err(e),
+        }
     }
 
     pub fn insert_coin(&mut self) -> Result<u32, Box<dyn Error>> {
         self.stream.send_message(ClientMessage::Insert)?;
 
-        let response = self.stream.recv_message();
+        let response = self.recv_response();
+
+        match response {
+            Ok(ServerMessage::FellCoins(n)) => {
+                self.record(&[n]);
+                Ok(n)
+            }
+            Ok(ServerMessage::InsufficientFunds(balance)) => Err(insufficient_funds(balance)),
+            Ok(ServerMessage::NotInMachine) => Err(not_in_machine()),
+            Ok(ServerMessage::RevealRequired(max)) => Err(reveal_required(max)),
+            Ok(ServerMessage::PayoutChanged) => Err(payout_changed()),
+            Ok(_) => Err("Unexpected server response".into()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Arroja `n` monedas de una vez.
+    /// Devuelve las monedas que cayeron con cada una y el total ganado.
+    pub fn insert_coins(&mut self, n: u32) -> Result<(Vec<u32>, u32), Box<dyn Error>> {
+        self.stream.send_message(ClientMessage::InsertMany(n))?;
+
+        let response = self.recv_response();
+
+        match response {
+            Ok(ServerMessage::FellCoinsBatch { outcomes, total }) => {
+                self.record(&outcomes);
+                Ok((outcomes, total))
+            }
+            Ok(ServerMessage::BatchTooLarge(max)) => {
+                Err(format!("Can't insert more than {max} coins at once").into())
+            }
+            Ok(ServerMessage::PoolFull(room)) => {
+                Err(format!("The machine is almost full: only {room} coins fit at once").into())
+            }
+            Ok(ServerMessage::InsufficientFunds(balance)) => Err(insufficient_funds(balance)),
+            Ok(ServerMessage::NotInMachine) => Err(not_in_machine()),
+            Ok(ServerMessage::RevealRequired(max)) => Err(reveal_required(max)),
+            Ok(ServerMessage::PayoutChanged) => Err(payout_changed()),
+            Ok(_) => Err("Unexpected server response".into()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Arroja una moneda sobre un carril del estante.
+    /// Devuelve el carril en el que terminó y cuántas monedas cayeron.
+    pub fn insert_coin_at(&mut self, lane: u32) -> Result<(u32, u32), Box<dyn Error>> {
+        self.stream.send_message(ClientMessage::InsertAt(lane))?;
+
+        let response = self.recv_response();
+
+        match response {
+            Ok(ServerMessage::Dropped { lane, fell }) => {
+                self.record(&[fell]);
+                Ok((lane, fell))
+            }
+            Ok(ServerMessage::InvalidLane(0)) => Err("The board has no lanes".into()),
+            Ok(ServerMessage::InvalidLane(width)) => {
+                Err(format!("The board only has lanes 0 to {}", width - 1).into())
+            }
+            Ok(ServerMessage::NotABoard(id)) => Err(not_a_board(id)),
+            Ok(ServerMessage::InsufficientFunds(balance)) => Err(insufficient_funds(balance)),
+            Ok(ServerMessage::NotInMachine) => Err(not_in_machine()),
+            Ok(ServerMessage::RevealRequired(max)) => Err(reveal_required(max)),
+            Ok(ServerMessage::PayoutChanged) => Err(payout_changed()),
+            Ok(_) => Err("Unexpected server response".into()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn consult_board(&mut self) -> Result<BoardInfo, Box<dyn Error>> {
+        self.stream.send_message(ClientMessage::ConsultBoard)?;
+
+        let response = self.recv_response();
 
         match response {
-            Ok(ServerMessage::FellCoins(n)) => Ok(n),
-            Ok(_) => panic!("Invalid server response"),
-            Err(e) => Err(Box::new(e)),
+            Ok(ServerMessage::BoardState(board)) => Ok(board),
+            Ok(ServerMessage::NotABoard(id)) => Err(not_a_board(id)),
+            Ok(ServerMessage::NotInMachine) => Err(not_in_machine()),
+            Ok(_) => Err("Unexpected server response".into()),
+            Err(e) => Err(e),
         }
     }
 
     pub fn consult_pool(&mut self) -> Result<u32, Box<dyn Error>> {
         self.stream.send_message(ClientMessage::ConsultPool)?;
 
-        let response = self.stream.recv_message();
+        let response = self.recv_response();
 
         match response {
             Ok(ServerMessage::PoolState(n)) => Ok(n),
-            Ok(_) => panic!("Invalid server response"),
-            Err(e) => Err(Box::new(e)),
+            Ok(ServerMessage::NotInMachine) => Err(not_in_machine()),
+            Ok(_) => Err("Unexpected server response".into()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Monedas en el pozo de la máquina donde está sentado.
+    pub fn consult_jackpot(&mut self) -> Result<u32, Box<dyn Error>> {
+        self.stream.send_message(ClientMessage::ConsultJackpot)?;
+
+        let response = self.recv_response();
+
+        match response {
+            Ok(ServerMessage::JackpotState(n)) => Ok(n),
+            Ok(ServerMessage::NotInMachine) => Err(not_in_machine()),
+            Ok(_) => Err("Unexpected server response".into()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Cuántos premios de cada tipo tiene el jugador.
+    pub fn consult_inventory(&mut self) -> Result<Vec<(Item, u32)>, Box<dyn Error>> {
+        self.stream.send_message(ClientMessage::ConsultInventory)?;
+
+        let response = self.recv_response();
+
+        match response {
+            Ok(ServerMessage::Inventory(items)) => Ok(items),
+            Ok(_) => Err("Unexpected server response".into()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn consult_balance(&mut self) -> Result<u32, Box<dyn Error>> {
+        self.stream.send_message(ClientMessage::ConsultBalance)?;
+
+        let response = self.recv_response();
+
+        match response {
+            Ok(ServerMessage::Balance(n)) => Ok(n),
+            Ok(_) => Err("Unexpected server response".into()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn list_machines(&mut self) -> Result<Vec<MachineInfo>, Box<dyn Error>> {
+        self.stream.send_message(ClientMessage::ListMachines)?;
+
+        let response = self.recv_response();
+
+        match response {
+            Ok(ServerMessage::MachineList(machines)) => Ok(machines),
+            Ok(_) => Err("Unexpected server response".into()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Si la máquina está llena devuelve la posición en la fila de espera.
+    pub fn join_machine(&mut self, id: u32) -> Result<Option<u32>, Box<dyn Error>> {
+        self.stream.send_message(ClientMessage::JoinMachine(id))?;
+
+        let response = self.recv_response();
+
+        match response {
+            Ok(ServerMessage::Joined(_)) => Ok(None),
+            Ok(ServerMessage::MachineFull { position, .. }) => Ok(Some(position)),
+            Ok(ServerMessage::UnknownMachine(id)) => {
+                Err(format!("There is no machine {id}").into())
+            }
+            Ok(_) => Err("Unexpected server response".into()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Se levanta de la máquina actual. Devuelve su id.
+    pub fn leave_machine(&mut self) -> Result<u32, Box<dyn Error>> {
+        self.stream.send_message(ClientMessage::LeaveMachine)?;
+
+        let response = self.recv_response();
+
+        match response {
+            Ok(ServerMessage::Left(id)) => Ok(id),
+            Ok(ServerMessage::NotInMachine) => Err(not_in_machine()),
+            Ok(_) => Err("Unexpected server response".into()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Empieza a jugar de manera verificable con `client_seed`.
+    /// El hash de la semilla del servidor se pide antes de mandar la del
+    /// cliente, así el servidor no puede elegir la suya conociéndola.
+    /// Devuelve ese hash. Las jugadas con la semilla anterior se tienen que
+    /// verificar antes de cambiarla.
+    pub fn play_fair(&mut self, client_seed: String) -> Result<String, Box<dyn Error>> {
+        if self.fair_log.as_ref().is_some_and(|log| !log.is_empty()) {
+            return Err(unverified_plays());
+        }
+        let commitment = self.consult_commitment()?;
+
+        self.stream
+            .send_message(ClientMessage::SetClientSeed(client_seed.clone()))?;
+        let (nonce, payout) = match self.recv_response() {
+            Ok(ServerMessage::Commitment {
+                server_seed_hash,
+                nonce,
+                payout,
+                ..
+            }) if server_seed_hash == commitment => (nonce, payout),
+            Ok(ServerMessage::Commitment { .. }) => {
+                return Err("The server changed its seed after seeing yours".into())
+            }
+            Ok(ServerMessage::InvalidClientSeed(max)) => {
+                return Err(format!("The seed can have up to {max} bytes").into())
+            }
+            Ok(ServerMessage::RevealRequired(_)) => return Err(unverified_plays()),
+            Ok(_) => return Err("Unexpected server response".into()),
+            Err(e) => return Err(e),
+        };
+
+        self.fair_log = Some(FairLog::new(commitment.clone(), client_seed, nonce, payout));
+        Ok(commitment)
+    }
+
+    fn consult_commitment(&mut self) -> Result<String, Box<dyn Error>> {
+        self.stream.send_message(ClientMessage::ConsultCommitment)?;
+
+        let response = self.recv_response();
+
+        match response {
+            Ok(ServerMessage::Commitment {
+                server_seed_hash, ..
+            }) => Ok(server_seed_hash),
+            Ok(_) => Err("Unexpected server response".into()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Si hay jugadas verificables sin revisar, pide la semilla del servidor
+    /// y recalcula cada una.
+    pub fn verify(&mut self) -> Result<Verification, Box<dyn Error>> {
+        if self.fair_log.as_ref().is_none_or(FairLog::is_empty) {
+            return Ok(Verification {
+                fair: 0,
+                unverifiable: 0,
+            });
+        }
+
+        self.stream.send_message(ClientMessage::RevealSeed)?;
+
+        let response = self.recv_response();
+
+        let (result, next_hash) = match (response, self.fair_log.as_ref()) {
+            (
+                Ok(ServerMessage::SeedRevealed {
+                    server_seed,
+                    client_seed,
+                    plays,
+                    next_hash,
+                }),
+                Some(log),
+            ) => (log.verify(&server_seed, &client_seed, &plays), next_hash),
+            (Ok(_), _) => return Err("Unexpected server response".into()),
+            (Err(e), _) => return Err(e),
+        };
+
+        if let Some(log) = self.fair_log.as_mut() {
+            log.restart(next_hash);
+        }
+        Ok(result?)
+    }
+
+    fn record(&mut self, outcomes: &[u32]) {
+        if let Some(log) = self.fair_log.as_mut() {
+            log.record(outcomes);
         }
     }
 
@@ -47,4 +339,62 @@ impl CommandResolver {
 
         let _ = self.stream.send_message(ClientMessage::Quit);
     }
+
+    /// Cualquier pedido puede rechazarse porque el servidor está ocupado.
+    fn recv_response(&mut self) -> Result<ServerMessage, Box<dyn Error>> {
+        match self.responses.recv() {
+            Ok(ServerMessage::ServerBusy) => Err("The server is busy, try again later".into()),
+            Ok(response) => Ok(response),
+            Err(_) => Err("Lost connection with the server".into()),
+        }
+    }
+}
+
+fn insufficient_funds(balance: u32) -> Box<dyn Error> {
+    format!("Not enough coins in your wallet (you have {balance})").into()
+}
+
+fn not_in_machine() -> Box<dyn Error> {
+    "You are not playing at any machine".into()
+}
+
+fn reveal_required(max: u32) -> Box<dyn Error> {
+    format!("Only {max} coins can be played before verifying them").into()
+}
+
+fn unverified_plays() -> Box<dyn Error> {
+    "Verify your coins before changing your seed".into()
+}
+
+fn payout_changed() -> Box<dyn Error> {
+    "This machine does not pay as your seed committed to: verify your coins and play fair again"
+        .into()
+}
+
+fn not_a_board(id: u32) -> Box<dyn Error> {
+    format!("Machine {id} has no board").into()
+}
+
+fn listen<F>(mut reader: StreamToServer, responses: Sender<ServerMessage>, on_broadcast: F)
+where
+    F: Fn(ServerMessage),
+{
+    loop {
+        match reader.recv_message() {
+            Ok(
+                msg @ (ServerMessage::Notification(_)
+                | ServerMessage::Jackpot { .. }
+                | ServerMessage::ItemsWon { .. }
+                | ServerMessage::ShuttingDown(_)),
+            ) => on_broadcast(msg),
+            Ok(response) => {
+                if responses.send(response).is_err() {
+                    return;
+                }
+            }
+            // Mensajes de versiones más nuevas del servidor: se ignoran
+            Err(e) if e.kind() == ProtocolErrorKind::UnknownMessage => continue,
+            Err(_) => return,
+        }
+    }
 }
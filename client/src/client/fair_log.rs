@@ -0,0 +1,265 @@
+use common::{
+    fairness,
+    payout::{PayoutConfig, PayoutModel},
+    protocol::FairPlay,
+};
+
+/// Lo que el cliente vio mientras jugaba de manera verificable,
+/// para compararlo con lo que informa el servidor al revelar su semilla.
+pub struct FairLog {
+    commitment: String,
+    client_seed: String,
+    first_nonce: u64,
+    // Modelo de pago que publicó el servidor junto con el hash
+    payout: Option<PayoutConfig>,
+    seen: Vec<u32>,
+}
+
+/// Resultado de revisar las monedas de una semilla.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Verification {
+    /// Monedas cuyo premio se recalculó y coincide.
+    pub fair: usize,
+    /// Monedas que cayeron en un estante, o que se jugaron sin que el
+    /// servidor publicara el modelo de pago: no hay premio que recalcular.
+    pub unverifiable: usize,
+}
+
+impl FairLog {
+    /// `commitment` tiene que ser el hash publicado antes de elegir
+    /// `client_seed`, y `payout` el modelo de pago publicado con él.
+    pub fn new(
+        commitment: String,
+        client_seed: String,
+        first_nonce: u64,
+        payout: Option<PayoutConfig>,
+    ) -> FairLog {
+        FairLog {
+            commitment,
+            client_seed,
+            first_nonce,
+            payout,
+            seen: Vec::new(),
+        }
+    }
+
+    /// Anota las monedas que cayeron con cada moneda arrojada.
+    pub fn record(&mut self, outcomes: &[u32]) {
+        self.seen.extend_from_slice(outcomes);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// Recalcula el azar de cada moneda con la semilla revelada y, con el
+    /// modelo de pago comprometido, la probabilidad y el premio. Del
+    /// servidor solo se toma el pool, que depende de los demás jugadores.
+    pub fn verify(
+        &self,
+        server_seed: &str,
+        client_seed: &str,
+        plays: &[FairPlay],
+    ) -> Result<Verification, String> {
+        let server_seed = fairness::from_hex(server_seed)
+            .ok_or_else(|| "The revealed seed is not hexadecimal".to_string())?;
+        if fairness::commitment(&server_seed) != self.commitment {
+            return Err("The revealed seed does not match the published hash".to_string());
+        }
+        if client_seed != self.client_seed {
+            return Err("The server used another client seed".to_string());
+        }
+        if plays.len() != self.seen.len() {
+            return Err(format!(
+                "The server reports {} coins but {} were played",
+                plays.len(),
+                self.seen.len()
+            ));
+        }
+
+        let model = self.payout.as_ref().map(PayoutConfig::build);
+        let mut verification = Verification {
+            fair: 0,
+            unverifiable: 0,
+        };
+        for (i, (play, seen)) in plays.iter().zip(&self.seen).enumerate() {
+            let coin = i + 1;
+            if play.nonce != self.first_nonce + i as u64 {
+                return Err(format!("Coin {coin} has an unexpected nonce"));
+            }
+            if play.fell != *seen {
+                return Err(format!(
+                    "Coin {coin} won {seen} coins but the server reports {}",
+                    play.fell
+                ));
+            }
+            if play.fell > play.pool {
+                return Err(format!("Coin {coin} won more coins than the machine had"));
+            }
+            // Las monedas de un estante no tienen probabilidad
+            let (Some(model), Some(probability)) = (&model, play.probability) else {
+                verification.unverifiable += 1;
+                continue;
+            };
+            if probability != model.probability(play.pool) {
+                return Err(format!(
+                    "Coin {coin} was played with another probability than the committed payout"
+                ));
+            }
+            let coin_seed = fairness::coin_seed(&server_seed, client_seed, play.nonce);
+            let expected = prize(model.as_ref(), play.pool, fairness::roll(&coin_seed));
+            if play.fell != expected {
+                return Err(format!(
+                    "Coin {coin} should have won {expected} coins but won {}",
+                    play.fell
+                ));
+            }
+            verification.fair += 1;
+        }
+        Ok(verification)
+    }
+
+    /// Sigue con la semilla nueva que publicó el servidor al revelar la
+    /// anterior, con el mismo modelo de pago.
+    pub fn restart(&mut self, commitment: String) {
+        self.commitment = commitment;
+        self.first_nonce = 0;
+        self.seen.clear();
+    }
+}
+
+/// Monedas que tenían que caer con `roll` y `pool` monedas en la máquina,
+/// como las calcula el servidor: nunca más de las que hay.
+fn prize(model: &dyn PayoutModel, pool: u32, roll: f64) -> u32 {
+    if roll < model.probability(pool) {
+        model.payout(pool, roll).min(pool)
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod fair_log_tests {
+    use super::*;
+    use common::payout::FixedRtp;
+
+    const SERVER_SEED: [u8; 32] = [9; 32];
+
+    fn payout() -> PayoutConfig {
+        PayoutConfig::FixedRtp(FixedRtp::new(0.9, 0.5).unwrap())
+    }
+
+    fn plays(n: u64) -> Vec<FairPlay> {
+        let model = payout().build();
+        (0..n)
+            .map(|nonce| {
+                let coin_seed = fairness::coin_seed(&SERVER_SEED, "lucky", nonce);
+                FairPlay {
+                    nonce,
+                    pool: 100,
+                    probability: Some(0.5),
+                    fell: prize(model.as_ref(), 100, fairness::roll(&coin_seed)),
+                }
+            })
+            .collect()
+    }
+
+    fn log(plays: &[FairPlay], payout: Option<PayoutConfig>) -> FairLog {
+        let mut log = FairLog::new(
+            fairness::commitment(&SERVER_SEED),
+            "lucky".to_string(),
+            0,
+            payout,
+        );
+        let seen: Vec<u32> = plays.iter().map(|play| play.fell).collect();
+        log.record(&seen);
+        log
+    }
+
+    #[test]
+    fn honest_server() {
+        let plays = plays(40);
+
+        let verified =
+            log(&plays, Some(payout())).verify(&fairness::to_hex(&SERVER_SEED), "lucky", &plays);
+
+        assert_eq!(
+            verified,
+            Ok(Verification {
+                fair: 40,
+                unverifiable: 0
+            })
+        );
+    }
+
+    #[test]
+    fn seed_does_not_match_commitment() {
+        let plays = plays(5);
+
+        let verified =
+            log(&plays, Some(payout())).verify(&fairness::to_hex(&[1; 32]), "lucky", &plays);
+
+        assert!(verified.is_err());
+    }
+
+    #[test]
+    fn outcome_does_not_match_roll() {
+        let mut plays = plays(40);
+        let log = log(&plays, Some(payout()));
+        // El servidor dice que usó otra probabilidad para negar un premio
+        let won = plays.iter().position(|play| play.fell > 0).unwrap();
+        plays[won].probability = Some(0.0);
+
+        let verified = log.verify(&fairness::to_hex(&SERVER_SEED), "lucky", &plays);
+
+        assert!(verified.is_err());
+    }
+
+    #[test]
+    fn prize_does_not_match_payout() {
+        let mut plays = plays(40);
+        // El servidor paga una moneda menos y la cuenta como lo que cayó
+        let won = plays.iter().position(|play| play.fell > 0).unwrap();
+        plays[won].fell -= 1;
+        let log = log(&plays, Some(payout()));
+
+        let verified = log.verify(&fairness::to_hex(&SERVER_SEED), "lucky", &plays);
+
+        assert!(verified.is_err());
+    }
+
+    #[test]
+    fn board_and_uncommitted_plays_are_not_verifiable() {
+        let mut plays = plays(10);
+        plays[2].probability = None;
+        plays[2].fell = 7;
+        let seed = fairness::to_hex(&SERVER_SEED);
+
+        assert_eq!(
+            log(&plays, Some(payout())).verify(&seed, "lucky", &plays),
+            Ok(Verification {
+                fair: 9,
+                unverifiable: 1
+            })
+        );
+        assert_eq!(
+            log(&plays, None).verify(&seed, "lucky", &plays),
+            Ok(Verification {
+                fair: 0,
+                unverifiable: 10
+            })
+        );
+    }
+
+    #[test]
+    fn missing_or_altered_plays() {
+        let plays = plays(10);
+        let log = log(&plays, Some(payout()));
+        let seed = fairness::to_hex(&SERVER_SEED);
+
+        assert!(log.verify(&seed, "lucky", &plays[..9]).is_err());
+        let mut altered = plays.clone();
+        altered[3].fell += 1;
+        assert!(log.verify(&seed, "lucky", &altered).is_err());
+    }
+}
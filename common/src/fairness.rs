@@ -0,0 +1,88 @@
+use sha2::{Digest, Sha256};
+
+/// Largo en bytes de la semilla del servidor.
+pub const SEED_LEN: usize = 32;
+
+/// Hash de la semilla del servidor, que se publica antes de jugar.
+pub fn commitment(server_seed: &[u8]) -> String {
+    to_hex(&Sha256::digest(server_seed))
+}
+
+/// Azar de la moneda número `nonce`: SHA-256 de la semilla del servidor,
+/// la del cliente y el nonce. Como la semilla del servidor tiene largo fijo
+/// y el nonce va al final, dos combinaciones distintas no se confunden.
+pub fn coin_seed(server_seed: &[u8], client_seed: &str, nonce: u64) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(server_seed);
+    hasher.update(client_seed.as_bytes());
+    hasher.update(nonce.to_be_bytes());
+    hasher.finalize().into()
+}
+
+/// Número uniforme en [0, 1) sacado de los primeros 8 bytes de `coin_seed`.
+pub fn roll(coin_seed: &[u8; 32]) -> f64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&coin_seed[..8]);
+    // 53 bits: todos los valores se representan exactos en un f64
+    (u64::from_be_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod fairness_tests {
+    use super::*;
+
+    #[test]
+    fn commitment_is_sha256() {
+        assert_eq!(
+            commitment(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let bytes = [0u8, 1, 127, 128, 255];
+
+        assert_eq!(to_hex(&bytes), "00017f80ff");
+        assert_eq!(from_hex("00017f80ff").unwrap(), bytes);
+        assert_eq!(from_hex("0g"), None);
+        assert_eq!(from_hex("abc"), None);
+    }
+
+    #[test]
+    fn every_input_changes_the_coin() {
+        let seed = [7u8; SEED_LEN];
+        let coin = coin_seed(&seed, "client", 0);
+
+        assert_eq!(coin, coin_seed(&seed, "client", 0));
+        assert_ne!(coin, coin_seed(&seed, "client", 1));
+        assert_ne!(coin, coin_seed(&seed, "other", 0));
+        assert_ne!(coin, coin_seed(&[8u8; SEED_LEN], "client", 0));
+    }
+
+    #[test]
+    fn roll_is_in_unit_interval() {
+        assert_eq!(roll(&[0u8; 32]), 0.0);
+        assert!(roll(&[255u8; 32]) < 1.0);
+        let seed = [3u8; SEED_LEN];
+        let mean = (0..1000)
+            .map(|nonce| roll(&coin_seed(&seed, "", nonce)))
+            .sum::<f64>()
+            / 1000.0;
+        assert!((mean - 0.5).abs() < 0.05);
+    }
+}
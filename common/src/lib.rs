@@ -1,3 +1,5 @@
+pub mod fairness;
+pub mod payout;
 pub mod protocol;
 pub mod thread_pool;
 pub mod thread_pool_error;
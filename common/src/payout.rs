@@ -0,0 +1,328 @@
+pub const DEFAULT_STEEPNESS: f64 = 0.02;
+pub const DEFAULT_THRESHOLD: f64 = 700.0;
+pub const DEFAULT_PAYOUT_FRACTION: f64 = 0.05;
+
+/// Decide cuándo y cuánto paga una máquina según las monedas que tiene.
+pub trait PayoutModel: Send {
+    /// Probabilidad de que caigan monedas con `pool` monedas en la máquina.
+    fn probability(&self, pool: u32) -> f64;
+
+    /// Monedas que caen. `rng` es uniforme en [0, `probability(pool)`).
+    fn payout(&self, pool: u32, rng: f64) -> u32;
+}
+
+/// Curva logística: casi nunca paga con pocas monedas
+/// y casi siempre cuando el pool pasa `threshold`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Logistic {
+    pub(crate) steepness: f64,
+    pub(crate) threshold: f64,
+    pub(crate) payout_fraction: f64,
+}
+
+impl Logistic {
+    pub fn new(
+        steepness: f64,
+        threshold: f64,
+        payout_fraction: f64,
+    ) -> Result<Logistic, &'static str> {
+        if !steepness.is_finite() || steepness <= 0.0 {
+            return Err("steepness must be positive");
+        }
+        if !threshold.is_finite() {
+            return Err("threshold must be a number");
+        }
+        check_fraction(payout_fraction)?;
+
+        Ok(Logistic {
+            steepness,
+            threshold,
+            payout_fraction,
+        })
+    }
+}
+
+impl Default for Logistic {
+    fn default() -> Self {
+        Logistic {
+            steepness: DEFAULT_STEEPNESS,
+            threshold: DEFAULT_THRESHOLD,
+            payout_fraction: DEFAULT_PAYOUT_FRACTION,
+        }
+    }
+}
+
+impl PayoutModel for Logistic {
+    // ver función Heaviside o escalón
+    fn probability(&self, pool: u32) -> f64 {
+        let n = f64::from(pool);
+
+        1.0_f64 / (1.0_f64 + (-self.steepness * (n - self.threshold)).exp())
+    }
+
+    fn payout(&self, pool: u32, rng: f64) -> u32 {
+        fraction_of_pool(self.payout_fraction, pool, rng)
+    }
+}
+
+/// Escalón: nunca paga por debajo de `threshold` y
+/// por encima paga con probabilidad `probability`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub(crate) threshold: u32,
+    pub(crate) probability: f64,
+    pub(crate) payout_fraction: f64,
+}
+
+impl Step {
+    pub fn new(
+        threshold: u32,
+        probability: f64,
+        payout_fraction: f64,
+    ) -> Result<Step, &'static str> {
+        check_probability(probability)?;
+        check_fraction(payout_fraction)?;
+
+        Ok(Step {
+            threshold,
+            probability,
+            payout_fraction,
+        })
+    }
+}
+
+impl PayoutModel for Step {
+    fn probability(&self, pool: u32) -> f64 {
+        if pool >= self.threshold {
+            self.probability
+        } else {
+            0.0
+        }
+    }
+
+    fn payout(&self, pool: u32, rng: f64) -> u32 {
+        fraction_of_pool(self.payout_fraction, pool, rng)
+    }
+}
+
+/// Probabilidad interpolada linealmente entre puntos (pool, probabilidad).
+/// Antes del primer punto y después del último se mantiene constante.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PiecewiseLinear {
+    pub(crate) points: Vec<(u32, f64)>,
+    pub(crate) payout_fraction: f64,
+}
+
+impl PiecewiseLinear {
+    /// Los puntos tienen que estar ordenados por pool, sin repetir.
+    pub fn new(
+        points: Vec<(u32, f64)>,
+        payout_fraction: f64,
+    ) -> Result<PiecewiseLinear, &'static str> {
+        if points.is_empty() {
+            return Err("at least one point is required");
+        }
+        if points.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err("points must be sorted by pool");
+        }
+        for (_, probability) in &points {
+            check_probability(*probability)?;
+        }
+        check_fraction(payout_fraction)?;
+
+        Ok(PiecewiseLinear {
+            points,
+            payout_fraction,
+        })
+    }
+}
+
+impl PayoutModel for PiecewiseLinear {
+    fn probability(&self, pool: u32) -> f64 {
+        let next = self.points.partition_point(|(x, _)| *x <= pool);
+        if next == 0 {
+            return self.points[0].1;
+        }
+        let (x0, y0) = self.points[next - 1];
+        match self.points.get(next) {
+            Some(&(x1, y1)) => {
+                let t = f64::from(pool - x0) / f64::from(x1 - x0);
+                y0 + t * (y1 - y0)
+            }
+            None => y0,
+        }
+    }
+
+    fn payout(&self, pool: u32, rng: f64) -> u32 {
+        fraction_of_pool(self.payout_fraction, pool, rng)
+    }
+}
+
+/// Paga con probabilidad fija un premio cuyo valor esperado es `rtp`
+/// monedas por moneda arrojada, sin importar el pool.
+/// La máquina nunca entrega más monedas de las que tiene, así que con
+/// pools chicos el retorno real puede quedar por debajo.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixedRtp {
+    pub(crate) rtp: f64,
+    pub(crate) probability: f64,
+}
+
+impl FixedRtp {
+    pub fn new(rtp: f64, probability: f64) -> Result<FixedRtp, &'static str> {
+        if !rtp.is_finite() || rtp <= 0.0 {
+            return Err("rtp must be positive");
+        }
+        if !(probability > 0.0 && probability <= 1.0) {
+            return Err("probability must be in (0, 1]");
+        }
+
+        Ok(FixedRtp { rtp, probability })
+    }
+}
+
+impl PayoutModel for FixedRtp {
+    fn probability(&self, _pool: u32) -> f64 {
+        self.probability
+    }
+
+    // Con rng uniforme en [0, p), floor(rtp / p + rng / p) vale en promedio rtp / p
+    fn payout(&self, _pool: u32, rng: f64) -> u32 {
+        ((self.rtp + rng) / self.probability).floor() as u32
+    }
+}
+
+/// Modelo de pago elegido en la configuración.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PayoutConfig {
+    Logistic(Logistic),
+    Step(Step),
+    PiecewiseLinear(PiecewiseLinear),
+    FixedRtp(FixedRtp),
+}
+
+impl PayoutConfig {
+    pub fn build(&self) -> Box<dyn PayoutModel> {
+        match self {
+            PayoutConfig::Logistic(model) => Box::new(model.clone()),
+            PayoutConfig::Step(model) => Box::new(model.clone()),
+            PayoutConfig::PiecewiseLinear(model) => Box::new(model.clone()),
+            PayoutConfig::FixedRtp(model) => Box::new(model.clone()),
+        }
+    }
+
+    /// Pool a partir del cual el modelo empieza a pagar, si tiene uno.
+    pub fn threshold(&self) -> Option<f64> {
+        match self {
+            PayoutConfig::Logistic(model) => Some(model.threshold),
+            PayoutConfig::Step(model) => Some(f64::from(model.threshold)),
+            PayoutConfig::PiecewiseLinear(_) | PayoutConfig::FixedRtp(_) => None,
+        }
+    }
+}
+
+impl Default for PayoutConfig {
+    fn default() -> Self {
+        PayoutConfig::Logistic(Logistic::default())
+    }
+}
+
+// si bien es una función lineal,
+// rng tiene como tope la probabilidad lo cual genera esa dependencia
+fn fraction_of_pool(fraction: f64, pool: u32, rng: f64) -> u32 {
+    let r = (fraction * rng * f64::from(pool)).ceil() as u32;
+
+    r.max(1)
+}
+
+fn check_probability(probability: f64) -> Result<(), &'static str> {
+    if (0.0..=1.0).contains(&probability) {
+        Ok(())
+    } else {
+        Err("probability must be between 0 and 1")
+    }
+}
+
+fn check_fraction(fraction: f64) -> Result<(), &'static str> {
+    if fraction > 0.0 && fraction <= 1.0 {
+        Ok(())
+    } else {
+        Err("payout_fraction must be in (0, 1]")
+    }
+}
+
+#[cfg(test)]
+mod payout_tests {
+    use super::*;
+
+    #[test]
+    fn logistic_prob_with_one_coin() {
+        let r = Logistic::default().probability(1);
+
+        // Si solo hay una moneda, la prob de que caigan
+        // debe ser menor a 1 en 1 millon
+        assert!(r < 0.000001_f64);
+    }
+
+    #[test]
+    fn logistic_prob_with_max_coins() {
+        let r = Logistic::default().probability(1000);
+
+        // Si las monedas estan al máximo, la prob de que caigan
+        // debe ser mayor a 99%
+        assert!(r > 0.99_f64);
+    }
+
+    #[test]
+    fn logistic_pays_at_least_one_coin() {
+        assert_eq!(Logistic::default().payout(10, 0.0), 1);
+        assert_eq!(Logistic::default().payout(1000, 1.0), 50);
+    }
+
+    #[test]
+    fn invalid_logistic() {
+        assert!(Logistic::new(0.0, 700.0, 0.05).is_err());
+        assert!(Logistic::new(0.02, 700.0, 1.5).is_err());
+    }
+
+    #[test]
+    fn step() {
+        let model = Step::new(500, 0.3, 0.1).unwrap();
+
+        assert_eq!(model.probability(499), 0.0);
+        assert_eq!(model.probability(500), 0.3);
+        assert_eq!(model.probability(1000), 0.3);
+    }
+
+    #[test]
+    fn piecewise_linear_interpolates() {
+        let model = PiecewiseLinear::new(vec![(100, 0.0), (300, 0.5), (500, 0.9)], 0.05).unwrap();
+
+        assert_eq!(model.probability(0), 0.0);
+        assert_eq!(model.probability(200), 0.25);
+        assert_eq!(model.probability(300), 0.5);
+        assert!((model.probability(400) - 0.7).abs() < 1e-9);
+        assert_eq!(model.probability(900), 0.9);
+    }
+
+    #[test]
+    fn piecewise_linear_needs_sorted_points() {
+        assert!(PiecewiseLinear::new(vec![(300, 0.5), (100, 0.0)], 0.05).is_err());
+        assert!(PiecewiseLinear::new(vec![(100, 0.5), (100, 0.6)], 0.05).is_err());
+        assert!(PiecewiseLinear::new(Vec::new(), 0.05).is_err());
+    }
+
+    #[test]
+    fn fixed_rtp_expected_payout() {
+        let model = FixedRtp::new(0.9, 0.2).unwrap();
+        let steps = 10_000;
+
+        // rng recorre [0, p) de manera uniforme
+        let total: u32 = (0..steps)
+            .map(|i| model.payout(0, model.probability(0) * f64::from(i) / f64::from(steps)))
+            .sum();
+        let expected = model.probability(0) * f64::from(total) / f64::from(steps);
+
+        assert!((expected - 0.9).abs() < 0.01);
+    }
+}
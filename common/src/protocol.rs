@@ -4,37 +4,335 @@ use std::net::TcpStream;
 use std::num::ParseIntError;
 use std::str;
 
-const INSERT_BYTE: char = 't';
-const CONSULT_BYTE: char = 'y';
-const QUIT_BYTE: char = 'q';
+use serde::{Deserialize, Serialize};
 
-const FELL_BYTE: char = 'f';
-const POOL_BYTE: char = 'p';
+use crate::payout::{FixedRtp, Logistic, PayoutConfig, PiecewiseLinear, Step};
+
+// Frame layout:
+// | version (1 byte) | type (1 byte) | payload length (4 bytes, BE) | payload |
+pub const PROTOCOL_VERSION: u8 = 3;
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+
+const HEADER_LEN: usize = 6;
+const MAX_PAYLOAD_LEN: u32 = 64 * 1024;
+/// Cuánto guarda un `ClientDecoder` sin procesar: unos pocos mensajes del
+/// tamaño máximo. Lo que mande el cliente de más espera en el socket.
+pub const MAX_BUFFERED_LEN: usize = 4 * (HEADER_LEN + MAX_PAYLOAD_LEN as usize);
+
+const HANDSHAKE_BYTE: u8 = b'v';
+
+const HELLO_BYTE: u8 = b'h';
+const INSERT_BYTE: u8 = b't';
+const INSERT_MANY_BYTE: u8 = b'm';
+const CONSULT_BYTE: u8 = b'y';
+const CONSULT_BALANCE_BYTE: u8 = b'w';
+const LIST_MACHINES_BYTE: u8 = b'a';
+const JOIN_MACHINE_BYTE: u8 = b'j';
+const LEAVE_MACHINE_BYTE: u8 = b'x';
+const INSERT_AT_BYTE: u8 = b'd';
+const CONSULT_BOARD_BYTE: u8 = b'o';
+const SET_CLIENT_SEED_BYTE: u8 = b'c';
+const CONSULT_COMMITMENT_BYTE: u8 = b'k';
+const REVEAL_SEED_BYTE: u8 = b'e';
+const CONSULT_JACKPOT_BYTE: u8 = b'g';
+const CONSULT_INVENTORY_BYTE: u8 = b'u';
+const QUIT_BYTE: u8 = b'q';
+
+const FELL_BYTE: u8 = b'f';
+const POOL_BYTE: u8 = b'p';
+const BATCH_BYTE: u8 = b'b';
+const BATCH_TOO_LARGE_BYTE: u8 = b'B';
+const BALANCE_BYTE: u8 = b'w';
+const INSUFFICIENT_FUNDS_BYTE: u8 = b'i';
+const WELCOME_BYTE: u8 = b's';
+const LOGIN_REJECTED_BYTE: u8 = b'r';
+const LOGIN_REQUIRED_BYTE: u8 = b'l';
+const NOTIFICATION_BYTE: u8 = b'n';
+const MACHINE_LIST_BYTE: u8 = b'M';
+const JOINED_BYTE: u8 = b'J';
+const LEFT_BYTE: u8 = b'X';
+const UNKNOWN_MACHINE_BYTE: u8 = b'U';
+const NOT_IN_MACHINE_BYTE: u8 = b'N';
+const MACHINE_FULL_BYTE: u8 = b'F';
+const DROPPED_BYTE: u8 = b'D';
+const BOARD_STATE_BYTE: u8 = b'O';
+const NOT_A_BOARD_BYTE: u8 = b'G';
+const INVALID_LANE_BYTE: u8 = b'L';
+const COMMITMENT_BYTE: u8 = b'k';
+const SEED_REVEALED_BYTE: u8 = b'e';
+const INVALID_CLIENT_SEED_BYTE: u8 = b'I';
+const REVEAL_REQUIRED_BYTE: u8 = b'R';
+const JACKPOT_BYTE: u8 = b'W';
+const JACKPOT_STATE_BYTE: u8 = b'g';
+const ITEMS_WON_BYTE: u8 = b'T';
+const INVENTORY_BYTE: u8 = b'V';
+const POOL_FULL_BYTE: u8 = b'P';
+const SERVER_BUSY_BYTE: u8 = b'S';
+const SHUTTING_DOWN_BYTE: u8 = b'Z';
+const PAYOUT_CHANGED_BYTE: u8 = b'K';
+
+const MULTIPLIER_BYTE: u8 = b'm';
+const FREE_DROP_BYTE: u8 = b'f';
+const BADGE_BYTE: u8 = b'b';
+
+const LOGISTIC_BYTE: u8 = b'l';
+const STEP_BYTE: u8 = b's';
+const PIECEWISE_LINEAR_BYTE: u8 = b'p';
+const FIXED_RTP_BYTE: u8 = b'r';
+
+// 'c' era el PoolChanged sin máquina; los clientes que lo conocían
+// ignoran las notificaciones desconocidas.
+const POOL_CHANGED_BYTE: u8 = b'C';
+const COINS_FELL_BYTE: u8 = b'f';
+const PLAYER_JOINED_BYTE: u8 = b'j';
+const PLAYER_LEFT_BYTE: u8 = b'l';
+const SEATED_BYTE: u8 = b's';
+
+const HANDSHAKE_ACCEPTED: u8 = 0;
+const HANDSHAKE_REJECTED: u8 = 1;
+
+const COUNT_LEN: usize = 5;
+const LEGACY_COUNT_MAX: u64 = 99999;
+
+const VARINT_COUNTS_VERSION: u8 = 2;
+const NOTIFICATIONS_VERSION: u8 = 2;
+const COMMITTED_PAYOUT_VERSION: u8 = 3;
+const MAX_VARINT_LEN: usize = 10;
 
 #[derive(Debug)]
 pub enum ClientMessage {
+    Hello {
+        name: String,
+        token: String,
+    },
     Insert,
+    InsertMany(u32),
     ConsultPool,
+    ConsultBalance,
+    ListMachines,
+    JoinMachine(u32),
+    LeaveMachine,
+    /// Arroja una moneda sobre un carril del estante.
+    InsertAt(u32),
+    ConsultBoard,
+    /// Desde ahora el azar de cada moneda sale de esta semilla y la del servidor.
+    SetClientSeed(String),
+    ConsultCommitment,
+    /// Pide la semilla del servidor para verificar las jugadas y empieza otra.
+    RevealSeed,
+    ConsultJackpot,
+    ConsultInventory,
     Quit,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ServerMessage {
     FellCoins(u32),
     PoolState(u32),
+    FellCoinsBatch {
+        outcomes: Vec<u32>,
+        total: u32,
+    },
+    BatchTooLarge(u32),
+    Balance(u32),
+    InsufficientFunds(u32),
+    Welcome(u64),
+    LoginRejected(String),
+    LoginRequired,
+    Notification(Notification),
+    MachineList(Vec<MachineInfo>),
+    Joined(u32),
+    Left(u32),
+    UnknownMachine(u32),
+    NotInMachine,
+    /// La máquina está llena; el jugador espera en la fila (desde 1).
+    MachineFull {
+        machine: u32,
+        position: u32,
+    },
+    /// Carril en el que terminó la moneda y cuántas cayeron.
+    Dropped {
+        lane: u32,
+        fell: u32,
+    },
+    BoardState(BoardInfo),
+    /// La máquina no tiene estante.
+    NotABoard(u32),
+    /// El carril no existe; el estante tiene esta cantidad de carriles.
+    InvalidLane(u32),
+    /// Hash de la semilla actual del servidor, semilla del cliente
+    /// (vacía si no se juega de manera verificable) y próximo nonce.
+    /// Desde la versión 3 incluye el modelo de pago con el que se juegan
+    /// las monedas de esta semilla; no hay si el jugador no está sentado
+    /// o la máquina tiene estante.
+    Commitment {
+        server_seed_hash: String,
+        client_seed: String,
+        nonce: u64,
+        payout: Option<PayoutConfig>,
+    },
+    /// Semilla del servidor en hexadecimal y las jugadas hechas con ella.
+    /// `next_hash` es el compromiso de la semilla que la reemplaza.
+    SeedRevealed {
+        server_seed: String,
+        client_seed: String,
+        plays: Vec<FairPlay>,
+        next_hash: String,
+    },
+    /// La semilla del cliente es demasiado larga; el máximo es este.
+    InvalidClientSeed(u32),
+    /// Hay que pedir la semilla antes de seguir jugando; entran estas jugadas por semilla.
+    RevealRequired(u32),
+    /// La máquina ya no paga con el modelo comprometido para la semilla
+    /// (el jugador cambió de máquina o se cambió la configuración). Hay que
+    /// revelar la semilla y pedir el compromiso de nuevo.
+    PayoutChanged,
+    /// Un jugador se llevó el pozo de una máquina. Se avisa a todos,
+    /// como las notificaciones.
+    Jackpot {
+        machine: u32,
+        player: String,
+        coins: u32,
+    },
+    /// Monedas enteras en el pozo de la máquina; 0 si no tiene.
+    JackpotState(u32),
+    /// Premios que cayeron del estante en la última jugada. Llega sin que
+    /// se pida, como las notificaciones, pero solo al jugador que los ganó.
+    ItemsWon {
+        machine: u32,
+        items: Vec<Item>,
+    },
+    /// Cuántos premios de cada tipo tiene el jugador.
+    Inventory(Vec<(Item, u32)>),
+    /// La máquina no acepta tantas monedas juntas; en una jugada entran estas.
+    PoolFull(u32),
+    /// El servidor no da abasto; el pedido no se atendió.
+    ServerBusy,
+    /// El servidor se apaga y corta las conexiones en estos segundos.
+    /// Se avisa a todos, como las notificaciones.
+    ShuttingDown(u32),
+}
+
+impl From<Notification> for ServerMessage {
+    fn from(notification: Notification) -> ServerMessage {
+        ServerMessage::Notification(notification)
+    }
+}
+
+/// Premios que no son monedas y que pueden caer del estante.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Item {
+    /// Multiplica las monedas de la próxima moneda que pague.
+    Multiplier,
+    /// Una moneda que se arroja sin descontarla de la billetera.
+    FreeDrop,
+    /// De colección; no tiene otro efecto.
+    Badge,
+}
+
+impl Item {
+    pub const ALL: [Item; 3] = [Item::Multiplier, Item::FreeDrop, Item::Badge];
+}
+
+/// Resumen de una máquina para elegir dónde jugar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineInfo {
+    pub id: u32,
+    pub pool: u32,
+    pub players: u32,
+}
+
+/// Una moneda arrojada de manera verificable. `pool` es el pool con el que
+/// se calculó `probability`; las máquinas con estante no tienen probabilidad.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FairPlay {
+    pub nonce: u64,
+    pub pool: u32,
+    pub probability: Option<f64>,
+    pub fell: u32,
+}
+
+/// Monedas en cada celda del estante, fila por fila desde el fondo.
+/// La última fila es la del borde.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoardInfo {
+    pub machine: u32,
+    pub width: u32,
+    pub depth: u32,
+    pub cells: Vec<u32>,
+}
+
+/// Eventos de la máquina que el servidor envía sin que el cliente los pida.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    PoolChanged {
+        machine: u32,
+        pool: u32,
+    },
+    CoinsFell {
+        player: String,
+        coins: u32,
+    },
+    PlayerJoined(String),
+    PlayerLeft(String),
+    /// Se liberó un lugar y el jugador que esperaba ya está sentado.
+    Seated(u32),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Frame {
+    version: u8,
+    msg_type: u8,
+    payload: Vec<u8>,
 }
 
 pub struct StreamToServer {
     stream: TcpStream,
+    version: u8,
 }
 
 impl StreamToServer {
     pub fn new(stream: TcpStream) -> Self {
-        StreamToServer { stream }
+        StreamToServer {
+            stream,
+            version: PROTOCOL_VERSION,
+        }
+    }
+
+    /// Ofrece la versión propia del protocolo al servidor y espera
+    /// la versión acordada. Debe llamarse antes de cualquier otro mensaje.
+    pub fn handshake(&mut self) -> Result<u8, ProtocolError> {
+        write_frame(&mut self.stream, PROTOCOL_VERSION, HANDSHAKE_BYTE, &[])?;
+
+        let frame = read_frame(&mut self.stream)?;
+        if frame.msg_type != HANDSHAKE_BYTE {
+            let msg = format!(
+                "Expected handshake reply, got message type {}",
+                frame.msg_type
+            );
+            return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Malformed));
+        }
+
+        let version = decode_handshake_reply(&frame.payload)?;
+        if !is_supported(version) {
+            return Err(version_mismatch(version));
+        }
+
+        self.version = version;
+        Ok(version)
+    }
+
+    /// Devuelve otro extremo sobre la misma conexión, con la versión ya acordada.
+    pub fn try_clone(&self) -> Result<Self, ProtocolError> {
+        Ok(StreamToServer {
+            stream: self.stream.try_clone()?,
+            version: self.version,
+        })
     }
 
     pub fn send_message(&mut self, msg: ClientMessage) -> Result<(), ProtocolError> {
-        let encoded_msg = encode_client_msg(msg);
+        let encoded_msg = encode_client_msg(msg, self.version);
 
         self.stream.write_all(&encoded_msg)?;
 
@@ -42,115 +340,1140 @@ impl StreamToServer {
     }
 
     pub fn recv_message(&mut self) -> Result<ServerMessage, ProtocolError> {
-        let mut buffer = vec![0u8; 1];
+        let frame = read_frame(&mut self.stream)?;
+        check_version(&frame, self.version)?;
 
-        self.stream.read_exact(&mut buffer)?;
+        decode_server_msg(&frame)
+    }
+}
 
-        // Should never panic
-        let msg_byte = char::from(buffer.pop().unwrap());
+/// Formato de cable que habla un cliente.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wire {
+    /// Clientes previos al framing: un byte ASCII por mensaje y
+    /// cantidades de cinco dígitos decimales.
+    Legacy,
+    Framed(u8),
+}
 
-        match msg_byte {
-            FELL_BYTE => {
-                let mut buffer = vec![0u8; 5];
+impl fmt::Display for Wire {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Wire::Legacy => write!(f, "legacy (unframed)"),
+            Wire::Framed(version) => write!(f, "{}", version),
+        }
+    }
+}
 
-                self.stream.read_exact(&mut buffer)?;
+impl Wire {
+    /// Si el compromiso de las semillas incluye el modelo de pago.
+    pub fn commits_payout(&self) -> bool {
+        matches!(self, Wire::Framed(version) if *version >= COMMITTED_PAYOUT_VERSION)
+    }
 
-                let n = decode_count(&buffer)?;
+    /// Codifica un mensaje del servidor en este formato.
+    pub fn encode(&self, msg: ServerMessage) -> Result<Vec<u8>, ProtocolError> {
+        match self {
+            Wire::Legacy => encode_legacy_server_msg(msg),
+            Wire::Framed(version) => encode_server_msg(msg, *version),
+        }
+    }
+}
 
-                Ok(ServerMessage::FellCoins(n))
-            }
-            POOL_BYTE => {
-                let mut buffer = vec![0u8; 5];
+/// Algo completo que mandó el cliente.
+#[derive(Debug)]
+pub enum Incoming {
+    /// Oferta de versión. Hay que responderle `reply` al cliente (nada si
+    /// no usa framing); si no hay una versión en común, `wire` es el error
+    /// y después de responder se corta la conexión.
+    Handshake {
+        wire: Result<Wire, ProtocolError>,
+        reply: Vec<u8>,
+    },
+    Message(ClientMessage),
+}
 
-                self.stream.read_exact(&mut buffer)?;
+/// Lado del servidor de una conexión, sin hacer E/S: junta los bytes que
+/// llegan del cliente y devuelve lo que se completó. Así una conexión no
+/// bloqueante puede leer lo que haya y seguir con otra.
+#[derive(Debug, Default)]
+pub struct ClientDecoder {
+    wire: Option<Wire>,
+    buffer: Vec<u8>,
+    // Bytes del principio de `buffer` que ya se decodificaron. Se descartan
+    // de a muchos, así un buffer lleno de mensajes de un byte no se mueve
+    // entero por cada uno
+    read: usize,
+}
 
-                let n = decode_count(&buffer)?;
+impl ClientDecoder {
+    pub fn new() -> Self {
+        ClientDecoder::default()
+    }
 
-                Ok(ServerMessage::PoolState(n))
+    /// Formato acordado en el handshake, si ya se hizo.
+    pub fn wire(&self) -> Option<Wire> {
+        self.wire
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        if self.read * 2 >= self.buffer.len() {
+            self.buffer.drain(..self.read);
+            self.read = 0;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Cuántos bytes más se pueden agregar sin pasar `MAX_BUFFERED_LEN`.
+    pub fn room(&self) -> usize {
+        MAX_BUFFERED_LEN.saturating_sub(self.unread().len())
+    }
+
+    fn unread(&self) -> &[u8] {
+        &self.buffer[self.read..]
+    }
+
+    /// Lo primero que mande el cliente tiene que ser la oferta de versión.
+    /// Los clientes sin framing no hacen handshake: su primer mensaje
+    /// queda en el buffer y sale en la llamada siguiente.
+    pub fn next_incoming(&mut self) -> Result<Option<Incoming>, ProtocolError> {
+        let Some(&first) = self.unread().first() else {
+            return Ok(None);
+        };
+
+        match self.wire {
+            None if decode_legacy_client_msg(first).is_some() => {
+                self.wire = Some(Wire::Legacy);
+                Ok(Some(Incoming::Handshake {
+                    wire: Ok(Wire::Legacy),
+                    reply: Vec::new(),
+                }))
             }
-            c => {
-                let msg = format!("Unknown server message: {}", c);
-                Err(ProtocolError { msg })
+            None => {
+                let Some(frame) = self.take_frame()? else {
+                    return Ok(None);
+                };
+                if frame.msg_type != HANDSHAKE_BYTE {
+                    let msg = format!("Expected handshake, got message type {}", frame.msg_type);
+                    return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Malformed));
+                }
+
+                if !is_supported(frame.version) {
+                    let reply = [HANDSHAKE_REJECTED, PROTOCOL_VERSION];
+                    return Ok(Some(Incoming::Handshake {
+                        wire: Err(version_mismatch(frame.version)),
+                        reply: encode_frame(PROTOCOL_VERSION, HANDSHAKE_BYTE, &reply),
+                    }));
+                }
+
+                let wire = Wire::Framed(frame.version);
+                self.wire = Some(wire);
+                let reply = [HANDSHAKE_ACCEPTED, frame.version];
+                Ok(Some(Incoming::Handshake {
+                    wire: Ok(wire),
+                    reply: encode_frame(frame.version, HANDSHAKE_BYTE, &reply),
+                }))
+            }
+            Some(Wire::Legacy) => {
+                self.read += 1;
+                match decode_legacy_client_msg(first) {
+                    Some(msg) => Ok(Some(Incoming::Message(msg))),
+                    None => {
+                        let msg = format!("Unknown client message: {}", char::from(first));
+                        Err(ProtocolError::new_kind(
+                            msg,
+                            ProtocolErrorKind::UnknownMessage,
+                        ))
+                    }
+                }
+            }
+            Some(Wire::Framed(version)) => {
+                let Some(frame) = self.take_frame()? else {
+                    return Ok(None);
+                };
+                check_version(&frame, version)?;
+
+                Ok(Some(Incoming::Message(decode_client_msg(&frame)?)))
             }
         }
     }
+
+    /// Saca un frame del buffer si ya llegó entero.
+    fn take_frame(&mut self) -> Result<Option<Frame>, ProtocolError> {
+        let unread = self.unread();
+        if unread.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([unread[2], unread[3], unread[4], unread[5]]);
+        if len > MAX_PAYLOAD_LEN {
+            let msg = format!("Payload too big ({} bytes)", len);
+            return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Malformed));
+        }
+
+        let frame_len = HEADER_LEN + len as usize;
+        if unread.len() < frame_len {
+            return Ok(None);
+        }
+        let frame = read_frame(&mut &unread[..frame_len])?;
+        self.read += frame_len;
+        Ok(Some(frame))
+    }
 }
 
-pub struct StreamToClient {
-    stream: TcpStream,
+fn is_supported(version: u8) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
 }
 
-impl StreamToClient {
-    pub fn new(stream: TcpStream) -> Self {
-        StreamToClient { stream }
+fn version_mismatch(version: u8) -> ProtocolError {
+    let msg = format!(
+        "Unsupported protocol version {} (supported: {}-{})",
+        version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+    );
+    ProtocolError::new_kind(msg, ProtocolErrorKind::VersionMismatch)
+}
+
+fn check_version(frame: &Frame, version: u8) -> Result<(), ProtocolError> {
+    if frame.version != version {
+        let msg = format!(
+            "Frame has protocol version {} but version {} was negotiated",
+            frame.version, version
+        );
+        return Err(ProtocolError::new_kind(
+            msg,
+            ProtocolErrorKind::VersionMismatch,
+        ));
+    }
+    Ok(())
+}
+
+fn decode_handshake_reply(payload: &[u8]) -> Result<u8, ProtocolError> {
+    match payload {
+        [HANDSHAKE_ACCEPTED, version] => Ok(*version),
+        [HANDSHAKE_REJECTED, server_version] => {
+            let msg = format!(
+                "Server rejected protocol version {} (server speaks version {})",
+                PROTOCOL_VERSION, server_version
+            );
+            Err(ProtocolError::new_kind(
+                msg,
+                ProtocolErrorKind::VersionMismatch,
+            ))
+        }
+        _ => Err(ProtocolError::new_kind(
+            "Malformed handshake reply",
+            ProtocolErrorKind::Malformed,
+        )),
     }
+}
 
-    pub fn send_message(&mut self, msg: ServerMessage) -> Result<(), ProtocolError> {
-        let encoded_msg = encode_server_msg(msg)?;
+fn write_frame(
+    stream: &mut impl Write,
+    version: u8,
+    msg_type: u8,
+    payload: &[u8],
+) -> Result<(), ProtocolError> {
+    stream.write_all(&encode_frame(version, msg_type, payload))?;
+    Ok(())
+}
 
-        self.stream.write_all(&encoded_msg)?;
-        Ok(())
+fn read_frame(stream: &mut impl Read) -> Result<Frame, ProtocolError> {
+    let mut version = [0u8; 1];
+
+    stream.read_exact(&mut version)?;
+
+    read_frame_after(version[0], stream)
+}
+
+/// Lee el resto de un frame cuyo primer byte (la versión) ya fue consumido.
+fn read_frame_after(version: u8, stream: &mut impl Read) -> Result<Frame, ProtocolError> {
+    let mut header = [0u8; HEADER_LEN - 1];
+
+    stream.read_exact(&mut header)?;
+
+    let msg_type = header[0];
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+
+    if len > MAX_PAYLOAD_LEN {
+        let msg = format!("Payload too big ({} bytes)", len);
+        return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Malformed));
     }
 
-    pub fn recv_message(&mut self) -> Result<ClientMessage, ProtocolError> {
-        let mut buffer = vec![0u8; 1];
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload)?;
 
-        self.stream.read_exact(&mut buffer)?;
+    Ok(Frame {
+        version,
+        msg_type,
+        payload,
+    })
+}
 
-        // Should never panic
-        let msg_byte = char::from(buffer.pop().unwrap());
+fn encode_frame(version: u8, msg_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.push(version);
+    frame.push(msg_type);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
 
-        match msg_byte {
-            INSERT_BYTE => Ok(ClientMessage::Insert),
-            CONSULT_BYTE => Ok(ClientMessage::ConsultPool),
-            QUIT_BYTE => Ok(ClientMessage::Quit),
-            c => {
-                let msg = format!("Unknown client message: {}", c);
-                Err(ProtocolError { msg })
-            }
+fn encode_client_msg(msg: ClientMessage, version: u8) -> Vec<u8> {
+    match msg {
+        ClientMessage::Hello { name, token } => {
+            let mut payload = Vec::new();
+            push_string(&mut payload, &name);
+            push_string(&mut payload, &token);
+            encode_frame(version, HELLO_BYTE, &payload)
+        }
+        ClientMessage::Insert => encode_frame(version, INSERT_BYTE, &[]),
+        ClientMessage::InsertMany(n) => {
+            encode_frame(version, INSERT_MANY_BYTE, &encode_varint(n.into()))
+        }
+        ClientMessage::ConsultPool => encode_frame(version, CONSULT_BYTE, &[]),
+        ClientMessage::ConsultBalance => encode_frame(version, CONSULT_BALANCE_BYTE, &[]),
+        ClientMessage::ListMachines => encode_frame(version, LIST_MACHINES_BYTE, &[]),
+        ClientMessage::JoinMachine(id) => {
+            encode_frame(version, JOIN_MACHINE_BYTE, &encode_varint(id.into()))
         }
+        ClientMessage::LeaveMachine => encode_frame(version, LEAVE_MACHINE_BYTE, &[]),
+        ClientMessage::InsertAt(lane) => {
+            encode_frame(version, INSERT_AT_BYTE, &encode_varint(lane.into()))
+        }
+        ClientMessage::ConsultBoard => encode_frame(version, CONSULT_BOARD_BYTE, &[]),
+        ClientMessage::SetClientSeed(seed) => {
+            let mut payload = Vec::new();
+            push_string(&mut payload, &seed);
+            encode_frame(version, SET_CLIENT_SEED_BYTE, &payload)
+        }
+        ClientMessage::ConsultCommitment => encode_frame(version, CONSULT_COMMITMENT_BYTE, &[]),
+        ClientMessage::RevealSeed => encode_frame(version, REVEAL_SEED_BYTE, &[]),
+        ClientMessage::ConsultJackpot => encode_frame(version, CONSULT_JACKPOT_BYTE, &[]),
+        ClientMessage::ConsultInventory => encode_frame(version, CONSULT_INVENTORY_BYTE, &[]),
+        ClientMessage::Quit => encode_frame(version, QUIT_BYTE, &[]),
     }
 }
 
-fn encode_client_msg(msg: ClientMessage) -> Vec<u8> {
-    match msg {
-        ClientMessage::Insert => format!("{}", INSERT_BYTE).into_bytes(),
-        ClientMessage::ConsultPool => format!("{}", CONSULT_BYTE).into_bytes(),
-        ClientMessage::Quit => format!("{}", QUIT_BYTE).into_bytes(),
+fn decode_client_msg(frame: &Frame) -> Result<ClientMessage, ProtocolError> {
+    match frame.msg_type {
+        HELLO_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let name = payload.string()?;
+            let token = payload.string()?;
+            payload.finish()?;
+            Ok(ClientMessage::Hello { name, token })
+        }
+        INSERT_BYTE => Ok(ClientMessage::Insert),
+        INSERT_MANY_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let n = payload.count()?;
+            payload.finish()?;
+            Ok(ClientMessage::InsertMany(n))
+        }
+        CONSULT_BYTE => Ok(ClientMessage::ConsultPool),
+        CONSULT_BALANCE_BYTE => Ok(ClientMessage::ConsultBalance),
+        LIST_MACHINES_BYTE => Ok(ClientMessage::ListMachines),
+        JOIN_MACHINE_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let id = payload.count()?;
+            payload.finish()?;
+            Ok(ClientMessage::JoinMachine(id))
+        }
+        LEAVE_MACHINE_BYTE => Ok(ClientMessage::LeaveMachine),
+        INSERT_AT_BYTE => Ok(ClientMessage::InsertAt(decode_varint_u32(frame)?)),
+        CONSULT_BOARD_BYTE => Ok(ClientMessage::ConsultBoard),
+        SET_CLIENT_SEED_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let seed = payload.string()?;
+            payload.finish()?;
+            Ok(ClientMessage::SetClientSeed(seed))
+        }
+        CONSULT_COMMITMENT_BYTE => Ok(ClientMessage::ConsultCommitment),
+        REVEAL_SEED_BYTE => Ok(ClientMessage::RevealSeed),
+        CONSULT_JACKPOT_BYTE => Ok(ClientMessage::ConsultJackpot),
+        CONSULT_INVENTORY_BYTE => Ok(ClientMessage::ConsultInventory),
+        QUIT_BYTE => Ok(ClientMessage::Quit),
+        c => {
+            let msg = format!("Unknown client message: {}", c);
+            Err(ProtocolError::new_kind(
+                msg,
+                ProtocolErrorKind::UnknownMessage,
+            ))
+        }
     }
 }
 
-fn encode_server_msg(msg: ServerMessage) -> Result<Vec<u8>, ProtocolError> {
+fn encode_server_msg(msg: ServerMessage, version: u8) -> Result<Vec<u8>, ProtocolError> {
     match msg {
-        ServerMessage::FellCoins(n) => {
-            if n > 99999 {
-                let msg = format!("n ({}) too big. Can't have more than 5 digits", n);
-                Err(ProtocolError { msg })
-            } else {
-                Ok(format!("{}{:0>5}", FELL_BYTE, n.to_string()).into_bytes())
+        ServerMessage::FellCoins(n) => Ok(encode_frame(
+            version,
+            FELL_BYTE,
+            &encode_count(n.into(), version),
+        )),
+        ServerMessage::PoolState(n) => Ok(encode_frame(
+            version,
+            POOL_BYTE,
+            &encode_count(n.into(), version),
+        )),
+        ServerMessage::FellCoinsBatch { outcomes, total } => {
+            let mut payload = Vec::new();
+            push_varint(&mut payload, outcomes.len() as u64);
+            for fell in outcomes {
+                push_varint(&mut payload, fell.into());
             }
+            push_varint(&mut payload, total.into());
+            Ok(encode_frame(version, BATCH_BYTE, &payload))
         }
-        ServerMessage::PoolState(n) => {
-            if n > 99999 {
-                let msg = format!("n ({}) too big. Can't have more than 5 digits", n);
-                Err(ProtocolError { msg })
-            } else {
-                Ok(format!("{}{:0>5}", POOL_BYTE, n.to_string()).into_bytes())
+        ServerMessage::BatchTooLarge(max) => Ok(encode_frame(
+            version,
+            BATCH_TOO_LARGE_BYTE,
+            &encode_varint(max.into()),
+        )),
+        ServerMessage::Balance(n) => Ok(encode_frame(
+            version,
+            BALANCE_BYTE,
+            &encode_count(n.into(), version),
+        )),
+        ServerMessage::InsufficientFunds(n) => Ok(encode_frame(
+            version,
+            INSUFFICIENT_FUNDS_BYTE,
+            &encode_count(n.into(), version),
+        )),
+        ServerMessage::PoolFull(room) => Ok(encode_frame(
+            version,
+            POOL_FULL_BYTE,
+            &encode_count(room.into(), version),
+        )),
+        ServerMessage::Welcome(session_id) => Ok(encode_frame(
+            version,
+            WELCOME_BYTE,
+            &encode_varint(session_id),
+        )),
+        ServerMessage::LoginRejected(reason) => {
+            let mut payload = Vec::new();
+            push_string(&mut payload, &reason);
+            Ok(encode_frame(version, LOGIN_REJECTED_BYTE, &payload))
+        }
+        ServerMessage::LoginRequired => Ok(encode_frame(version, LOGIN_REQUIRED_BYTE, &[])),
+        ServerMessage::Notification(notification) => {
+            if version < NOTIFICATIONS_VERSION {
+                let msg = format!("Version {} does not support notifications", version);
+                return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Unsupported));
+            }
+            Ok(encode_frame(
+                version,
+                NOTIFICATION_BYTE,
+                &encode_notification(&notification),
+            ))
+        }
+        ServerMessage::MachineList(machines) => {
+            let mut payload = Vec::new();
+            push_varint(&mut payload, machines.len() as u64);
+            for machine in machines {
+                push_varint(&mut payload, machine.id.into());
+                push_varint(&mut payload, machine.pool.into());
+                push_varint(&mut payload, machine.players.into());
+            }
+            Ok(encode_frame(version, MACHINE_LIST_BYTE, &payload))
+        }
+        ServerMessage::Joined(id) => Ok(encode_frame(
+            version,
+            JOINED_BYTE,
+            &encode_varint(id.into()),
+        )),
+        ServerMessage::Left(id) => Ok(encode_frame(version, LEFT_BYTE, &encode_varint(id.into()))),
+        ServerMessage::UnknownMachine(id) => Ok(encode_frame(
+            version,
+            UNKNOWN_MACHINE_BYTE,
+            &encode_varint(id.into()),
+        )),
+        ServerMessage::NotInMachine => Ok(encode_frame(version, NOT_IN_MACHINE_BYTE, &[])),
+        ServerMessage::ServerBusy => Ok(encode_frame(version, SERVER_BUSY_BYTE, &[])),
+        ServerMessage::MachineFull { machine, position } => {
+            let mut payload = Vec::new();
+            push_varint(&mut payload, machine.into());
+            push_varint(&mut payload, position.into());
+            Ok(encode_frame(version, MACHINE_FULL_BYTE, &payload))
+        }
+        ServerMessage::Dropped { lane, fell } => {
+            let mut payload = Vec::new();
+            push_varint(&mut payload, lane.into());
+            push_varint(&mut payload, fell.into());
+            Ok(encode_frame(version, DROPPED_BYTE, &payload))
+        }
+        ServerMessage::BoardState(board) => {
+            let mut payload = Vec::new();
+            push_varint(&mut payload, board.machine.into());
+            push_varint(&mut payload, board.width.into());
+            push_varint(&mut payload, board.depth.into());
+            push_varint(&mut payload, board.cells.len() as u64);
+            for coins in board.cells {
+                push_varint(&mut payload, coins.into());
+            }
+            Ok(encode_frame(version, BOARD_STATE_BYTE, &payload))
+        }
+        ServerMessage::NotABoard(id) => Ok(encode_frame(
+            version,
+            NOT_A_BOARD_BYTE,
+            &encode_varint(id.into()),
+        )),
+        ServerMessage::InvalidLane(width) => Ok(encode_frame(
+            version,
+            INVALID_LANE_BYTE,
+            &encode_varint(width.into()),
+        )),
+        ServerMessage::Commitment {
+            server_seed_hash,
+            client_seed,
+            nonce,
+            payout,
+        } => {
+            let mut payload = Vec::new();
+            push_string(&mut payload, &server_seed_hash);
+            push_string(&mut payload, &client_seed);
+            push_varint(&mut payload, nonce);
+            if version >= COMMITTED_PAYOUT_VERSION {
+                push_payout(&mut payload, payout.as_ref());
+            }
+            Ok(encode_frame(version, COMMITMENT_BYTE, &payload))
+        }
+        ServerMessage::PayoutChanged => {
+            if version < COMMITTED_PAYOUT_VERSION {
+                let msg = format!("Version {} does not commit to a payout model", version);
+                return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Unsupported));
+            }
+            Ok(encode_frame(version, PAYOUT_CHANGED_BYTE, &[]))
+        }
+        ServerMessage::SeedRevealed {
+            server_seed,
+            client_seed,
+            plays,
+            next_hash,
+        } => {
+            let mut payload = Vec::new();
+            push_string(&mut payload, &server_seed);
+            push_string(&mut payload, &client_seed);
+            push_varint(&mut payload, plays.len() as u64);
+            for play in plays {
+                push_varint(&mut payload, play.nonce);
+                push_varint(&mut payload, play.pool.into());
+                // Marca de presencia seguida de los bits del f64
+                match play.probability {
+                    Some(probability) => {
+                        payload.push(1);
+                        push_f64(&mut payload, probability);
+                    }
+                    None => payload.push(0),
+                }
+                push_varint(&mut payload, play.fell.into());
+            }
+            push_string(&mut payload, &next_hash);
+            Ok(encode_frame(version, SEED_REVEALED_BYTE, &payload))
+        }
+        ServerMessage::InvalidClientSeed(max) => Ok(encode_frame(
+            version,
+            INVALID_CLIENT_SEED_BYTE,
+            &encode_varint(max.into()),
+        )),
+        ServerMessage::RevealRequired(max) => Ok(encode_frame(
+            version,
+            REVEAL_REQUIRED_BYTE,
+            &encode_varint(max.into()),
+        )),
+        ServerMessage::Jackpot {
+            machine,
+            player,
+            coins,
+        } => {
+            // Llega sin que se pida, igual que una notificación
+            if version < NOTIFICATIONS_VERSION {
+                let msg = format!("Version {} does not support jackpot messages", version);
+                return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Unsupported));
+            }
+            let mut payload = Vec::new();
+            push_varint(&mut payload, machine.into());
+            push_string(&mut payload, &player);
+            push_varint(&mut payload, coins.into());
+            Ok(encode_frame(version, JACKPOT_BYTE, &payload))
+        }
+        ServerMessage::JackpotState(coins) => Ok(encode_frame(
+            version,
+            JACKPOT_STATE_BYTE,
+            &encode_varint(coins.into()),
+        )),
+        ServerMessage::ItemsWon { machine, items } => {
+            if version < NOTIFICATIONS_VERSION {
+                let msg = format!("Version {} does not support item messages", version);
+                return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Unsupported));
+            }
+            let mut payload = Vec::new();
+            push_varint(&mut payload, machine.into());
+            push_varint(&mut payload, items.len() as u64);
+            payload.extend(items.into_iter().map(item_byte));
+            Ok(encode_frame(version, ITEMS_WON_BYTE, &payload))
+        }
+        ServerMessage::Inventory(items) => {
+            let mut payload = Vec::new();
+            push_varint(&mut payload, items.len() as u64);
+            for (item, count) in items {
+                payload.push(item_byte(item));
+                push_varint(&mut payload, count.into());
+            }
+            Ok(encode_frame(version, INVENTORY_BYTE, &payload))
+        }
+        ServerMessage::ShuttingDown(secs) => {
+            if version < NOTIFICATIONS_VERSION {
+                let msg = format!("Version {} does not support shutdown messages", version);
+                return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Unsupported));
+            }
+            Ok(encode_frame(
+                version,
+                SHUTTING_DOWN_BYTE,
+                &encode_varint(secs.into()),
+            ))
+        }
+    }
+}
+
+fn item_byte(item: Item) -> u8 {
+    match item {
+        Item::Multiplier => MULTIPLIER_BYTE,
+        Item::FreeDrop => FREE_DROP_BYTE,
+        Item::Badge => BADGE_BYTE,
+    }
+}
+
+fn encode_notification(notification: &Notification) -> Vec<u8> {
+    let mut payload = Vec::new();
+    match notification {
+        Notification::PoolChanged { machine, pool } => {
+            payload.push(POOL_CHANGED_BYTE);
+            push_varint(&mut payload, (*machine).into());
+            push_varint(&mut payload, (*pool).into());
+        }
+        Notification::CoinsFell { player, coins } => {
+            payload.push(COINS_FELL_BYTE);
+            push_string(&mut payload, player);
+            push_varint(&mut payload, (*coins).into());
+        }
+        Notification::PlayerJoined(player) => {
+            payload.push(PLAYER_JOINED_BYTE);
+            push_string(&mut payload, player);
+        }
+        Notification::PlayerLeft(player) => {
+            payload.push(PLAYER_LEFT_BYTE);
+            push_string(&mut payload, player);
+        }
+        Notification::Seated(machine) => {
+            payload.push(SEATED_BYTE);
+            push_varint(&mut payload, (*machine).into());
+        }
+    }
+    payload
+}
+
+fn decode_notification(buffer: &[u8]) -> Result<Notification, ProtocolError> {
+    let mut payload = Payload::new(buffer);
+    let notification = match payload.u8()? {
+        POOL_CHANGED_BYTE => Notification::PoolChanged {
+            machine: payload.count()?,
+            pool: payload.count()?,
+        },
+        COINS_FELL_BYTE => Notification::CoinsFell {
+            player: payload.string()?,
+            coins: payload.count()?,
+        },
+        PLAYER_JOINED_BYTE => Notification::PlayerJoined(payload.string()?),
+        PLAYER_LEFT_BYTE => Notification::PlayerLeft(payload.string()?),
+        SEATED_BYTE => Notification::Seated(payload.count()?),
+        c => {
+            let msg = format!("Unknown notification: {}", c);
+            return Err(ProtocolError::new_kind(
+                msg,
+                ProtocolErrorKind::UnknownMessage,
+            ));
+        }
+    };
+    payload.finish()?;
+    Ok(notification)
+}
+
+fn decode_server_msg(frame: &Frame) -> Result<ServerMessage, ProtocolError> {
+    match frame.msg_type {
+        FELL_BYTE => Ok(ServerMessage::FellCoins(decode_count_u32(frame)?)),
+        POOL_BYTE => Ok(ServerMessage::PoolState(decode_count_u32(frame)?)),
+        BATCH_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let len = payload.varint()?;
+            let outcomes = (0..len)
+                .map(|_| payload.count())
+                .collect::<Result<Vec<_>, _>>()?;
+            let total = payload.count()?;
+            payload.finish()?;
+            Ok(ServerMessage::FellCoinsBatch { outcomes, total })
+        }
+        BATCH_TOO_LARGE_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let max = payload.count()?;
+            payload.finish()?;
+            Ok(ServerMessage::BatchTooLarge(max))
+        }
+        BALANCE_BYTE => Ok(ServerMessage::Balance(decode_count_u32(frame)?)),
+        INSUFFICIENT_FUNDS_BYTE => Ok(ServerMessage::InsufficientFunds(decode_count_u32(frame)?)),
+        POOL_FULL_BYTE => Ok(ServerMessage::PoolFull(decode_count_u32(frame)?)),
+        WELCOME_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let session_id = payload.varint()?;
+            payload.finish()?;
+            Ok(ServerMessage::Welcome(session_id))
+        }
+        LOGIN_REJECTED_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let reason = payload.string()?;
+            payload.finish()?;
+            Ok(ServerMessage::LoginRejected(reason))
+        }
+        LOGIN_REQUIRED_BYTE => Ok(ServerMessage::LoginRequired),
+        NOTIFICATION_BYTE => Ok(ServerMessage::Notification(decode_notification(
+            &frame.payload,
+        )?)),
+        MACHINE_LIST_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let len = payload.varint()?;
+            let machines = (0..len)
+                .map(|_| {
+                    Ok(MachineInfo {
+                        id: payload.count()?,
+                        pool: payload.count()?,
+                        players: payload.count()?,
+                    })
+                })
+                .collect::<Result<Vec<_>, ProtocolError>>()?;
+            payload.finish()?;
+            Ok(ServerMessage::MachineList(machines))
+        }
+        JOINED_BYTE => Ok(ServerMessage::Joined(decode_varint_u32(frame)?)),
+        LEFT_BYTE => Ok(ServerMessage::Left(decode_varint_u32(frame)?)),
+        UNKNOWN_MACHINE_BYTE => Ok(ServerMessage::UnknownMachine(decode_varint_u32(frame)?)),
+        NOT_IN_MACHINE_BYTE => Ok(ServerMessage::NotInMachine),
+        SERVER_BUSY_BYTE => Ok(ServerMessage::ServerBusy),
+        SHUTTING_DOWN_BYTE => Ok(ServerMessage::ShuttingDown(decode_count_u32(frame)?)),
+        MACHINE_FULL_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let machine = payload.count()?;
+            let position = payload.count()?;
+            payload.finish()?;
+            Ok(ServerMessage::MachineFull { machine, position })
+        }
+        DROPPED_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let lane = payload.count()?;
+            let fell = payload.count()?;
+            payload.finish()?;
+            Ok(ServerMessage::Dropped { lane, fell })
+        }
+        BOARD_STATE_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let machine = payload.count()?;
+            let width = payload.count()?;
+            let depth = payload.count()?;
+            let len = payload.varint()?;
+            if len != u64::from(width) * u64::from(depth) {
+                let msg = format!("Board of {}x{} with {} cells", width, depth, len);
+                return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Malformed));
             }
+            let cells = (0..len)
+                .map(|_| payload.count())
+                .collect::<Result<Vec<_>, _>>()?;
+            payload.finish()?;
+            Ok(ServerMessage::BoardState(BoardInfo {
+                machine,
+                width,
+                depth,
+                cells,
+            }))
+        }
+        NOT_A_BOARD_BYTE => Ok(ServerMessage::NotABoard(decode_varint_u32(frame)?)),
+        INVALID_LANE_BYTE => Ok(ServerMessage::InvalidLane(decode_varint_u32(frame)?)),
+        COMMITMENT_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let server_seed_hash = payload.string()?;
+            let client_seed = payload.string()?;
+            let nonce = payload.varint()?;
+            let payout = if frame.version >= COMMITTED_PAYOUT_VERSION {
+                payload.payout()?
+            } else {
+                None
+            };
+            payload.finish()?;
+            Ok(ServerMessage::Commitment {
+                server_seed_hash,
+                client_seed,
+                nonce,
+                payout,
+            })
+        }
+        PAYOUT_CHANGED_BYTE => Ok(ServerMessage::PayoutChanged),
+        SEED_REVEALED_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let server_seed = payload.string()?;
+            let client_seed = payload.string()?;
+            let len = payload.varint()?;
+            let plays = (0..len)
+                .map(|_| {
+                    let nonce = payload.varint()?;
+                    let pool = payload.count()?;
+                    let probability = match payload.u8()? {
+                        0 => None,
+                        1 => Some(payload.f64()?),
+                        flag => {
+                            let msg = format!("Invalid probability flag: {}", flag);
+                            return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Malformed));
+                        }
+                    };
+                    let fell = payload.count()?;
+                    Ok(FairPlay {
+                        nonce,
+                        pool,
+                        probability,
+                        fell,
+                    })
+                })
+                .collect::<Result<Vec<_>, ProtocolError>>()?;
+            let next_hash = payload.string()?;
+            payload.finish()?;
+            Ok(ServerMessage::SeedRevealed {
+                server_seed,
+                client_seed,
+                plays,
+                next_hash,
+            })
         }
+        INVALID_CLIENT_SEED_BYTE => Ok(ServerMessage::InvalidClientSeed(decode_varint_u32(frame)?)),
+        REVEAL_REQUIRED_BYTE => Ok(ServerMessage::RevealRequired(decode_varint_u32(frame)?)),
+        JACKPOT_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let machine = payload.count()?;
+            let player = payload.string()?;
+            let coins = payload.count()?;
+            payload.finish()?;
+            Ok(ServerMessage::Jackpot {
+                machine,
+                player,
+                coins,
+            })
+        }
+        JACKPOT_STATE_BYTE => Ok(ServerMessage::JackpotState(decode_varint_u32(frame)?)),
+        ITEMS_WON_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let machine = payload.count()?;
+            let len = payload.varint()?;
+            let items = (0..len)
+                .map(|_| payload.item())
+                .collect::<Result<Vec<_>, _>>()?;
+            payload.finish()?;
+            Ok(ServerMessage::ItemsWon { machine, items })
+        }
+        INVENTORY_BYTE => {
+            let mut payload = Payload::new(&frame.payload);
+            let len = payload.varint()?;
+            let items = (0..len)
+                .map(|_| Ok((payload.item()?, payload.count()?)))
+                .collect::<Result<Vec<_>, ProtocolError>>()?;
+            payload.finish()?;
+            Ok(ServerMessage::Inventory(items))
+        }
+        c => {
+            let msg = format!("Unknown server message: {}", c);
+            Err(ProtocolError::new_kind(
+                msg,
+                ProtocolErrorKind::UnknownMessage,
+            ))
+        }
+    }
+}
+
+fn decode_legacy_client_msg(byte: u8) -> Option<ClientMessage> {
+    match byte {
+        INSERT_BYTE => Some(ClientMessage::Insert),
+        CONSULT_BYTE => Some(ClientMessage::ConsultPool),
+        QUIT_BYTE => Some(ClientMessage::Quit),
+        _ => None,
     }
 }
 
+fn encode_legacy_server_msg(msg: ServerMessage) -> Result<Vec<u8>, ProtocolError> {
+    let (tag, n) = match msg {
+        ServerMessage::FellCoins(n) => (FELL_BYTE, n),
+        ServerMessage::PoolState(n) => (POOL_BYTE, n),
+        // Para los clientes viejos la moneda rechazada es una tirada sin premio
+        ServerMessage::InsufficientFunds(_)
+        | ServerMessage::PoolFull(_)
+        | ServerMessage::ServerBusy => (FELL_BYTE, 0),
+        msg => {
+            let msg = format!("Legacy clients do not support {:?}", msg);
+            return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Unsupported));
+        }
+    };
+    let mut encoded = vec![tag];
+    encoded.extend(encode_legacy_count(n.into()));
+    Ok(encoded)
+}
+
+// Desde la versión 2 las cantidades viajan como varint (LEB128)
+fn encode_count(n: u64, version: u8) -> Vec<u8> {
+    if version >= VARINT_COUNTS_VERSION {
+        encode_varint(n)
+    } else {
+        encode_legacy_count(n)
+    }
+}
+
+fn decode_count_u32(frame: &Frame) -> Result<u32, ProtocolError> {
+    if frame.version >= VARINT_COUNTS_VERSION {
+        let mut payload = Payload::new(&frame.payload);
+        let n = payload.count()?;
+        payload.finish()?;
+        Ok(n)
+    } else {
+        decode_count(&frame.payload)
+    }
+}
+
+fn decode_varint_u32(frame: &Frame) -> Result<u32, ProtocolError> {
+    let mut payload = Payload::new(&frame.payload);
+    let n = payload.count()?;
+    payload.finish()?;
+    Ok(n)
+}
+
+// Los pares de cinco dígitos no pueden representar más de 99999:
+// se satura en lugar de cortar la conexión.
+fn encode_legacy_count(n: u64) -> Vec<u8> {
+    format!("{:0>5}", n.min(LEGACY_COUNT_MAX)).into_bytes()
+}
+
 fn decode_count(buffer: &[u8]) -> Result<u32, ProtocolError> {
-    // Should always be 5 bytes
-    assert_eq!(buffer.len(), 5);
+    if buffer.len() != COUNT_LEN {
+        let msg = format!(
+            "Count should have {} digits, got {}",
+            COUNT_LEN,
+            buffer.len()
+        );
+        return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Malformed));
+    }
     let n = str::from_utf8(buffer)?.parse::<u32>()?;
     Ok(n)
 }
 
+fn encode_varint(mut n: u64) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            encoded.push(byte);
+            return encoded;
+        }
+        encoded.push(byte | 0x80);
+    }
+}
+
+fn push_varint(buffer: &mut Vec<u8>, n: u64) {
+    buffer.extend(encode_varint(n));
+}
+
+fn push_string(buffer: &mut Vec<u8>, s: &str) {
+    push_varint(buffer, s.len() as u64);
+    buffer.extend_from_slice(s.as_bytes());
+}
+
+// Los bits del f64, así llega exacto
+fn push_f64(buffer: &mut Vec<u8>, x: f64) {
+    push_varint(buffer, x.to_bits());
+}
+
+/// Marca de presencia, tipo de modelo y sus parámetros.
+fn push_payout(buffer: &mut Vec<u8>, payout: Option<&PayoutConfig>) {
+    let Some(payout) = payout else {
+        buffer.push(0);
+        return;
+    };
+    buffer.push(1);
+    match payout {
+        PayoutConfig::Logistic(model) => {
+            buffer.push(LOGISTIC_BYTE);
+            push_f64(buffer, model.steepness);
+            push_f64(buffer, model.threshold);
+            push_f64(buffer, model.payout_fraction);
+        }
+        PayoutConfig::Step(model) => {
+            buffer.push(STEP_BYTE);
+            push_varint(buffer, model.threshold.into());
+            push_f64(buffer, model.probability);
+            push_f64(buffer, model.payout_fraction);
+        }
+        PayoutConfig::PiecewiseLinear(model) => {
+            buffer.push(PIECEWISE_LINEAR_BYTE);
+            push_varint(buffer, model.points.len() as u64);
+            for (pool, probability) in &model.points {
+                push_varint(buffer, (*pool).into());
+                push_f64(buffer, *probability);
+            }
+            push_f64(buffer, model.payout_fraction);
+        }
+        PayoutConfig::FixedRtp(model) => {
+            buffer.push(FIXED_RTP_BYTE);
+            push_f64(buffer, model.rtp);
+            push_f64(buffer, model.probability);
+        }
+    }
+}
+
+/// Cursor sobre el payload de un frame.
+struct Payload<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> Payload<'a> {
+    fn new(buffer: &'a [u8]) -> Self {
+        Payload { buffer }
+    }
+
+    fn u8(&mut self) -> Result<u8, ProtocolError> {
+        let (first, rest) = self.buffer.split_first().ok_or_else(|| {
+            ProtocolError::new_kind("Truncated payload", ProtocolErrorKind::Malformed)
+        })?;
+        self.buffer = rest;
+        Ok(*first)
+    }
+
+    fn varint(&mut self) -> Result<u64, ProtocolError> {
+        let mut n: u64 = 0;
+        for i in 0..MAX_VARINT_LEN {
+            let byte = self.u8().map_err(|_| {
+                ProtocolError::new_kind("Truncated varint", ProtocolErrorKind::Malformed)
+            })?;
+            if i == MAX_VARINT_LEN - 1 && byte > 1 {
+                break;
+            }
+            n |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(ProtocolError::new_kind(
+            "Varint overflows 64 bits",
+            ProtocolErrorKind::Malformed,
+        ))
+    }
+
+    fn count(&mut self) -> Result<u32, ProtocolError> {
+        let n = self.varint()?;
+        u32::try_from(n).map_err(|_| {
+            let msg = format!("Count {} does not fit in 32 bits", n);
+            ProtocolError::new_kind(msg, ProtocolErrorKind::Malformed)
+        })
+    }
+
+    fn string(&mut self) -> Result<String, ProtocolError> {
+        let len = self.varint()? as usize;
+        if len > self.buffer.len() {
+            return Err(ProtocolError::new_kind(
+                "Truncated string",
+                ProtocolErrorKind::Malformed,
+            ));
+        }
+        let (s, rest) = self.buffer.split_at(len);
+        self.buffer = rest;
+        Ok(str::from_utf8(s)?.to_string())
+    }
+
+    fn f64(&mut self) -> Result<f64, ProtocolError> {
+        Ok(f64::from_bits(self.varint()?))
+    }
+
+    /// Los parámetros pasan por las mismas validaciones que en el servidor.
+    fn payout(&mut self) -> Result<Option<PayoutConfig>, ProtocolError> {
+        match self.u8()? {
+            0 => return Ok(None),
+            1 => (),
+            flag => {
+                let msg = format!("Invalid payout flag: {}", flag);
+                return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Malformed));
+            }
+        }
+        let payout = match self.u8()? {
+            LOGISTIC_BYTE => {
+                Logistic::new(self.f64()?, self.f64()?, self.f64()?).map(PayoutConfig::Logistic)
+            }
+            STEP_BYTE => Step::new(self.count()?, self.f64()?, self.f64()?).map(PayoutConfig::Step),
+            PIECEWISE_LINEAR_BYTE => {
+                let len = self.varint()?;
+                let points = (0..len)
+                    .map(|_| Ok((self.count()?, self.f64()?)))
+                    .collect::<Result<Vec<_>, ProtocolError>>()?;
+                PiecewiseLinear::new(points, self.f64()?).map(PayoutConfig::PiecewiseLinear)
+            }
+            FIXED_RTP_BYTE => FixedRtp::new(self.f64()?, self.f64()?).map(PayoutConfig::FixedRtp),
+            c => {
+                let msg = format!("Unknown payout model: {}", c);
+                return Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Malformed));
+            }
+        };
+        payout
+            .map(Some)
+            .map_err(|e| ProtocolError::new_kind(e, ProtocolErrorKind::Malformed))
+    }
+
+    fn item(&mut self) -> Result<Item, ProtocolError> {
+        match self.u8()? {
+            MULTIPLIER_BYTE => Ok(Item::Multiplier),
+            FREE_DROP_BYTE => Ok(Item::FreeDrop),
+            BADGE_BYTE => Ok(Item::Badge),
+            c => {
+                let msg = format!("Unknown item: {}", c);
+                Err(ProtocolError::new_kind(msg, ProtocolErrorKind::Malformed))
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), ProtocolError> {
+        if !self.buffer.is_empty() {
+            return Err(ProtocolError::new_kind(
+                "Trailing bytes in payload",
+                ProtocolErrorKind::Malformed,
+            ));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct ProtocolError {
     msg: String,
+    kind: ProtocolErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolErrorKind {
+    VersionMismatch,
+    UnknownMessage,
+    Unsupported,
+    Malformed,
+    Io,
+}
+
+impl ProtocolError {
+    pub fn new_kind<T: Into<String>>(msg: T, kind: ProtocolErrorKind) -> ProtocolError {
+        ProtocolError {
+            msg: msg.into(),
+            kind,
+        }
+    }
+
+    pub fn kind(&self) -> ProtocolErrorKind {
+        self.kind
+    }
 }
 
 impl std::error::Error for ProtocolError {
@@ -169,60 +1492,142 @@ impl fmt::Display for ProtocolError {
 //https://doc.rust-lang.org/book/ch17-02-trait-objects.html#using-trait-objects-that-allow-for-values-of-different-types
 impl From<str::Utf8Error> for ProtocolError {
     fn from(err: str::Utf8Error) -> Self {
-        ProtocolError {
-            msg: format!("{}", err),
-        }
+        ProtocolError::new_kind(format!("{}", err), ProtocolErrorKind::Malformed)
     }
 }
 
 impl From<ParseIntError> for ProtocolError {
     fn from(err: ParseIntError) -> Self {
-        ProtocolError {
-            msg: format!("{}", err),
-        }
+        ProtocolError::new_kind(format!("{}", err), ProtocolErrorKind::Malformed)
     }
 }
 
 impl From<std::io::Error> for ProtocolError {
     fn from(err: std::io::Error) -> Self {
-        ProtocolError {
-            msg: format!("{}", err),
-        }
+        ProtocolError::new_kind(format!("{}", err), ProtocolErrorKind::Io)
     }
 }
 
 #[cfg(test)]
 mod protocol_tests {
     use super::*;
+    use crate::payout::{FixedRtp, Logistic, PiecewiseLinear, Step};
+    use std::io::Cursor;
+
+    fn decode_varint(buffer: &[u8]) -> Result<u64, ProtocolError> {
+        let mut payload = Payload::new(buffer);
+        let n = payload.varint()?;
+        payload.finish()?;
+        Ok(n)
+    }
 
     #[test]
     fn encode_insert_msg() {
         let msg = ClientMessage::Insert;
 
-        let encoded_msg = encode_client_msg(msg);
-        let encoded_msg = str::from_utf8(&encoded_msg).unwrap();
+        let encoded_msg = encode_client_msg(msg, 1);
 
-        assert_eq!(encoded_msg, "t");
+        assert_eq!(encoded_msg, [1, b't', 0, 0, 0, 0]);
     }
 
     #[test]
     fn encode_consult_msg() {
         let msg = ClientMessage::ConsultPool;
 
-        let encoded_msg = encode_client_msg(msg);
-        let encoded_msg = str::from_utf8(&encoded_msg).unwrap();
+        let encoded_msg = encode_client_msg(msg, 1);
 
-        assert_eq!(encoded_msg, "y");
+        assert_eq!(encoded_msg, [1, b'y', 0, 0, 0, 0]);
     }
 
     #[test]
     fn encode_quit_msg() {
         let msg = ClientMessage::Quit;
 
-        let encoded_msg = encode_client_msg(msg);
-        let encoded_msg = str::from_utf8(&encoded_msg).unwrap();
+        let encoded_msg = encode_client_msg(msg, 1);
+
+        assert_eq!(encoded_msg, [1, b'q', 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn insert_many_round_trip() {
+        let encoded_msg = encode_client_msg(ClientMessage::InsertMany(300), 2);
+        assert_eq!(encoded_msg, [2, b'm', 0, 0, 0, 2, 0xac, 0x02]);
+
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        assert!(matches!(
+            decode_client_msg(&frame),
+            Ok(ClientMessage::InsertMany(300))
+        ));
+    }
+
+    #[test]
+    fn hello_round_trip() {
+        let msg = ClientMessage::Hello {
+            name: "nacho".to_string(),
+            token: "s3cr3t".to_string(),
+        };
+
+        let encoded_msg = encode_client_msg(msg, 2);
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+
+        match decode_client_msg(&frame).unwrap() {
+            ClientMessage::Hello { name, token } => {
+                assert_eq!(name, "nacho");
+                assert_eq!(token, "s3cr3t");
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn login_replies_round_trip() {
+        let encoded_msg = encode_server_msg(ServerMessage::Welcome(u64::MAX), 2).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::Welcome(u64::MAX))
+        ));
+
+        let msg = ServerMessage::LoginRejected("Wrong token".to_string());
+        let encoded_msg = encode_server_msg(msg, 2).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        match decode_server_msg(&frame).unwrap() {
+            ServerMessage::LoginRejected(reason) => assert_eq!(reason, "Wrong token"),
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn batch_result_round_trip() {
+        let msg = ServerMessage::FellCoinsBatch {
+            outcomes: vec![0, 3, 0, 200],
+            total: 203,
+        };
+
+        let encoded_msg = encode_server_msg(msg, 2).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+
+        match decode_server_msg(&frame).unwrap() {
+            ServerMessage::FellCoinsBatch { outcomes, total } => {
+                assert_eq!(outcomes, [0, 3, 0, 200]);
+                assert_eq!(total, 203);
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
 
-        assert_eq!(encoded_msg, "q");
+    #[test]
+    fn decode_batch_with_missing_outcomes() {
+        let mut payload = Vec::new();
+        push_varint(&mut payload, 3);
+        push_varint(&mut payload, 1);
+        let frame = Frame {
+            version: 2,
+            msg_type: BATCH_BYTE,
+            payload,
+        };
+
+        assert!(decode_server_msg(&frame).is_err());
     }
 
     #[test]
@@ -247,33 +1652,669 @@ mod protocol_tests {
     fn encode_fell_msg() {
         let msg = ServerMessage::FellCoins(0);
 
-        let encoded_msg = encode_server_msg(msg).unwrap();
-        let encoded_msg = str::from_utf8(&encoded_msg).unwrap();
+        let encoded_msg = encode_server_msg(msg, 1).unwrap();
 
-        assert_eq!(encoded_msg, "f00000");
+        assert_eq!(encoded_msg, b"\x01f\x00\x00\x00\x0500000");
     }
 
     #[test]
     fn encode_pool_msg() {
         let msg = ServerMessage::PoolState(99999);
 
-        let encoded_msg = encode_server_msg(msg).unwrap();
-        let encoded_msg = str::from_utf8(&encoded_msg).unwrap();
+        let encoded_msg = encode_server_msg(msg, 1).unwrap();
 
-        assert_eq!(encoded_msg, "p99999");
+        assert_eq!(encoded_msg, b"\x01p\x00\x00\x00\x0599999");
     }
 
     #[test]
-    fn encode_invalid_fell_msg() {
+    fn encode_v1_fell_msg_saturates() {
         let msg = ServerMessage::FellCoins(100000);
 
-        assert!(encode_server_msg(msg).is_err());
+        let encoded_msg = encode_server_msg(msg, 1).unwrap();
+
+        assert_eq!(encoded_msg, b"\x01f\x00\x00\x00\x0599999");
+    }
+
+    #[test]
+    fn encode_v1_pool_msg_saturates() {
+        let msg = ServerMessage::PoolState(u32::MAX);
+
+        let encoded_msg = encode_server_msg(msg, 1).unwrap();
+
+        assert_eq!(encoded_msg, b"\x01p\x00\x00\x00\x0599999");
+    }
+
+    #[test]
+    fn encode_v2_big_counts() {
+        let msg = ServerMessage::PoolState(u32::MAX);
+
+        let encoded_msg = encode_server_msg(msg, 2).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::PoolState(u32::MAX))
+        ));
+    }
+
+    #[test]
+    fn decode_v1_counts() {
+        let frame = Frame {
+            version: 1,
+            msg_type: FELL_BYTE,
+            payload: b"00042".to_vec(),
+        };
+
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::FellCoins(42))
+        ));
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for n in [0, 1, 127, 128, 99999, 100000, u64::from(u32::MAX), u64::MAX] {
+            assert_eq!(decode_varint(&encode_varint(n)).unwrap(), n);
+        }
+    }
+
+    #[test]
+    fn varint_encoding() {
+        assert_eq!(encode_varint(0), [0]);
+        assert_eq!(encode_varint(127), [0x7f]);
+        assert_eq!(encode_varint(300), [0xac, 0x02]);
+    }
+
+    #[test]
+    fn decode_invalid_varints() {
+        assert!(decode_varint(&[]).is_err());
+        assert!(decode_varint(&[0x80]).is_err());
+        assert!(decode_varint(&[0x01, 0x02]).is_err());
+        assert!(decode_varint(&[0xff; 11]).is_err());
+    }
+
+    #[test]
+    fn varint_count_too_big_for_u32() {
+        let frame = Frame {
+            version: 2,
+            msg_type: POOL_BYTE,
+            payload: encode_varint(u64::from(u32::MAX) + 1),
+        };
+
+        assert!(decode_server_msg(&frame).is_err());
+    }
+
+    #[test]
+    fn decode_legacy_client_msgs() {
+        assert!(matches!(
+            decode_legacy_client_msg(b't'),
+            Some(ClientMessage::Insert)
+        ));
+        assert!(matches!(
+            decode_legacy_client_msg(b'y'),
+            Some(ClientMessage::ConsultPool)
+        ));
+        assert!(matches!(
+            decode_legacy_client_msg(b'q'),
+            Some(ClientMessage::Quit)
+        ));
+        assert!(decode_legacy_client_msg(PROTOCOL_VERSION).is_none());
+    }
+
+    #[test]
+    fn encode_legacy_msgs() {
+        assert_eq!(
+            encode_legacy_server_msg(ServerMessage::FellCoins(12)).unwrap(),
+            b"f00012"
+        );
+        assert_eq!(
+            encode_legacy_server_msg(ServerMessage::PoolState(123456)).unwrap(),
+            b"p99999"
+        );
+    }
+
+    #[test]
+    fn balance_msgs_round_trip() {
+        let encoded_msg = encode_server_msg(ServerMessage::InsufficientFunds(3), 2).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::InsufficientFunds(3))
+        ));
+
+        let encoded_msg = encode_server_msg(ServerMessage::Balance(1000), 2).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::Balance(1000))
+        ));
+
+        let encoded_msg = encode_server_msg(ServerMessage::PoolFull(7), 2).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::PoolFull(7))
+        ));
+
+        let encoded_msg = encode_server_msg(ServerMessage::ServerBusy, 2).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::ServerBusy)
+        ));
+    }
+
+    #[test]
+    fn balance_msgs_round_trip_v1() {
+        let encoded_msg = encode_server_msg(ServerMessage::InsufficientFunds(3), 1).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::InsufficientFunds(3))
+        ));
+
+        let encoded_msg = encode_server_msg(ServerMessage::Balance(1000), 1).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::Balance(1000))
+        ));
+
+        let encoded_msg = encode_server_msg(ServerMessage::PoolFull(7), 1).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::PoolFull(7))
+        ));
+    }
+
+    #[test]
+    fn insufficient_funds_for_legacy_clients() {
+        let encoded_msg = encode_legacy_server_msg(ServerMessage::InsufficientFunds(0)).unwrap();
+
+        assert_eq!(encoded_msg, b"f00000");
+    }
+
+    #[test]
+    fn notifications_round_trip() {
+        let notifications = [
+            Notification::PoolChanged {
+                machine: 3,
+                pool: u32::MAX,
+            },
+            Notification::CoinsFell {
+                player: "127.0.0.1:4000".to_string(),
+                coins: 12,
+            },
+            Notification::PlayerJoined("ñandú".to_string()),
+            Notification::PlayerLeft(String::new()),
+            Notification::Seated(2),
+        ];
+
+        for notification in notifications {
+            let msg = ServerMessage::Notification(notification.clone());
+            let encoded_msg = encode_server_msg(msg, 2).unwrap();
+            let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+
+            match decode_server_msg(&frame).unwrap() {
+                ServerMessage::Notification(decoded) => assert_eq!(decoded, notification),
+                other => panic!("Unexpected message {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn notifications_unsupported_by_old_peers() {
+        let msg = ServerMessage::Notification(Notification::PlayerJoined("ana".to_string()));
+        let err = encode_server_msg(msg, 1).unwrap_err();
+        assert_eq!(err.kind(), ProtocolErrorKind::Unsupported);
+
+        let msg = ServerMessage::Notification(Notification::PlayerJoined("ana".to_string()));
+        let err = encode_legacy_server_msg(msg).unwrap_err();
+        assert_eq!(err.kind(), ProtocolErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn machine_msgs_round_trip() {
+        for msg in [
+            ClientMessage::ListMachines,
+            ClientMessage::JoinMachine(300),
+            ClientMessage::LeaveMachine,
+            ClientMessage::InsertAt(6),
+            ClientMessage::ConsultBoard,
+            ClientMessage::SetClientSeed("lucky".to_string()),
+            ClientMessage::ConsultCommitment,
+            ClientMessage::RevealSeed,
+            ClientMessage::ConsultJackpot,
+            ClientMessage::ConsultInventory,
+        ] {
+            let expected = format!("{:?}", msg);
+            let frame = read_frame(&mut Cursor::new(encode_client_msg(msg, 2))).unwrap();
+            assert_eq!(
+                format!("{:?}", decode_client_msg(&frame).unwrap()),
+                expected
+            );
+        }
+
+        let machines = vec![
+            MachineInfo {
+                id: 1,
+                pool: 100,
+                players: 0,
+            },
+            MachineInfo {
+                id: 7,
+                pool: 999,
+                players: 3,
+            },
+        ];
+        let encoded_msg =
+            encode_server_msg(ServerMessage::MachineList(machines.clone()), 1).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        match decode_server_msg(&frame).unwrap() {
+            ServerMessage::MachineList(decoded) => assert_eq!(decoded, machines),
+            other => panic!("Unexpected message {:?}", other),
+        }
+
+        let encoded_msg = encode_server_msg(ServerMessage::UnknownMachine(42), 2).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::UnknownMachine(42))
+        ));
+
+        let msg = ServerMessage::MachineFull {
+            machine: 3,
+            position: 200,
+        };
+        let encoded_msg = encode_server_msg(msg, 2).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::MachineFull {
+                machine: 3,
+                position: 200
+            })
+        ));
+    }
+
+    #[test]
+    fn decode_truncated_notification() {
+        let mut payload = vec![COINS_FELL_BYTE];
+        push_varint(&mut payload, 10);
+        payload.extend_from_slice(b"abc");
+
+        assert!(decode_notification(&payload).is_err());
+    }
+
+    #[test]
+    fn read_written_frame() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, 1, b'x', b"payload").unwrap();
+
+        let frame = read_frame(&mut Cursor::new(buffer)).unwrap();
+
+        assert_eq!(
+            frame,
+            Frame {
+                version: 1,
+                msg_type: b'x',
+                payload: b"payload".to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn read_frame_with_too_big_payload() {
+        let mut buffer = vec![1, b'x'];
+        buffer.extend_from_slice(&(MAX_PAYLOAD_LEN + 1).to_be_bytes());
+
+        let err = read_frame(&mut Cursor::new(buffer)).unwrap_err();
+
+        assert_eq!(err.kind(), ProtocolErrorKind::Malformed);
+    }
+
+    #[test]
+    fn unknown_message_type_consumes_frame() {
+        let mut buffer = encode_frame(1, b'?', b"abc");
+        buffer.extend(encode_server_msg(ServerMessage::PoolState(7), 1).unwrap());
+        let mut cursor = Cursor::new(buffer);
+
+        let frame = read_frame(&mut cursor).unwrap();
+        let err = decode_server_msg(&frame).unwrap_err();
+        assert_eq!(err.kind(), ProtocolErrorKind::UnknownMessage);
+
+        let frame = read_frame(&mut cursor).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::PoolState(7))
+        ));
+    }
+
+    #[test]
+    fn accepted_handshake_reply() {
+        assert_eq!(decode_handshake_reply(&[HANDSHAKE_ACCEPTED, 1]).unwrap(), 1);
+    }
+
+    #[test]
+    fn rejected_handshake_reply() {
+        let err = decode_handshake_reply(&[HANDSHAKE_REJECTED, 9]).unwrap_err();
+
+        assert_eq!(err.kind(), ProtocolErrorKind::VersionMismatch);
     }
 
     #[test]
-    fn encode_invalid_pool_msg() {
-        let msg = ServerMessage::PoolState(100000);
+    fn decoder_waits_for_whole_frames() {
+        let mut bytes = encode_frame(2, HANDSHAKE_BYTE, &[]);
+        bytes.extend(encode_client_msg(ClientMessage::InsertMany(300), 2));
+        let mut decoder = ClientDecoder::new();
+
+        // Llega de a un byte, como puede pasar en una conexión lenta
+        let mut incoming = Vec::new();
+        for byte in bytes {
+            decoder.push(&[byte]);
+            while let Some(next) = decoder.next_incoming().unwrap() {
+                incoming.push(next);
+            }
+        }
+
+        assert_eq!(decoder.wire(), Some(Wire::Framed(2)));
+        assert!(matches!(
+            &incoming[..],
+            [
+                Incoming::Handshake { wire: Ok(Wire::Framed(2)), reply },
+                Incoming::Message(ClientMessage::InsertMany(300)),
+            ] if decode_handshake_reply(&reply[HEADER_LEN..]).unwrap() == 2
+        ));
+    }
+
+    #[test]
+    fn decoder_accepts_legacy_clients() {
+        let mut decoder = ClientDecoder::new();
+        decoder.push(b"ty");
+
+        assert!(matches!(
+            decoder.next_incoming(),
+            Ok(Some(Incoming::Handshake { wire: Ok(Wire::Legacy), reply })) if reply.is_empty()
+        ));
+        assert!(matches!(
+            decoder.next_incoming(),
+            Ok(Some(Incoming::Message(ClientMessage::Insert)))
+        ));
+        assert!(matches!(
+            decoder.next_incoming(),
+            Ok(Some(Incoming::Message(ClientMessage::ConsultPool)))
+        ));
+        assert!(matches!(decoder.next_incoming(), Ok(None)));
+    }
+
+    #[test]
+    fn decoder_discards_read_bytes_in_bulk() {
+        let mut decoder = ClientDecoder::new();
+        decoder.push(&vec![b't'; MAX_BUFFERED_LEN]);
+        assert_eq!(decoder.room(), 0);
+        assert!(decoder.next_incoming().unwrap().is_some());
+
+        // Como el servidor: saca un mensaje y vuelve a llenar el lugar libre
+        for _ in 0..3 * MAX_BUFFERED_LEN {
+            assert!(matches!(
+                decoder.next_incoming(),
+                Ok(Some(Incoming::Message(ClientMessage::Insert)))
+            ));
+            assert_eq!(decoder.room(), 1);
+            decoder.push(b"t");
+            assert!(decoder.buffer.len() <= 2 * MAX_BUFFERED_LEN);
+        }
+        assert_eq!(decoder.room(), 0);
+    }
+
+    #[test]
+    fn decoder_rejects_unsupported_versions() {
+        let mut decoder = ClientDecoder::new();
+        decoder.push(&encode_frame(9, HANDSHAKE_BYTE, &[]));
+
+        let Ok(Some(Incoming::Handshake { wire, reply })) = decoder.next_incoming() else {
+            panic!("Expected a handshake");
+        };
+        assert_eq!(wire.unwrap_err().kind(), ProtocolErrorKind::VersionMismatch);
+        assert_eq!(
+            decode_handshake_reply(&reply[HEADER_LEN..])
+                .unwrap_err()
+                .kind(),
+            ProtocolErrorKind::VersionMismatch
+        );
+        assert_eq!(decoder.wire(), None);
+    }
+
+    #[test]
+    fn frame_with_other_version_is_rejected() {
+        let frame = Frame {
+            version: 2,
+            msg_type: INSERT_BYTE,
+            payload: vec![],
+        };
+
+        let err = check_version(&frame, 1).unwrap_err();
+
+        assert_eq!(err.kind(), ProtocolErrorKind::VersionMismatch);
+    }
+
+    #[test]
+    fn board_msgs_round_trip() {
+        let board = BoardInfo {
+            machine: 2,
+            width: 3,
+            depth: 2,
+            cells: vec![0, 1, 2, 300, 4, 5],
+        };
+        let encoded_msg = encode_server_msg(ServerMessage::BoardState(board.clone()), 2).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        match decode_server_msg(&frame).unwrap() {
+            ServerMessage::BoardState(decoded) => assert_eq!(decoded, board),
+            other => panic!("Unexpected message {:?}", other),
+        }
+
+        let msg = ServerMessage::Dropped { lane: 4, fell: 12 };
+        let frame = read_frame(&mut Cursor::new(encode_server_msg(msg, 2).unwrap())).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::Dropped { lane: 4, fell: 12 })
+        ));
+
+        let msg = ServerMessage::InvalidLane(7);
+        let frame = read_frame(&mut Cursor::new(encode_server_msg(msg, 2).unwrap())).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::InvalidLane(7))
+        ));
+    }
+
+    #[test]
+    fn decode_board_with_wrong_cell_count() {
+        let mut payload = Vec::new();
+        for n in [1, 3, 2, 5] {
+            push_varint(&mut payload, n);
+        }
+        let frame = Frame {
+            version: 2,
+            msg_type: BOARD_STATE_BYTE,
+            payload,
+        };
+
+        assert!(matches!(
+            decode_server_msg(&frame).map_err(|e| e.kind()),
+            Err(ProtocolErrorKind::Malformed)
+        ));
+    }
+
+    #[test]
+    fn fairness_msgs_round_trip() {
+        let payouts = [
+            None,
+            Some(PayoutConfig::Logistic(
+                Logistic::new(0.02, 700.0, 0.05).unwrap(),
+            )),
+            Some(PayoutConfig::Step(Step::new(500, 0.3, 0.1).unwrap())),
+            Some(PayoutConfig::PiecewiseLinear(
+                PiecewiseLinear::new(vec![(100, 0.0), (300, 0.5)], 0.05).unwrap(),
+            )),
+            Some(PayoutConfig::FixedRtp(FixedRtp::new(0.9, 0.2).unwrap())),
+        ];
+        for payout in payouts {
+            let msg = ServerMessage::Commitment {
+                server_seed_hash: "ab12".to_string(),
+                client_seed: "lucky".to_string(),
+                nonce: 1 << 40,
+                payout,
+            };
+            let expected = format!("{:?}", msg);
+            let frame = read_frame(&mut Cursor::new(encode_server_msg(msg, 3).unwrap())).unwrap();
+            assert_eq!(
+                format!("{:?}", decode_server_msg(&frame).unwrap()),
+                expected
+            );
+        }
+
+        // Antes de la versión 3 el compromiso no lleva el modelo de pago
+        let msg = ServerMessage::Commitment {
+            server_seed_hash: "ab12".to_string(),
+            client_seed: "lucky".to_string(),
+            nonce: 7,
+            payout: Some(PayoutConfig::FixedRtp(FixedRtp::new(0.9, 0.2).unwrap())),
+        };
+        let frame = read_frame(&mut Cursor::new(encode_server_msg(msg, 2).unwrap())).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::Commitment {
+                nonce: 7,
+                payout: None,
+                ..
+            })
+        ));
+
+        let frame = read_frame(&mut Cursor::new(
+            encode_server_msg(ServerMessage::PayoutChanged, 3).unwrap(),
+        ))
+        .unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::PayoutChanged)
+        ));
+        let err = encode_server_msg(ServerMessage::PayoutChanged, 2).unwrap_err();
+        assert_eq!(err.kind(), ProtocolErrorKind::Unsupported);
+
+        let plays = vec![
+            FairPlay {
+                nonce: 0,
+                pool: 700,
+                probability: Some(0.4999),
+                fell: 12,
+            },
+            FairPlay {
+                nonce: 1,
+                pool: 300,
+                probability: None,
+                fell: 0,
+            },
+        ];
+        let msg = ServerMessage::SeedRevealed {
+            server_seed: "ff00".to_string(),
+            client_seed: "lucky".to_string(),
+            plays: plays.clone(),
+            next_hash: "cd34".to_string(),
+        };
+        let frame = read_frame(&mut Cursor::new(encode_server_msg(msg, 2).unwrap())).unwrap();
+        match decode_server_msg(&frame).unwrap() {
+            ServerMessage::SeedRevealed {
+                server_seed,
+                plays: decoded,
+                next_hash,
+                ..
+            } => {
+                assert_eq!(server_seed, "ff00");
+                assert_eq!(decoded, plays);
+                assert_eq!(next_hash, "cd34");
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn jackpot_msgs_round_trip() {
+        let msg = ServerMessage::Jackpot {
+            machine: 3,
+            player: "ana".to_string(),
+            coins: 1200,
+        };
+        let expected = format!("{:?}", msg);
+        let frame =
+            read_frame(&mut Cursor::new(encode_server_msg(msg.clone(), 2).unwrap())).unwrap();
+        assert_eq!(
+            format!("{:?}", decode_server_msg(&frame).unwrap()),
+            expected
+        );
+        let err = encode_server_msg(msg, 1).unwrap_err();
+        assert_eq!(err.kind(), ProtocolErrorKind::Unsupported);
+
+        let frame = read_frame(&mut Cursor::new(
+            encode_server_msg(ServerMessage::JackpotState(75), 2).unwrap(),
+        ))
+        .unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::JackpotState(75))
+        ));
+    }
+
+    #[test]
+    fn shutting_down_round_trip() {
+        let encoded_msg = encode_server_msg(ServerMessage::ShuttingDown(30), 2).unwrap();
+        let frame = read_frame(&mut Cursor::new(encoded_msg)).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::ShuttingDown(30))
+        ));
+
+        let err = encode_server_msg(ServerMessage::ShuttingDown(30), 1).unwrap_err();
+        assert_eq!(err.kind(), ProtocolErrorKind::Unsupported);
+        let err = encode_legacy_server_msg(ServerMessage::ShuttingDown(30)).unwrap_err();
+        assert_eq!(err.kind(), ProtocolErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn item_msgs_round_trip() {
+        let msg = ServerMessage::ItemsWon {
+            machine: 2,
+            items: vec![Item::Badge, Item::Multiplier],
+        };
+        let frame =
+            read_frame(&mut Cursor::new(encode_server_msg(msg.clone(), 2).unwrap())).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::ItemsWon { machine: 2, items })
+                if items == [Item::Badge, Item::Multiplier]
+        ));
+        let err = encode_server_msg(msg, 1).unwrap_err();
+        assert_eq!(err.kind(), ProtocolErrorKind::Unsupported);
+
+        let inventory = vec![
+            (Item::Multiplier, 0),
+            (Item::FreeDrop, 3),
+            (Item::Badge, 300),
+        ];
+        let msg = ServerMessage::Inventory(inventory.clone());
+        let frame = read_frame(&mut Cursor::new(encode_server_msg(msg, 1).unwrap())).unwrap();
+        assert!(matches!(
+            decode_server_msg(&frame),
+            Ok(ServerMessage::Inventory(items)) if items == inventory
+        ));
 
-        assert!(encode_server_msg(msg).is_err());
+        let frame = Frame {
+            version: 2,
+            msg_type: INVENTORY_BYTE,
+            payload: vec![1, b'z', 1],
+        };
+        let err = decode_server_msg(&frame).unwrap_err();
+        assert_eq!(err.kind(), ProtocolErrorKind::Malformed);
     }
 }
@@ -0,0 +1,354 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{items::Inventory, server::traits::AccountStore, wallet::Wallet};
+
+// El log se compacta cuando tiene más de este factor de líneas por cuenta
+const COMPACTION_FACTOR: usize = 4;
+const MIN_LINES_TO_COMPACT: usize = 64;
+
+/// Datos persistentes de un jugador.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    name: String,
+    token_hash: String,
+    #[serde(flatten)]
+    wallet: Wallet,
+    #[serde(default, skip_serializing_if = "Inventory::is_empty")]
+    inventory: Inventory,
+    coins_inserted: u64,
+    coins_won: u64,
+    last_seen: u64,
+}
+
+impl Account {
+    pub fn new(name: &str, token: &str, balance: u32) -> Account {
+        Account {
+            name: name.to_string(),
+            token_hash: hash_token(token),
+            wallet: Wallet::with(balance),
+            inventory: Inventory::default(),
+            coins_inserted: 0,
+            coins_won: 0,
+            last_seen: now(),
+        }
+    }
+
+    pub fn check_token(&self, token: &str) -> bool {
+        self.token_hash == hash_token(token)
+    }
+
+    pub fn wallet(&self) -> &Wallet {
+        &self.wallet
+    }
+
+    pub fn wallet_mut(&mut self) -> &mut Wallet {
+        &mut self.wallet
+    }
+
+    pub fn inventory(&self) -> &Inventory {
+        &self.inventory
+    }
+
+    pub fn inventory_mut(&mut self) -> &mut Inventory {
+        &mut self.inventory
+    }
+
+    pub fn coins_inserted(&self) -> u64 {
+        self.coins_inserted
+    }
+
+    pub fn coins_won(&self) -> u64 {
+        self.coins_won
+    }
+
+    /// Suma una tirada a las estadísticas históricas del jugador.
+    pub fn record_play(&mut self, inserted: u32, won: u32) {
+        self.coins_inserted += u64::from(inserted);
+        self.coins_won += u64::from(won);
+        self.touch();
+    }
+
+    pub fn touch(&mut self) {
+        self.last_seen = now();
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Cuentas en memoria. Se pierden al reiniciar el servidor.
+#[derive(Default)]
+pub struct MemoryAccountStore {
+    accounts: Mutex<HashMap<String, Account>>,
+}
+
+impl AccountStore for MemoryAccountStore {
+    fn load(&self, name: &str) -> io::Result<Option<Account>> {
+        Ok(self.accounts.lock().map_err(poisoned)?.get(name).cloned())
+    }
+
+    fn save(&self, account: &Account) -> io::Result<()> {
+        self.accounts
+            .lock()
+            .map_err(poisoned)?
+            .insert(account.name.clone(), account.clone());
+        Ok(())
+    }
+}
+
+/// Cuentas guardadas en un archivo JSON Lines.
+///
+/// Cada `save` agrega una línea al final del archivo y la sincroniza a disco
+/// fuera del lock, así las escrituras de distintos jugadores comparten la
+/// sincronización en lugar de esperarse; al leer gana la última línea de
+/// cada cuenta. Si el servidor se cae a mitad
+/// de una escritura, la última línea queda incompleta y se descarta al abrir;
+/// si la escritura falla sin que se caiga, el archivo se corta donde estaba.
+/// Cada tanto el archivo se reescribe con una línea por cuenta en un archivo
+/// temporal que después se renombra sobre el original.
+pub struct FileAccountStore {
+    path: PathBuf,
+    state: Mutex<FileState>,
+}
+
+struct FileState {
+    accounts: HashMap<String, Account>,
+    log: Arc<File>,
+    lines: usize,
+    // Largo del archivo hasta la última línea completa
+    bytes: u64,
+}
+
+impl FileAccountStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileAccountStore> {
+        let path = path.as_ref().to_path_buf();
+        let accounts = read_accounts(&path)?;
+
+        // Se compacta al abrir para descartar una posible línea incompleta
+        compact(&path, &accounts)?;
+        let log = Arc::new(OpenOptions::new().append(true).open(&path)?);
+        let bytes = log.metadata()?.len();
+
+        Ok(FileAccountStore {
+            path,
+            state: Mutex::new(FileState {
+                lines: accounts.len(),
+                accounts,
+                log,
+                bytes,
+            }),
+        })
+    }
+}
+
+impl AccountStore for FileAccountStore {
+    fn load(&self, name: &str) -> io::Result<Option<Account>> {
+        let state = self.state.lock().map_err(poisoned)?;
+        Ok(state.accounts.get(name).cloned())
+    }
+
+    fn save(&self, account: &Account) -> io::Result<()> {
+        let mut line = serde_json::to_vec(account)?;
+        line.push(b'\n');
+
+        let log = {
+            let mut state = self.state.lock().map_err(poisoned)?;
+            // Una línea a medias en el medio del archivo impediría abrirlo
+            if let Err(e) = state.log.as_ref().write_all(&line) {
+                state.log.set_len(state.bytes).map_err(|truncate| {
+                    io::Error::other(format!("{} (and could not undo it: {})", e, truncate))
+                })?;
+                return Err(e);
+            }
+            state.bytes += line.len() as u64;
+
+            state.accounts.insert(account.name.clone(), account.clone());
+            state.lines += 1;
+
+            if state.lines > MIN_LINES_TO_COMPACT
+                && state.lines > COMPACTION_FACTOR * state.accounts.len()
+            {
+                // El archivo compactado ya queda sincronizado
+                compact(&self.path, &state.accounts)?;
+                state.log = Arc::new(OpenOptions::new().append(true).open(&self.path)?);
+                state.bytes = state.log.metadata()?.len();
+                state.lines = state.accounts.len();
+                return Ok(());
+            }
+            state.log.clone()
+        };
+        log.sync_data()
+    }
+}
+
+fn read_accounts(path: &Path) -> io::Result<HashMap<String, Account>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+
+    let lines = BufReader::new(file)
+        .lines()
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut accounts = HashMap::new();
+    for (i, line) in lines.iter().enumerate() {
+        match serde_json::from_str::<Account>(line) {
+            Ok(account) => {
+                accounts.insert(account.name.clone(), account);
+            }
+            // Escritura interrumpida
+            Err(_) if i + 1 == lines.len() => {
+                eprintln!("Discarding incomplete account entry in {:?}", path);
+            }
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{:?} line {}: {}", path, i + 1, e),
+                ))
+            }
+        }
+    }
+    Ok(accounts)
+}
+
+fn compact(path: &Path, accounts: &HashMap<String, Account>) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut tmp = File::create(&tmp_path)?;
+    for account in accounts.values() {
+        let mut line = serde_json::to_vec(account)?;
+        line.push(b'\n');
+        tmp.write_all(&line)?;
+    }
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    // Sin sincronizar el directorio el renombre se puede perder si se cae
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+fn poisoned<T>(_: std::sync::PoisonError<T>) -> io::Error {
+    io::Error::other("Account store lock poisoned")
+}
+
+#[cfg(test)]
+mod accounts_tests {
+    use super::*;
+    use common::protocol::Item;
+    use std::env;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("coin_pusher_{}_{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn check_token() {
+        let account = Account::new("ana", "secret", 10);
+
+        assert!(account.check_token("secret"));
+        assert!(!account.check_token("other"));
+    }
+
+    #[test]
+    fn record_play() {
+        let mut account = Account::new("ana", "", 10);
+
+        account.record_play(3, 5);
+        account.record_play(1, 0);
+
+        assert_eq!(account.coins_inserted(), 4);
+        assert_eq!(account.coins_won(), 5);
+    }
+
+    #[test]
+    fn memory_store_round_trip() {
+        let store = MemoryAccountStore::default();
+        let account = Account::new("ana", "", 10);
+
+        store.save(&account).unwrap();
+
+        assert_eq!(store.load("ana").unwrap(), Some(account));
+        assert_eq!(store.load("bob").unwrap(), None);
+    }
+
+    #[test]
+    fn file_store_survives_reopening() {
+        let path = temp_path("reopen");
+        let mut account = Account::new("ana", "secret", 10);
+        {
+            let store = FileAccountStore::open(&path).unwrap();
+            store.save(&account).unwrap();
+            account.wallet_mut().credit(5);
+            account.inventory_mut().add(Item::Badge);
+            store.save(&account).unwrap();
+        }
+
+        let store = FileAccountStore::open(&path).unwrap();
+
+        assert_eq!(store.load("ana").unwrap(), Some(account));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn file_store_discards_incomplete_last_line() {
+        let path = temp_path("torn");
+        let account = Account::new("ana", "secret", 10);
+        {
+            let store = FileAccountStore::open(&path).unwrap();
+            store.save(&account).unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"name\":\"bob\",\"tok").unwrap();
+
+        let store = FileAccountStore::open(&path).unwrap();
+
+        assert_eq!(store.load("ana").unwrap(), Some(account));
+        assert_eq!(store.load("bob").unwrap(), None);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn file_store_compacts_log() {
+        let path = temp_path("compact");
+        let mut account = Account::new("ana", "secret", 0);
+        let store = FileAccountStore::open(&path).unwrap();
+
+        for _ in 0..(2 * MIN_LINES_TO_COMPACT) {
+            account.wallet_mut().credit(1);
+            store.save(&account).unwrap();
+        }
+
+        let lines = fs::read_to_string(&path).unwrap().lines().count();
+        assert!(lines <= MIN_LINES_TO_COMPACT);
+        drop(store);
+        let store = FileAccountStore::open(&path).unwrap();
+        assert_eq!(store.load("ana").unwrap(), Some(account));
+        let _ = fs::remove_file(&path);
+    }
+}
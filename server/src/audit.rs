@@ -0,0 +1,412 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use common::protocol::Item;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// `prev_hash` de la primera entrada del registro.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Una moneda arrojada en una máquina.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoinEvent {
+    /// Milisegundos desde la época Unix.
+    pub timestamp: u64,
+    pub connection: String,
+    pub session: u64,
+    pub player: String,
+    pub machine: u32,
+    pub pool_before: u32,
+    pub pool_after: u32,
+    pub fell: u32,
+    /// Monedas que rebalsaron la máquina hacia el jugador. Solo aparece si hubo.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub spilled: u32,
+    /// Monedas del pozo acumulado. Solo aparece si se ganó el pozo.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub jackpot: u32,
+    /// Premio que cayó del estante, si cayó alguno.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item: Option<Item>,
+    /// Con juego verificable es el nonce de las semillas de la sesión;
+    /// si no, el número de moneda del generador de la máquina.
+    pub nonce: u64,
+    pub fair: bool,
+    /// Número de la moneda en su máquina. Las jugadas se registran después
+    /// de soltar la máquina, así que dos jugadas en la misma máquina pueden
+    /// quedar en el registro en otro orden; este número da el real.
+    #[serde(default)]
+    pub machine_seq: u64,
+}
+
+/// Línea del registro. `hash` cubre el resto de los campos, incluido el
+/// hash de la entrada anterior, así que alterar, quitar o reordenar una
+/// línea rompe la cadena desde ahí en adelante.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub event: CoinEvent,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn new(seq: u64, event: CoinEvent, prev_hash: String) -> io::Result<AuditEntry> {
+        let hash = entry_hash(seq, &event, &prev_hash)?;
+        Ok(AuditEntry {
+            seq,
+            event,
+            prev_hash,
+            hash,
+        })
+    }
+
+    /// Si el hash corresponde al contenido de la entrada.
+    pub fn is_intact(&self) -> bool {
+        entry_hash(self.seq, &self.event, &self.prev_hash).is_ok_and(|hash| hash == self.hash)
+    }
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+fn entry_hash(seq: u64, event: &CoinEvent, prev_hash: &str) -> io::Result<String> {
+    let bytes = serde_json::to_vec(&(seq, event, prev_hash))?;
+    Ok(Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Registro de auditoría en JSON Lines, al que solo se le agregan líneas.
+///
+/// Cuando el archivo pasa de `max_bytes` se renombra a `<path>.1`, `<path>.2`,
+/// etc., y se sigue en uno nuevo. La cadena de hashes continúa entre archivos.
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    state: Mutex<LogState>,
+}
+
+struct LogState {
+    file: File,
+    bytes: u64,
+    rotated: u32,
+    next_seq: u64,
+    last_hash: String,
+}
+
+impl AuditLog {
+    /// Retoma la cadena desde la última entrada que haya en disco.
+    pub fn open<P: AsRef<Path>>(path: P, max_bytes: u64) -> io::Result<AuditLog> {
+        let path = path.as_ref().to_path_buf();
+        let bytes = trim_torn_line(&path)?;
+        let files = files(&path);
+        let rotated = (files.len() - 1) as u32;
+
+        let mut last = None;
+        for file in files.iter().rev() {
+            last = read_file(file)?.pop();
+            if last.is_some() {
+                break;
+            }
+        }
+        let (next_seq, last_hash) = match last {
+            Some(entry) => (entry.seq + 1, entry.hash),
+            None => (0, GENESIS_HASH.to_string()),
+        };
+
+        Ok(AuditLog {
+            state: Mutex::new(LogState {
+                file: append(&path)?,
+                bytes,
+                rotated,
+                next_seq,
+                last_hash,
+            }),
+            path,
+            max_bytes,
+        })
+    }
+
+    /// Agrega las monedas al registro y lo sincroniza a disco. Si falla,
+    /// el archivo vuelve a como estaba: ninguna de las monedas queda
+    /// registrada, ni siquiera a medias.
+    pub fn record(&self, events: impl IntoIterator<Item = CoinEvent>) -> io::Result<()> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| io::Error::other("Audit log lock poisoned"))?;
+        if state.bytes >= self.max_bytes {
+            self.rotate(&mut state)?;
+        }
+
+        let mut lines = Vec::new();
+        let (mut next_seq, mut last_hash) = (state.next_seq, state.last_hash.clone());
+        for event in events {
+            let entry = AuditEntry::new(next_seq, event, last_hash)?;
+            serde_json::to_writer(&mut lines, &entry)?;
+            lines.push(b'\n');
+            next_seq += 1;
+            last_hash = entry.hash;
+        }
+
+        let written = state
+            .file
+            .write_all(&lines)
+            .and_then(|()| state.file.sync_data());
+        if let Err(e) = written {
+            state.file.set_len(state.bytes).map_err(|truncate| {
+                io::Error::other(format!("{} (and could not undo it: {})", e, truncate))
+            })?;
+            return Err(e);
+        }
+        state.bytes += lines.len() as u64;
+        state.next_seq = next_seq;
+        state.last_hash = last_hash;
+        Ok(())
+    }
+
+    fn rotate(&self, state: &mut LogState) -> io::Result<()> {
+        state.file.sync_all()?;
+        fs::rename(&self.path, rotated_path(&self.path, state.rotated + 1))?;
+        state.rotated += 1;
+        state.file = append(&self.path)?;
+        state.bytes = 0;
+        Ok(())
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rotated_path(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Archivos del registro en el orden de la cadena: primero los rotados,
+/// del más viejo al más nuevo, y al final `path`.
+pub fn files<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
+    let path = path.as_ref();
+    let mut files: Vec<PathBuf> = (1..)
+        .map(|n| rotated_path(path, n))
+        .take_while(|rotated| rotated.exists())
+        .collect();
+    files.push(path.to_path_buf());
+    files
+}
+
+/// Todas las entradas del registro, sin revisar la cadena.
+pub fn entries<P: AsRef<Path>>(path: P) -> io::Result<Vec<AuditEntry>> {
+    let mut entries = Vec::new();
+    for file in files(path) {
+        entries.extend(read_file(&file)?);
+    }
+    Ok(entries)
+}
+
+/// Revisa que cada entrada esté intacta y encadenada a la anterior.
+/// Devuelve cuántas entradas tiene el registro.
+pub fn verify<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut count = 0;
+    for entry in entries(path)? {
+        if entry.seq != count || entry.prev_hash != prev_hash {
+            return Err(invalid(format!("Entry {} is out of the chain", entry.seq)));
+        }
+        if !entry.is_intact() {
+            return Err(invalid(format!("Entry {} was altered", entry.seq)));
+        }
+        prev_hash = entry.hash;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_file(path: &Path) -> io::Result<Vec<AuditEntry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    BufReader::new(file)
+        .lines()
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+/// Si el servidor se cayó a mitad de una escritura, la última línea quedó
+/// incompleta. Esa moneda nunca se confirmó, así que se descarta.
+/// Devuelve el largo del archivo.
+fn trim_torn_line(path: &Path) -> io::Result<u64> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let complete = bytes
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .map_or(0, |last| last + 1);
+    if complete < bytes.len() {
+        eprintln!(
+            "Discarding incomplete line at the end of audit log {}",
+            path.display()
+        );
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(complete as u64)?;
+    }
+    Ok(complete as u64)
+}
+
+#[cfg(test)]
+mod audit_tests {
+    use super::*;
+    use std::env;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!(
+            "coin_pusher_audit_{}_{}.jsonl",
+            name,
+            std::process::id()
+        ));
+        remove(&path);
+        path
+    }
+
+    fn remove(path: &Path) {
+        for file in files(path) {
+            let _ = fs::remove_file(file);
+        }
+    }
+
+    fn event(pool_before: u32, fell: u32) -> CoinEvent {
+        CoinEvent {
+            timestamp: now_millis(),
+            connection: "127.0.0.1:4000".to_string(),
+            session: 1,
+            player: "ana".to_string(),
+            machine: 1,
+            pool_before,
+            pool_after: pool_before + 1 - fell,
+            fell,
+            spilled: 0,
+            jackpot: 0,
+            item: None,
+            nonce: u64::from(pool_before),
+            fair: false,
+            machine_seq: u64::from(pool_before),
+        }
+    }
+
+    #[test]
+    fn entries_are_chained() {
+        let path = temp_path("chained");
+        let log = AuditLog::open(&path, 1 << 20).unwrap();
+
+        log.record([event(10, 0), event(11, 3)]).unwrap();
+        log.record([event(9, 0)]).unwrap();
+
+        let entries = entries(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].prev_hash, GENESIS_HASH);
+        assert_eq!(entries[2].prev_hash, entries[1].hash);
+        assert_eq!(entries[1].seq, 1);
+        assert_eq!(entries[1].event.fell, 3);
+        assert_eq!(entries[1].event.pool_after, 9);
+        assert_eq!(verify(&path).unwrap(), 3);
+        remove(&path);
+    }
+
+    #[test]
+    fn tampering_breaks_the_chain() {
+        let path = temp_path("tampered");
+        let log = AuditLog::open(&path, 1 << 20).unwrap();
+        log.record((0..5).map(|i| event(i, 0))).unwrap();
+        let original = fs::read_to_string(&path).unwrap();
+
+        fs::write(&path, original.replacen("\"fell\":0", "\"fell\":1", 1)).unwrap();
+        assert!(verify(&path).is_err());
+
+        let mut lines: Vec<&str> = original.lines().collect();
+        lines.remove(2);
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        assert!(verify(&path).is_err());
+        remove(&path);
+    }
+
+    #[test]
+    fn rotation_keeps_the_chain() {
+        let path = temp_path("rotated");
+        let log = AuditLog::open(&path, 600).unwrap();
+
+        for i in 0..10 {
+            log.record([event(i, 0)]).unwrap();
+        }
+
+        assert!(files(&path).len() > 2);
+        assert_eq!(verify(&path).unwrap(), 10);
+        remove(&path);
+    }
+
+    #[test]
+    fn a_failed_write_is_not_chained() {
+        let path = temp_path("failed");
+        let log = AuditLog::open(&path, 1 << 20).unwrap();
+        log.record([event(0, 0)]).unwrap();
+
+        // Un archivo abierto solo para leer no acepta escrituras
+        let append = std::mem::replace(
+            &mut log.state.lock().unwrap().file,
+            File::open(&path).unwrap(),
+        );
+        assert!(log.record([event(1, 0), event(2, 0)]).is_err());
+        log.state.lock().unwrap().file = append;
+        log.record([event(1, 0)]).unwrap();
+
+        assert_eq!(verify(&path).unwrap(), 2);
+        remove(&path);
+    }
+
+    #[test]
+    fn reopen_continues_the_chain() {
+        let path = temp_path("reopened");
+        AuditLog::open(&path, 600)
+            .unwrap()
+            .record((0..4).map(|i| event(i, 0)))
+            .unwrap();
+        // Una línea a medio escribir cuando se cayó el servidor
+        let mut file = append(&path).unwrap();
+        file.write_all(b"{\"seq\":4,\"times").unwrap();
+
+        let log = AuditLog::open(&path, 600).unwrap();
+        log.record([event(4, 0), event(5, 0)]).unwrap();
+
+        assert_eq!(verify(&path).unwrap(), 6);
+        remove(&path);
+    }
+}
@@ -0,0 +1,218 @@
+use std::env;
+use std::process;
+
+use server::audit;
+use server::config::FileConfig;
+use server::jackpot::JACKPOT_SCALE;
+use server::replay::{Comparison, Discrepancy, Replay};
+use server::server::traits::Config;
+use server::snapshot::{self, Snapshot};
+
+/// Argumentos de la reconstrucción.
+struct ReplayConfig {
+    config_path: String,
+    log_path: Option<String>,
+    until: Option<u64>,
+    from: Option<String>,
+    snapshot: Option<String>,
+}
+
+impl ReplayConfig {
+    /// Se asume que el primer argumento es el path del ejecutable, seguido del
+    /// archivo de configuración del servidor y opcionalmente `--log <path>`,
+    /// `--until <ms>`, `--from <snapshot>` y `--snapshot <snapshot>`.
+    fn build(mut args: impl Iterator<Item = String>) -> Result<ReplayConfig, &'static str> {
+        // skip first arg
+        args.next();
+
+        let config_path = match args.next() {
+            Some(arg) => arg,
+            None => return Err("Could not get the config file path"),
+        };
+
+        let mut config = ReplayConfig {
+            config_path,
+            log_path: None,
+            until: None,
+            from: None,
+            snapshot: None,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--log" => config.log_path = Some(args.next().ok_or("Missing log path")?),
+                "--until" => {
+                    let until = args.next().and_then(|arg| arg.parse().ok());
+                    config.until = Some(until.ok_or("Invalid timestamp")?)
+                }
+                "--from" => config.from = Some(args.next().ok_or("Missing snapshot path")?),
+                "--snapshot" => config.snapshot = Some(args.next().ok_or("Missing snapshot path")?),
+                _ => return Err("Unknown argument"),
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn main() {
+    let args = ReplayConfig::build(env::args()).unwrap_or_else(|err| {
+        eprintln!("Error while reading arguments: {err}");
+        eprintln!(
+            "Usage: replay <config> [--log <path>] [--until <ms>] [--from <snapshot>] [--snapshot <snapshot>]"
+        );
+        process::exit(1);
+    });
+
+    let config = FileConfig::new(&args.config_path).unwrap_or_else(|err| {
+        eprintln!("Error while reading config file: {err}");
+        process::exit(1);
+    });
+    let log_path = match args.log_path.as_deref().or(config.audit_path()) {
+        Some(path) => path.to_string(),
+        None => {
+            eprintln!("The config file has no audit_path, use --log");
+            process::exit(1);
+        }
+    };
+
+    // Si la cadena está rota no tiene sentido reconstruir nada
+    let count = audit::verify(&log_path).unwrap_or_else(|e| {
+        eprintln!("Audit log {log_path} is not trustworthy: {e}");
+        process::exit(1);
+    });
+    println!("Audit log {log_path}: {count} entries, hash chain intact");
+
+    let base = args.from.as_deref().map(load_snapshot);
+    let mut replay = Replay::new(config.machines(), base.as_ref()).unwrap_or_else(|err| {
+        eprintln!("Error while creating machines: {err}");
+        process::exit(1);
+    });
+    let entries = audit::entries(&log_path).unwrap_or_else(|e| {
+        eprintln!("Error while reading audit log: {e}");
+        process::exit(1);
+    });
+
+    let discrepancies = replay.apply_all(&entries, args.until);
+    for discrepancy in &discrepancies {
+        print_discrepancy(discrepancy);
+    }
+
+    for (id, machine) in replay.machines() {
+        println!("Machine {id}");
+        println!("  pool:             {}", machine.pool());
+        println!("  coins inserted:   {}", machine.inserted());
+        println!("  coins paid:       {}", machine.paid());
+        if let Some(jackpot) = machine.jackpot() {
+            println!("  jackpot:          {}", coins(jackpot));
+        }
+        match machine.last_timestamp() {
+            Some(timestamp) => println!("  last coin at:     {timestamp}"),
+            None => println!("  last coin at:     never"),
+        }
+    }
+
+    let mut mismatches = 0;
+    if let Some(path) = args.snapshot.as_deref() {
+        for comparison in replay.compare(&load_snapshot(path)) {
+            print_comparison(&comparison);
+            if !comparison.matches() {
+                mismatches += 1;
+            }
+        }
+    }
+
+    if !discrepancies.is_empty() || mismatches > 0 {
+        process::exit(1);
+    }
+}
+
+fn load_snapshot(path: &str) -> Snapshot {
+    match snapshot::load(path) {
+        Ok(Some(snapshot)) => snapshot,
+        Ok(None) => {
+            eprintln!("Snapshot {path} does not exist");
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error while reading snapshot {path}: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Milésimos de moneda, como monedas con tres decimales.
+fn coins(value: u64) -> String {
+    format!(
+        "{}.{:03} coins",
+        value / JACKPOT_SCALE,
+        value % JACKPOT_SCALE
+    )
+}
+
+fn print_comparison(comparison: &Comparison) {
+    let machine = comparison.machine;
+    let Some(stored) = comparison.stored else {
+        println!("Machine {machine}: missing from the snapshot");
+        return;
+    };
+    if comparison.matches() {
+        println!("Machine {machine}: matches the snapshot");
+        return;
+    }
+
+    let replayed = comparison.replayed;
+    if replayed.pool != stored.pool {
+        println!(
+            "Machine {machine}: replayed pool {} but the snapshot has {}",
+            replayed.pool, stored.pool
+        );
+    }
+    if replayed.board != stored.board {
+        let describe = |board: Option<u32>| match board {
+            Some(coins) => format!("{coins} coins on the board"),
+            None => "no board".to_string(),
+        };
+        println!(
+            "Machine {machine}: replayed {} but the snapshot has {}",
+            describe(replayed.board),
+            describe(stored.board)
+        );
+    }
+    if replayed.jackpot != stored.jackpot {
+        let describe = |jackpot: Option<u64>| match jackpot {
+            Some(value) => format!("a jackpot of {}", coins(value)),
+            None => "no jackpot".to_string(),
+        };
+        println!(
+            "Machine {machine}: replayed {} but the snapshot has {}",
+            describe(replayed.jackpot),
+            describe(stored.jackpot)
+        );
+    }
+}
+
+fn print_discrepancy(discrepancy: &Discrepancy) {
+    match discrepancy {
+        Discrepancy::Gap {
+            seq,
+            machine,
+            expected,
+            found,
+        } => println!(
+            "Entry {seq}: machine {machine} should have had {expected} coins but had {found}"
+        ),
+        Discrepancy::Inconsistent { seq, machine } => {
+            println!("Entry {seq}: machine {machine} gained coins out of nowhere")
+        }
+        Discrepancy::Jackpot {
+            seq,
+            machine,
+            available,
+            won,
+        } => println!(
+            "Entry {seq}: machine {machine} paid a jackpot of {won} coins but only had {available}"
+        ),
+        Discrepancy::UnknownMachine { seq, machine } => {
+            println!("Entry {seq}: machine {machine} is not in the config file")
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::env;
+use std::process;
+
+use server::config::{FileConfig, MachineConfig};
+use server::server::traits::Config;
+use server::simulation::{simulate_seeds, Report};
+
+const DEFAULT_COINS: u64 = 1_000_000;
+const DEFAULT_SEEDS: u64 = 4;
+
+/// Argumentos de la simulación.
+struct SimulationConfig {
+    config_path: String,
+    machine: Option<u32>,
+    coins: u64,
+    seeds: u64,
+    csv: bool,
+}
+
+impl SimulationConfig {
+    /// Se asume que el primer argumento es el path del ejecutable, seguido del
+    /// archivo de configuración del servidor y opcionalmente
+    /// `--machine <id>`, `--coins <n>`, `--seeds <n>` y `--csv`.
+    fn build(mut args: impl Iterator<Item = String>) -> Result<SimulationConfig, &'static str> {
+        // skip first arg
+        args.next();
+
+        let config_path = match args.next() {
+            Some(arg) => arg,
+            None => return Err("Could not get the config file path"),
+        };
+
+        let mut config = SimulationConfig {
+            config_path,
+            machine: None,
+            coins: DEFAULT_COINS,
+            seeds: DEFAULT_SEEDS,
+            csv: false,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--machine" => {
+                    config.machine = Some(parse_number(args.next(), "Invalid machine id")?)
+                }
+                "--coins" => config.coins = parse_number(args.next(), "Invalid coin count")?,
+                "--seeds" => config.seeds = parse_number(args.next(), "Invalid seed count")?,
+                "--csv" => config.csv = true,
+                _ => return Err("Unknown argument"),
+            }
+        }
+        if config.seeds == 0 {
+            return Err("At least one seed is required");
+        }
+        Ok(config)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(
+    arg: Option<String>,
+    err: &'static str,
+) -> Result<T, &'static str> {
+    arg.and_then(|arg| arg.parse().ok()).ok_or(err)
+}
+
+fn main() {
+    let args = SimulationConfig::build(env::args()).unwrap_or_else(|err| {
+        eprintln!("Error while reading arguments: {err}");
+        eprintln!("Usage: simulate <config> [--machine <id>] [--coins <n>] [--seeds <n>] [--csv]");
+        process::exit(1);
+    });
+
+    let config = FileConfig::new(&args.config_path).unwrap_or_else(|err| {
+        eprintln!("Error while reading config file: {err}");
+        process::exit(1);
+    });
+
+    let machines: Vec<&MachineConfig> = config
+        .machines()
+        .iter()
+        .filter(|machine| args.machine.is_none_or(|id| machine.id() == id))
+        .collect();
+    if machines.is_empty() {
+        eprintln!("Unknown machine");
+        process::exit(1);
+    }
+
+    if args.csv {
+        println!("machine,section,key,value");
+    }
+    for machine in machines {
+        // Semillas consecutivas desde la de la máquina, así la corrida se repite
+        let first_seed = machine.seed().unwrap_or(0);
+        let seeds = (0..args.seeds).map(|i| first_seed.wrapping_add(i));
+        let report = simulate_seeds(machine, seeds, args.coins).unwrap_or_else(|err| {
+            eprintln!("Error while simulating machine {}: {err}", machine.id());
+            process::exit(1);
+        });
+
+        if args.csv {
+            print_csv(machine.id(), &report);
+        } else {
+            print_text(machine.id(), &report);
+        }
+    }
+}
+
+fn print_text(id: u32, report: &Report) {
+    println!("Machine {id}");
+    println!("  coins inserted:   {}", report.inserted());
+    println!("  coins paid:       {}", report.paid());
+    if report.jackpots() > 0 {
+        println!(
+            "  jackpots:         {} ({} coins)",
+            report.jackpots(),
+            report.jackpot_paid()
+        );
+    }
+    if report.spilled() > 0 {
+        println!("  coins spilled:    {}", report.spilled());
+    }
+    println!("  RTP:              {:.4}", report.rtp());
+    println!("  pool mean:        {:.2}", report.pool().mean());
+    println!("  pool variance:    {:.2}", report.pool().variance());
+    println!("  time to first payout (inserts):");
+    for (i, first) in report.first_payouts().iter().enumerate() {
+        match first {
+            Some(inserts) => println!("    run {}: {}", i + 1, inserts),
+            None => println!("    run {}: never", i + 1),
+        }
+    }
+    println!("  payout histogram (coins: times):");
+    for (fell, count) in report.histogram() {
+        println!("    {fell}: {count}");
+    }
+    println!();
+}
+
+fn print_csv(id: u32, report: &Report) {
+    println!("{id},summary,inserted,{}", report.inserted());
+    println!("{id},summary,paid,{}", report.paid());
+    println!("{id},summary,jackpots,{}", report.jackpots());
+    println!("{id},summary,jackpot_paid,{}", report.jackpot_paid());
+    println!("{id},summary,spilled,{}", report.spilled());
+    println!("{id},summary,rtp,{}", report.rtp());
+    println!("{id},summary,pool_mean,{}", report.pool().mean());
+    println!("{id},summary,pool_variance,{}", report.pool().variance());
+    for (i, first) in report.first_payouts().iter().enumerate() {
+        let value = first.map_or(String::new(), |inserts| inserts.to_string());
+        println!("{id},first_payout,{},{value}", i + 1);
+    }
+    for (fell, count) in report.histogram() {
+        println!("{id},histogram,{fell},{count}");
+    }
+}
@@ -0,0 +1,340 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_WIDTH: u32 = 7;
+pub const DEFAULT_DEPTH: u32 = 5;
+pub const DEFAULT_CELL_CAPACITY: u32 = 20;
+pub const DEFAULT_PUSH_PERIOD: u32 = 4;
+
+/// Dimensiones del estante y cada cuántas monedas pasa el empujador.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoardConfig {
+    width: u32,
+    depth: u32,
+    cell_capacity: u32,
+    push_period: u32,
+}
+
+impl BoardConfig {
+    pub fn new(
+        width: u32,
+        depth: u32,
+        cell_capacity: u32,
+        push_period: u32,
+    ) -> Result<BoardConfig, &'static str> {
+        if width == 0 || depth == 0 {
+            return Err("the board needs at least one cell");
+        }
+        if cell_capacity == 0 {
+            return Err("cell_capacity must be positive");
+        }
+        if push_period == 0 {
+            return Err("push_period must be positive");
+        }
+
+        Ok(BoardConfig {
+            width,
+            depth,
+            cell_capacity,
+            push_period,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    pub fn cell_capacity(&self) -> u32 {
+        self.cell_capacity
+    }
+
+    pub fn push_period(&self) -> u32 {
+        self.push_period
+    }
+
+    /// Máximo de monedas que entran en el estante sin que se caiga ninguna.
+    pub fn capacity(&self) -> u32 {
+        self.width * self.depth * self.cell_capacity
+    }
+}
+
+impl Default for BoardConfig {
+    fn default() -> Self {
+        BoardConfig {
+            width: DEFAULT_WIDTH,
+            depth: DEFAULT_DEPTH,
+            cell_capacity: DEFAULT_CELL_CAPACITY,
+            push_period: DEFAULT_PUSH_PERIOD,
+        }
+    }
+}
+
+/// Estado del estante que se guarda entre reinicios.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BoardSnapshot {
+    cells: Vec<u32>,
+    until_push: u32,
+}
+
+impl BoardSnapshot {
+    pub fn coins(&self) -> u32 {
+        self.cells.iter().sum()
+    }
+}
+
+/// Dónde cayó una moneda arrojada en un estante y cuántas hizo caer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoinDrop {
+    pub lane: u32,
+    pub fell: u32,
+}
+
+/// Estante de `width` carriles por `depth` filas. La fila 0 es la del
+/// fondo, contra el empujador, y la última da al borde por el que caen
+/// las monedas que gana el jugador.
+///
+/// Cuando una celda supera `cell_capacity`, las monedas que sobran pasan
+/// a la fila siguiente, en el mismo carril o en uno vecino. Las que se
+/// salen por los costados se las queda la casa.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Board {
+    config: BoardConfig,
+    cells: Vec<u32>,
+    until_push: u32,
+}
+
+impl Board {
+    /// Reparte `coins` de manera pareja entre las celdas.
+    pub fn with(config: BoardConfig, coins: u32) -> Result<Board, &'static str> {
+        if coins > config.capacity() {
+            return Err("too many coins for the board");
+        }
+
+        let len = config.width * config.depth;
+        let cells = (0..len)
+            .map(|i| coins / len + u32::from(i < coins % len))
+            .collect();
+        Ok(Board {
+            config,
+            cells,
+            until_push: config.push_period,
+        })
+    }
+
+    /// El snapshot tiene que ser de un estante con las mismas dimensiones.
+    pub fn restore(config: BoardConfig, snapshot: &BoardSnapshot) -> Result<Board, &'static str> {
+        if snapshot.cells.len() != (config.width * config.depth) as usize {
+            return Err("the snapshot does not match the board size");
+        }
+        if snapshot
+            .cells
+            .iter()
+            .any(|coins| *coins > config.cell_capacity)
+        {
+            return Err("too many coins in a cell");
+        }
+
+        Ok(Board {
+            config,
+            cells: snapshot.cells.clone(),
+            until_push: snapshot.until_push.clamp(1, config.push_period),
+        })
+    }
+
+    pub fn snapshot(&self) -> BoardSnapshot {
+        BoardSnapshot {
+            cells: self.cells.clone(),
+            until_push: self.until_push,
+        }
+    }
+
+    pub fn config(&self) -> &BoardConfig {
+        &self.config
+    }
+
+    /// Monedas en cada celda, fila por fila desde el fondo.
+    pub fn cells(&self) -> &[u32] {
+        &self.cells
+    }
+
+    pub fn coins(&self) -> u32 {
+        self.cells.iter().sum()
+    }
+
+    /// Arroja una moneda sobre el carril `lane`; si no existe, cae en el último.
+    /// Puede rebotar a un carril vecino. Cada `push_period` monedas el
+    /// empujador barre la fila del fondo hacia adelante.
+    pub fn drop_coin(&mut self, lane: u32, rng: &mut impl Rng) -> CoinDrop {
+        let width = self.config.width as i64;
+        let lane = (i64::from(lane.min(self.config.width - 1)) + bounce(rng)).clamp(0, width - 1);
+        let lane = lane as u32;
+        self.cells[lane as usize] += 1;
+
+        self.until_push -= 1;
+        if self.until_push == 0 {
+            self.until_push = self.config.push_period;
+            self.push();
+        }
+
+        CoinDrop {
+            lane,
+            fell: self.settle(rng),
+        }
+    }
+
+    fn push(&mut self) {
+        if self.config.depth < 2 {
+            return;
+        }
+        let width = self.config.width as usize;
+        for lane in 0..width {
+            self.cells[width + lane] += self.cells[lane];
+            self.cells[lane] = 0;
+        }
+    }
+
+    /// Desborda las celdas llenas hacia el borde y devuelve
+    /// cuántas monedas cayeron por adelante.
+    fn settle(&mut self, rng: &mut impl Rng) -> u32 {
+        let width = self.config.width as i64;
+        let depth = self.config.depth as usize;
+        let mut fell = 0;
+        for row in 0..depth {
+            for lane in 0..width {
+                let i = row * width as usize + lane as usize;
+                let excess = self.cells[i].saturating_sub(self.config.cell_capacity);
+                self.cells[i] -= excess;
+                if row + 1 == depth {
+                    fell += excess;
+                    continue;
+                }
+                for _ in 0..excess {
+                    let to = lane + bounce(rng);
+                    if (0..width).contains(&to) {
+                        self.cells[(row + 1) * width as usize + to as usize] += 1;
+                    }
+                }
+            }
+        }
+        fell
+    }
+}
+
+/// -1, 0 o +1 carriles, con el centro el doble de probable.
+fn bounce(rng: &mut impl Rng) -> i64 {
+    match rng.gen_range(0..4) {
+        0 => -1,
+        3 => 1,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod board_tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    fn rng() -> ChaCha8Rng {
+        ChaCha8Rng::seed_from_u64(0)
+    }
+
+    #[test]
+    fn coins_are_spread_evenly() {
+        let board = Board::with(BoardConfig::new(3, 2, 10, 1).unwrap(), 8).unwrap();
+
+        assert_eq!(board.cells(), [2, 2, 1, 1, 1, 1]);
+        assert_eq!(board.coins(), 8);
+    }
+
+    #[test]
+    fn too_many_coins() {
+        let config = BoardConfig::new(3, 2, 10, 1).unwrap();
+
+        assert!(Board::with(config, 60).is_ok());
+        assert!(Board::with(config, 61).is_err());
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(BoardConfig::new(0, 2, 10, 1).is_err());
+        assert!(BoardConfig::new(3, 2, 0, 1).is_err());
+        assert!(BoardConfig::new(3, 2, 10, 0).is_err());
+    }
+
+    #[test]
+    fn coin_lands_near_its_lane() {
+        let mut board = Board::with(BoardConfig::new(5, 3, 10, 100).unwrap(), 0).unwrap();
+        let mut rng = rng();
+
+        for _ in 0..20 {
+            let drop = board.drop_coin(2, &mut rng);
+            assert!((1..=3).contains(&drop.lane));
+            assert_eq!(drop.fell, 0);
+        }
+        assert_eq!(board.coins(), 20);
+    }
+
+    #[test]
+    fn lane_past_the_edge_lands_on_the_last_lanes() {
+        let mut board = Board::with(BoardConfig::new(5, 3, 10, 100).unwrap(), 0).unwrap();
+
+        let drop = board.drop_coin(99, &mut rng());
+
+        assert!(drop.lane >= 3);
+    }
+
+    #[test]
+    fn pusher_moves_the_back_row_forward() {
+        let mut board = Board::with(BoardConfig::new(1, 3, 10, 2).unwrap(), 0).unwrap();
+        let mut rng = rng();
+
+        board.drop_coin(0, &mut rng);
+        assert_eq!(board.cells(), [1, 0, 0]);
+        board.drop_coin(0, &mut rng);
+        assert_eq!(board.cells(), [0, 2, 0]);
+    }
+
+    #[test]
+    fn full_edge_pays() {
+        // Un solo carril: nada se pierde por los costados
+        let mut board = Board::with(BoardConfig::new(1, 2, 3, 1).unwrap(), 6).unwrap();
+
+        // [3, 3] + 1 -> el empujador deja [0, 7] y caen las 4 que sobran
+        let drop = board.drop_coin(0, &mut rng());
+
+        assert_eq!(drop.fell, 4);
+        assert_eq!(board.cells(), [0, 3]);
+    }
+
+    #[test]
+    fn coins_are_never_created() {
+        let mut board = Board::with(BoardConfig::default(), 500).unwrap();
+        let mut rng = rng();
+        let mut paid = 0;
+
+        for i in 0..5000 {
+            paid += board.drop_coin(i % 7, &mut rng).fell;
+        }
+
+        assert!(board.coins() + paid <= 500 + 5000);
+        assert!(board.coins() <= board.config().capacity());
+    }
+
+    #[test]
+    fn restore_from_snapshot() {
+        let config = BoardConfig::new(3, 2, 10, 3).unwrap();
+        let mut board = Board::with(config, 20).unwrap();
+        board.drop_coin(1, &mut rng());
+
+        let restored = Board::restore(config, &board.snapshot()).unwrap();
+
+        assert_eq!(restored, board);
+        let other = BoardConfig::new(2, 2, 10, 3).unwrap();
+        assert!(Board::restore(other, &board.snapshot()).is_err());
+    }
+}
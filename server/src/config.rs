@@ -1,49 +1,746 @@
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
     fs::File,
     io::{BufRead, BufReader, Read},
+    str::FromStr,
+    time::Duration,
 };
 
+use common::payout::{
+    FixedRtp, Logistic, PayoutConfig, PiecewiseLinear, Step, DEFAULT_PAYOUT_FRACTION,
+    DEFAULT_STEEPNESS, DEFAULT_THRESHOLD,
+};
+
+use crate::board::{
+    BoardConfig, DEFAULT_CELL_CAPACITY, DEFAULT_DEPTH, DEFAULT_PUSH_PERIOD, DEFAULT_WIDTH,
+};
+use crate::items::ItemsConfig;
+use crate::jackpot::{JackpotConfig, DEFAULT_JACKPOT_PROBABILITY};
+use crate::machine::{Overflow, DEFAULT_CAPACITY};
 use crate::server::traits::Config;
 
 #[derive(Debug, Clone)]
 pub struct FileConfig {
     port: u16,
     host: String,
+    max_batch_size: u32,
+    initial_balance: u32,
+    accounts_path: Option<String>,
+    snapshot_path: Option<String>,
+    snapshot_interval: Duration,
+    resume_from_snapshot: bool,
+    audit_path: Option<String>,
+    audit_max_bytes: u64,
+    worker_threads: usize,
+    max_queued_requests: usize,
+    shutdown_grace: Duration,
+    machines: Vec<MachineConfig>,
+}
+
+/// Parámetros propios de cada máquina.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineConfig {
+    id: u32,
     initial_coins_count: u32,
+    max_players: u32,
+    capacity: u32,
+    overflow: Overflow,
+    payout: PayoutConfig,
+    board: Option<BoardConfig>,
+    jackpot: Option<JackpotConfig>,
+    items: Option<ItemsConfig>,
+    seed: Option<u64>,
+}
+
+impl MachineConfig {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn initial_coins_count(&self) -> u32 {
+        self.initial_coins_count
+    }
+
+    pub fn max_players(&self) -> u32 {
+        self.max_players
+    }
+
+    /// Monedas que entran en la máquina.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    pub fn payout(&self) -> &PayoutConfig {
+        &self.payout
+    }
+
+    /// Estante de la máquina. Sin estante, el pago lo decide `payout`.
+    pub fn board(&self) -> Option<&BoardConfig> {
+        self.board.as_ref()
+    }
+
+    /// Pozo acumulado de la máquina, si tiene.
+    pub fn jackpot(&self) -> Option<&JackpotConfig> {
+        self.jackpot.as_ref()
+    }
+
+    /// Premios que pueden aparecer en el estante, si hay alguno.
+    pub fn items(&self) -> Option<&ItemsConfig> {
+        self.items.as_ref()
+    }
+
+    /// Semilla del generador aleatorio. Sin semilla cada corrida es distinta.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Revisa que los parámetros, válidos cada uno por separado, tengan
+    /// sentido juntos con la capacidad de la máquina.
+    pub fn check(&self) -> Result<(), String> {
+        if self.initial_coins_count > self.capacity {
+            return Err(format!(
+                "initial_coins_count ({}) is above capacity ({})",
+                self.initial_coins_count, self.capacity
+            ));
+        }
+        if let Some(board) = &self.board {
+            if board.capacity() > self.capacity {
+                return Err(format!(
+                    "the board holds {} coins but capacity is {}",
+                    board.capacity(),
+                    self.capacity
+                ));
+            }
+            return Ok(());
+        }
+        if let Some(threshold) = self.payout.threshold() {
+            if threshold > f64::from(self.capacity) {
+                return Err(format!(
+                    "threshold ({}) is above capacity ({}): the machine would never pay",
+                    threshold, self.capacity
+                ));
+            }
+        }
+        if self.overflow == Overflow::Reject
+            && self.payout.build().probability(self.capacity) == 0.0
+        {
+            return Err(format!(
+                "the payout model never pays with {} coins: with overflow={} the machine would fill up and never empty",
+                self.capacity, REJECT_OVERFLOW
+            ));
+        }
+        Ok(())
+    }
+
+    /// Estos parámetros con los que solo se aplican al crear la máquina
+    /// tomados de `current`, la configuración con la que está corriendo.
+    pub fn live(&self, current: &MachineConfig) -> MachineConfig {
+        MachineConfig {
+            initial_coins_count: current.initial_coins_count,
+            board: current.board,
+            seed: current.seed,
+            ..self.clone()
+        }
+    }
+
+    /// Claves que cambian respecto de `current` pero que solo se aplican
+    /// al crear la máquina.
+    fn restart_keys(&self, current: &MachineConfig) -> Vec<&'static str> {
+        let mut keys = Vec::new();
+        if self.initial_coins_count != current.initial_coins_count {
+            keys.push(COINS_KEY);
+        }
+        match (&self.board, &current.board) {
+            (Some(board), Some(current)) => {
+                let changed = [
+                    (WIDTH_KEY, board.width() != current.width()),
+                    (DEPTH_KEY, board.depth() != current.depth()),
+                    (
+                        CELL_CAPACITY_KEY,
+                        board.cell_capacity() != current.cell_capacity(),
+                    ),
+                    (
+                        PUSH_PERIOD_KEY,
+                        board.push_period() != current.push_period(),
+                    ),
+                ];
+                keys.extend(
+                    changed
+                        .iter()
+                        .filter(|(_, changed)| *changed)
+                        .map(|(key, _)| *key),
+                );
+            }
+            (None, None) => (),
+            _ => keys.push(KIND_KEY),
+        }
+        if self.seed != current.seed {
+            keys.push(SEED_KEY);
+        }
+        keys
+    }
+
+    /// Parámetros que cambian respecto de `current` y se aplican en vivo.
+    /// Los del modelo de pago, el pozo y los premios se agrupan.
+    fn live_keys(&self, current: &MachineConfig) -> Vec<&'static str> {
+        let changed = [
+            (MAX_PLAYERS_KEY, self.max_players != current.max_players),
+            (CAPACITY_KEY, self.capacity != current.capacity),
+            (OVERFLOW_KEY, self.overflow != current.overflow),
+            (PAYOUT_GROUP, self.payout != current.payout),
+            (JACKPOT_GROUP, self.jackpot != current.jackpot),
+            (ITEMS_GROUP, self.items != current.items),
+        ];
+        changed
+            .iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(key, _)| *key)
+            .collect()
+    }
+}
+
+/// Lo que cambió al recargar la configuración.
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    /// Claves que se aplicaron sin reiniciar.
+    pub applied: Vec<String>,
+    /// Claves que recién se aplican al reiniciar el servidor.
+    pub restart: Vec<String>,
+}
+
+/// Claves de `new` que cambian respecto de `current` pero que solo se
+/// aplican al arrancar el servidor. Las de cada máquina van con su prefijo.
+pub fn restart_keys(current: &impl Config, new: &impl Config) -> Vec<String> {
+    let changed = [
+        (HOST_KEY, current.host() != new.host()),
+        (PORT_KEY, current.port() != new.port()),
+        (ACCOUNTS_KEY, current.accounts_path() != new.accounts_path()),
+        (SNAPSHOT_KEY, current.snapshot_path() != new.snapshot_path()),
+        (
+            SNAPSHOT_INTERVAL_KEY,
+            current.snapshot_interval() != new.snapshot_interval(),
+        ),
+        (
+            RESUME_KEY,
+            current.resume_from_snapshot() != new.resume_from_snapshot(),
+        ),
+        (AUDIT_KEY, current.audit_path() != new.audit_path()),
+        (
+            AUDIT_MAX_BYTES_KEY,
+            current.audit_max_bytes() != new.audit_max_bytes(),
+        ),
+        (
+            WORKER_THREADS_KEY,
+            current.worker_threads() != new.worker_threads(),
+        ),
+    ];
+    let mut keys: Vec<String> = changed
+        .iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(key, _)| key.to_string())
+        .collect();
+
+    if machine_ids(current) != machine_ids(new) {
+        keys.push(MACHINES_KEY.to_string());
+    }
+    for machine in new.machines() {
+        if let Some(running) = current.machines().iter().find(|m| m.id() == machine.id()) {
+            keys.extend(
+                machine
+                    .restart_keys(running)
+                    .into_iter()
+                    .map(|key| format!("{}.{}.{}", MACHINE_PREFIX, machine.id(), key)),
+            );
+        }
+    }
+    keys
+}
+
+/// Claves de `new` que cambian respecto de `current` y se aplican sin
+/// reiniciar el servidor. Las de cada máquina van con su prefijo.
+pub fn live_keys(current: &impl Config, new: &impl Config) -> Vec<String> {
+    let changed = [
+        (
+            MAX_BATCH_KEY,
+            current.max_batch_size() != new.max_batch_size(),
+        ),
+        (
+            BALANCE_KEY,
+            current.initial_balance() != new.initial_balance(),
+        ),
+        (
+            MAX_QUEUED_KEY,
+            current.max_queued_requests() != new.max_queued_requests(),
+        ),
+        (
+            SHUTDOWN_GRACE_KEY,
+            current.shutdown_grace() != new.shutdown_grace(),
+        ),
+    ];
+    let mut keys: Vec<String> = changed
+        .iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(key, _)| key.to_string())
+        .collect();
+
+    for machine in new.machines() {
+        if let Some(running) = current.machines().iter().find(|m| m.id() == machine.id()) {
+            keys.extend(
+                machine
+                    .live_keys(running)
+                    .into_iter()
+                    .map(|key| format!("{}.{}.{}", MACHINE_PREFIX, machine.id(), key)),
+            );
+        }
+    }
+    keys
 }
 
 const PORT_KEY: &str = "port";
 const HOST_KEY: &str = "host";
 const COINS_KEY: &str = "initial_coins_count";
+const MAX_BATCH_KEY: &str = "max_batch_size";
+const BALANCE_KEY: &str = "initial_balance";
+const ACCOUNTS_KEY: &str = "accounts_path";
+const SNAPSHOT_KEY: &str = "snapshot_path";
+const SNAPSHOT_INTERVAL_KEY: &str = "snapshot_interval_secs";
+const RESUME_KEY: &str = "resume_from_snapshot";
+const AUDIT_KEY: &str = "audit_path";
+const AUDIT_MAX_BYTES_KEY: &str = "audit_max_bytes";
+const WORKER_THREADS_KEY: &str = "worker_threads";
+const MAX_QUEUED_KEY: &str = "max_queued_requests";
+const SHUTDOWN_GRACE_KEY: &str = "shutdown_grace_secs";
+const MACHINES_KEY: &str = "machines";
+const MAX_PLAYERS_KEY: &str = "max_players";
+const CAPACITY_KEY: &str = "capacity";
+const OVERFLOW_KEY: &str = "overflow";
+const SEED_KEY: &str = "seed";
+const PAYOUT_MODEL_KEY: &str = "payout_model";
+const STEEPNESS_KEY: &str = "steepness";
+const THRESHOLD_KEY: &str = "threshold";
+const PAYOUT_FRACTION_KEY: &str = "payout_fraction";
+const PROBABILITY_KEY: &str = "probability";
+const POINTS_KEY: &str = "points";
+const RTP_KEY: &str = "rtp";
+const KIND_KEY: &str = "kind";
+const WIDTH_KEY: &str = "board_width";
+const DEPTH_KEY: &str = "board_depth";
+const CELL_CAPACITY_KEY: &str = "cell_capacity";
+const PUSH_PERIOD_KEY: &str = "push_period";
+const JACKPOT_CONTRIBUTION_KEY: &str = "jackpot_contribution";
+const JACKPOT_PROBABILITY_KEY: &str = "jackpot_probability";
+const MULTIPLIER_RATE_KEY: &str = "multiplier_rate";
+const FREE_DROP_RATE_KEY: &str = "free_drop_rate";
+const BADGE_RATE_KEY: &str = "badge_rate";
+
+const LOGISTIC_MODEL: &str = "logistic";
+const STEP_MODEL: &str = "step";
+const PIECEWISE_LINEAR_MODEL: &str = "piecewise_linear";
+const FIXED_RTP_MODEL: &str = "fixed_rtp";
+const SPILL_TO_WINNER_OVERFLOW: &str = "spill_to_winner";
+const SPILL_TO_HOUSE_OVERFLOW: &str = "spill_to_house";
+const REJECT_OVERFLOW: &str = "reject";
+const CURVE_KIND: &str = "curve";
+const BOARD_KIND: &str = "board";
+// Nombres con los que se avisan los cambios de varias claves relacionadas
+const PAYOUT_GROUP: &str = "payout";
+const JACKPOT_GROUP: &str = "jackpot";
+const ITEMS_GROUP: &str = "items";
+// Las claves de cada máquina tienen la forma machine.<id>.<clave>
+const MACHINE_PREFIX: &str = "machine";
+
+const DEFAULT_MACHINE_ID: u32 = 1;
+
+const DEFAULT_MAX_BATCH_SIZE: u32 = 10;
+const DEFAULT_INITIAL_BALANCE: u32 = 100;
+const DEFAULT_SNAPSHOT_INTERVAL_SECS: u64 = 60;
+const DEFAULT_MAX_PLAYERS: u32 = 10;
+const DEFAULT_AUDIT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_WORKER_THREADS: usize = 4;
+const DEFAULT_MAX_QUEUED_REQUESTS: usize = 64;
+const DEFAULT_SHUTDOWN_GRACE_SECS: u64 = 10;
+
+const DEFAULT_STEP_PROBABILITY: f64 = 1.0;
 
 const SEPARATOR: &str = "=";
+const LIST_SEPARATOR: char = ',';
+const POINT_SEPARATOR: char = ':';
 
 impl FileConfig {
-    pub fn new(path: &str) -> Option<FileConfig> {
-        let config_file = File::open(path).ok()?;
+    pub fn new(path: &str) -> Result<FileConfig, String> {
+        let config_file =
+            File::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
         FileConfig::new_from_file(config_file)
     }
 
-    pub fn new_from_file(config_file: impl Read) -> Option<FileConfig> {
+    /// Lee la configuración y revisa que cada máquina tenga sentido.
+    /// El error dice qué clave o qué línea no es válida.
+    pub fn new_from_file(config_file: impl Read) -> Result<FileConfig, String> {
         let lines: Vec<String> = BufReader::new(config_file)
             .lines()
             .collect::<Result<Vec<_>, _>>()
-            .ok()?;
-        let mut config: HashMap<String, String> = lines
+            .map_err(|e| format!("Could not read the config file: {}", e))?;
+        let values: HashMap<String, String> = lines
             .iter()
-            .map(|line| {
-                let (key, value) = line.trim().split_once(SEPARATOR)?;
-                Some((key.to_string(), value.to_string()))
+            .enumerate()
+            .map(|(n, line)| {
+                let (key, value) = line.trim().split_once(SEPARATOR).ok_or_else(|| {
+                    format!("Line {} is not of the form key{}value", n + 1, SEPARATOR)
+                })?;
+                Ok((key.to_string(), value.to_string()))
             })
-            .collect::<Option<HashMap<_, _>>>()?;
+            .collect::<Result<HashMap<_, _>, String>>()?;
+        let config = Keys::new(values);
 
-        Some(FileConfig {
-            port: config.remove(PORT_KEY)?.parse().ok()?,
-            host: config.remove(HOST_KEY)?,
-            initial_coins_count: config.remove(COINS_KEY)?.parse().ok()?,
-        })
+        let defaults = MachineConfig {
+            id: DEFAULT_MACHINE_ID,
+            initial_coins_count: parse_value(COINS_KEY, &required(&config, COINS_KEY)?)?,
+            max_players: match config.get(MAX_PLAYERS_KEY) {
+                Some(value) => parse_positive(MAX_PLAYERS_KEY, &value)?,
+                None => DEFAULT_MAX_PLAYERS,
+            },
+            capacity: match config.get(CAPACITY_KEY) {
+                Some(value) => parse_positive(CAPACITY_KEY, &value)?,
+                None => DEFAULT_CAPACITY,
+            },
+            overflow: match config.get(OVERFLOW_KEY) {
+                Some(value) => parse_overflow(OVERFLOW_KEY, &value)?,
+                None => Overflow::default(),
+            },
+            payout: parse_payout(|name| config.get(name))?,
+            board: parse_board(|name| config.get(name))?,
+            jackpot: parse_jackpot(|name| config.get(name))?,
+            items: parse_items(|name| config.get(name))?,
+            seed: match config.get(SEED_KEY) {
+                Some(value) => Some(parse_value(SEED_KEY, &value)?),
+                None => None,
+            },
+        };
+        let machines = match config.get(MACHINES_KEY) {
+            Some(ids) => parse_machines(&ids, &config, &defaults)?,
+            None => vec![defaults.clone()],
+        };
+        for machine in &machines {
+            machine
+                .check()
+                .map_err(|e| format!("Machine {}: {}", machine.id, e))?;
+        }
+
+        let file_config = FileConfig {
+            port: parse_value(PORT_KEY, &required(&config, PORT_KEY)?)?,
+            host: required(&config, HOST_KEY)?,
+            max_batch_size: match config.get(MAX_BATCH_KEY) {
+                Some(value) => parse_value(MAX_BATCH_KEY, &value)?,
+                None => DEFAULT_MAX_BATCH_SIZE,
+            },
+            initial_balance: match config.get(BALANCE_KEY) {
+                Some(value) => parse_value(BALANCE_KEY, &value)?,
+                None => DEFAULT_INITIAL_BALANCE,
+            },
+            accounts_path: config.get(ACCOUNTS_KEY),
+            snapshot_path: config.get(SNAPSHOT_KEY),
+            snapshot_interval: Duration::from_secs(match config.get(SNAPSHOT_INTERVAL_KEY) {
+                Some(value) => parse_positive(SNAPSHOT_INTERVAL_KEY, &value)?,
+                None => DEFAULT_SNAPSHOT_INTERVAL_SECS,
+            }),
+            resume_from_snapshot: match config.get(RESUME_KEY) {
+                Some(value) => parse_value(RESUME_KEY, &value)?,
+                None => false,
+            },
+            audit_path: config.get(AUDIT_KEY),
+            audit_max_bytes: match config.get(AUDIT_MAX_BYTES_KEY) {
+                Some(value) => parse_positive(AUDIT_MAX_BYTES_KEY, &value)?,
+                None => DEFAULT_AUDIT_MAX_BYTES,
+            },
+            worker_threads: match config.get(WORKER_THREADS_KEY) {
+                Some(value) => parse_positive(WORKER_THREADS_KEY, &value)?,
+                None => DEFAULT_WORKER_THREADS,
+            },
+            max_queued_requests: match config.get(MAX_QUEUED_KEY) {
+                Some(value) => parse_value(MAX_QUEUED_KEY, &value)?,
+                None => DEFAULT_MAX_QUEUED_REQUESTS,
+            },
+            shutdown_grace: Duration::from_secs(match config.get(SHUTDOWN_GRACE_KEY) {
+                Some(value) => parse_value(SHUTDOWN_GRACE_KEY, &value)?,
+                None => DEFAULT_SHUTDOWN_GRACE_SECS,
+            }),
+            machines,
+        };
+        config.check_unused()?;
+        Ok(file_config)
+    }
+}
+
+/// Claves del archivo. Las que se consultan quedan anotadas, así al final
+/// se rechazan las que no usó nadie, como una mal escrita.
+struct Keys {
+    values: HashMap<String, String>,
+    used: RefCell<HashSet<String>>,
+}
+
+impl Keys {
+    fn new(values: HashMap<String, String>) -> Keys {
+        Keys {
+            values,
+            used: RefCell::new(HashSet::new()),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        let value = self.values.get(key).cloned();
+        if value.is_some() {
+            self.used.borrow_mut().insert(key.to_string());
+        }
+        value
+    }
+
+    fn check_unused(&self) -> Result<(), String> {
+        let used = self.used.borrow();
+        let mut unused: Vec<&String> = self
+            .values
+            .keys()
+            .filter(|key| !used.contains(*key))
+            .collect();
+        unused.sort();
+        match unused.first() {
+            Some(key) => Err(format!("Unknown key {}", key)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn required(config: &Keys, key: &str) -> Result<String, String> {
+    config
+        .get(key)
+        .ok_or_else(|| format!("Missing key {}", key))
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: '{}'", key, value))
+}
+
+fn parse_positive<T: FromStr + Default + PartialOrd>(key: &str, value: &str) -> Result<T, String> {
+    let n = parse_value(key, value)?;
+    if n <= T::default() {
+        return Err(format!("{} must be greater than 0, got {}", key, value));
+    }
+    Ok(n)
+}
+
+fn machine_ids(config: &impl Config) -> Vec<u32> {
+    config.machines().iter().map(MachineConfig::id).collect()
+}
+
+/// `ids` es una lista de ids separados por coma. Las claves que una máquina
+/// no redefine toman el valor general de `defaults`.
+fn parse_machines(
+    ids: &str,
+    config: &Keys,
+    defaults: &MachineConfig,
+) -> Result<Vec<MachineConfig>, String> {
+    let mut machines: Vec<MachineConfig> = Vec::new();
+    for id in ids.split(LIST_SEPARATOR) {
+        let id: u32 = parse_value(MACHINES_KEY, id.trim())?;
+        if machines.iter().any(|machine| machine.id == id) {
+            return Err(format!(
+                "Machine {} is listed twice in {}",
+                id, MACHINES_KEY
+            ));
+        }
+
+        let key = |name: &str| format!("{}.{}.{}", MACHINE_PREFIX, id, name);
+        let value = |name: &str| config.get(&key(name)).or_else(|| config.get(name));
+        let in_machine = |e: String| format!("Machine {}: {}", id, e);
+        machines.push(MachineConfig {
+            id,
+            initial_coins_count: match config.get(&key(COINS_KEY)) {
+                Some(value) => parse_value(&key(COINS_KEY), &value)?,
+                None => defaults.initial_coins_count,
+            },
+            max_players: match config.get(&key(MAX_PLAYERS_KEY)) {
+                Some(value) => parse_positive(&key(MAX_PLAYERS_KEY), &value)?,
+                None => defaults.max_players,
+            },
+            capacity: match config.get(&key(CAPACITY_KEY)) {
+                Some(value) => parse_positive(&key(CAPACITY_KEY), &value)?,
+                None => defaults.capacity,
+            },
+            overflow: match config.get(&key(OVERFLOW_KEY)) {
+                Some(value) => parse_overflow(&key(OVERFLOW_KEY), &value)?,
+                None => defaults.overflow,
+            },
+            payout: parse_payout(value).map_err(in_machine)?,
+            board: parse_board(value).map_err(in_machine)?,
+            jackpot: parse_jackpot(value).map_err(in_machine)?,
+            items: parse_items(value).map_err(in_machine)?,
+            seed: match config.get(&key(SEED_KEY)) {
+                Some(value) => Some(parse_value(&key(SEED_KEY), &value)?),
+                None => defaults.seed,
+            },
+        });
     }
+    Ok(machines)
+}
+
+fn parse_overflow(key: &str, value: &str) -> Result<Overflow, String> {
+    match value {
+        SPILL_TO_WINNER_OVERFLOW => Ok(Overflow::SpillToWinner),
+        SPILL_TO_HOUSE_OVERFLOW => Ok(Overflow::SpillToHouse),
+        REJECT_OVERFLOW => Ok(Overflow::Reject),
+        _ => Err(format!(
+            "Unknown {} '{}', expected {}, {} or {}",
+            key, value, SPILL_TO_WINNER_OVERFLOW, SPILL_TO_HOUSE_OVERFLOW, REJECT_OVERFLOW
+        )),
+    }
+}
+
+/// `value` busca cada parámetro del modelo de pago por nombre.
+fn parse_payout(value: impl Fn(&str) -> Option<String>) -> Result<PayoutConfig, String> {
+    let number = |name: &str, default: Option<f64>| match value(name) {
+        Some(v) => parse_value::<f64>(name, &v),
+        None => default.ok_or_else(|| format!("Missing key {}", name)),
+    };
+    let invalid = |e: &str| format!("Invalid payout model: {}", e);
+    let payout_fraction = number(PAYOUT_FRACTION_KEY, Some(DEFAULT_PAYOUT_FRACTION))?;
+
+    let model = match value(PAYOUT_MODEL_KEY).as_deref().unwrap_or(LOGISTIC_MODEL) {
+        LOGISTIC_MODEL => PayoutConfig::Logistic(
+            Logistic::new(
+                number(STEEPNESS_KEY, Some(DEFAULT_STEEPNESS))?,
+                number(THRESHOLD_KEY, Some(DEFAULT_THRESHOLD))?,
+                payout_fraction,
+            )
+            .map_err(invalid)?,
+        ),
+        STEP_MODEL => PayoutConfig::Step(
+            Step::new(
+                parse_value(
+                    THRESHOLD_KEY,
+                    &value(THRESHOLD_KEY).ok_or_else(|| {
+                        format!("Missing key {} for the {} model", THRESHOLD_KEY, STEP_MODEL)
+                    })?,
+                )?,
+                number(PROBABILITY_KEY, Some(DEFAULT_STEP_PROBABILITY))?,
+                payout_fraction,
+            )
+            .map_err(invalid)?,
+        ),
+        PIECEWISE_LINEAR_MODEL => PayoutConfig::PiecewiseLinear(
+            PiecewiseLinear::new(
+                parse_points(&value(POINTS_KEY).ok_or_else(|| {
+                    format!(
+                        "Missing key {} for the {} model",
+                        POINTS_KEY, PIECEWISE_LINEAR_MODEL
+                    )
+                })?)?,
+                payout_fraction,
+            )
+            .map_err(invalid)?,
+        ),
+        FIXED_RTP_MODEL => PayoutConfig::FixedRtp(
+            FixedRtp::new(number(RTP_KEY, None)?, number(PROBABILITY_KEY, None)?)
+                .map_err(invalid)?,
+        ),
+        other => {
+            return Err(format!(
+                "Unknown {} '{}', expected {}, {}, {} or {}",
+                PAYOUT_MODEL_KEY,
+                other,
+                LOGISTIC_MODEL,
+                STEP_MODEL,
+                PIECEWISE_LINEAR_MODEL,
+                FIXED_RTP_MODEL
+            ))
+        }
+    };
+    Ok(model)
+}
+
+/// `value` busca cada parámetro del estante por nombre.
+/// Devuelve `Ok(None)` si la máquina no tiene estante.
+fn parse_board(value: impl Fn(&str) -> Option<String>) -> Result<Option<BoardConfig>, String> {
+    let number = |name: &str, default: u32| match value(name) {
+        Some(v) => parse_value::<u32>(name, &v),
+        None => Ok(default),
+    };
+
+    match value(KIND_KEY).as_deref().unwrap_or(CURVE_KIND) {
+        CURVE_KIND => Ok(None),
+        BOARD_KIND => Ok(Some(
+            BoardConfig::new(
+                number(WIDTH_KEY, DEFAULT_WIDTH)?,
+                number(DEPTH_KEY, DEFAULT_DEPTH)?,
+                number(CELL_CAPACITY_KEY, DEFAULT_CELL_CAPACITY)?,
+                number(PUSH_PERIOD_KEY, DEFAULT_PUSH_PERIOD)?,
+            )
+            .map_err(|e| format!("Invalid board: {}", e))?,
+        )),
+        other => Err(format!(
+            "Unknown {} '{}', expected {} or {}",
+            KIND_KEY, other, CURVE_KIND, BOARD_KIND
+        )),
+    }
+}
+
+/// `value` busca cada parámetro del pozo por nombre.
+/// Devuelve `Ok(None)` si la máquina no tiene pozo.
+fn parse_jackpot(value: impl Fn(&str) -> Option<String>) -> Result<Option<JackpotConfig>, String> {
+    let contribution = match value(JACKPOT_CONTRIBUTION_KEY) {
+        Some(v) => parse_value(JACKPOT_CONTRIBUTION_KEY, &v)?,
+        None => return Ok(None),
+    };
+    let probability = match value(JACKPOT_PROBABILITY_KEY) {
+        Some(v) => parse_value(JACKPOT_PROBABILITY_KEY, &v)?,
+        None => DEFAULT_JACKPOT_PROBABILITY,
+    };
+    JackpotConfig::new(contribution, probability)
+        .map(Some)
+        .map_err(|e| format!("Invalid jackpot: {}", e))
+}
+
+/// Sin ninguna de las probabilidades la máquina no tiene premios;
+/// las que falten valen 0.
+fn parse_items(value: impl Fn(&str) -> Option<String>) -> Result<Option<ItemsConfig>, String> {
+    let keys = [MULTIPLIER_RATE_KEY, FREE_DROP_RATE_KEY, BADGE_RATE_KEY];
+    if keys.iter().all(|key| value(key).is_none()) {
+        return Ok(None);
+    }
+    let rate = |key: &str| match value(key) {
+        Some(v) => parse_value::<f64>(key, &v),
+        None => Ok(0.0),
+    };
+    ItemsConfig::new(
+        rate(MULTIPLIER_RATE_KEY)?,
+        rate(FREE_DROP_RATE_KEY)?,
+        rate(BADGE_RATE_KEY)?,
+    )
+    .map(Some)
+    .map_err(|e| format!("Invalid items: {}", e))
+}
+
+/// Puntos de la forma `pool:probabilidad`, separados por coma.
+fn parse_points(points: &str) -> Result<Vec<(u32, f64)>, String> {
+    points
+        .split(LIST_SEPARATOR)
+        .map(|point| {
+            let invalid = || format!("Invalid point in {}: '{}'", POINTS_KEY, point.trim());
+            let (pool, probability) = point
+                .trim()
+                .split_once(POINT_SEPARATOR)
+                .ok_or_else(invalid)?;
+            Ok((
+                pool.parse().map_err(|_| invalid())?,
+                probability.parse().map_err(|_| invalid())?,
+            ))
+        })
+        .collect()
 }
 
 impl Config for FileConfig {
@@ -55,17 +752,67 @@ impl Config for FileConfig {
         &self.host
     }
 
-    fn initial_coins_count(&self) -> u32 {
-        self.initial_coins_count
+    fn max_batch_size(&self) -> u32 {
+        self.max_batch_size
+    }
+
+    fn initial_balance(&self) -> u32 {
+        self.initial_balance
+    }
+
+    fn accounts_path(&self) -> Option<&str> {
+        self.accounts_path.as_deref()
+    }
+
+    fn snapshot_path(&self) -> Option<&str> {
+        self.snapshot_path.as_deref()
+    }
+
+    fn snapshot_interval(&self) -> Duration {
+        self.snapshot_interval
+    }
+
+    fn resume_from_snapshot(&self) -> bool {
+        self.resume_from_snapshot
+    }
+
+    fn audit_path(&self) -> Option<&str> {
+        self.audit_path.as_deref()
+    }
+
+    fn audit_max_bytes(&self) -> u64 {
+        self.audit_max_bytes
+    }
+
+    fn worker_threads(&self) -> usize {
+        self.worker_threads
+    }
+
+    fn max_queued_requests(&self) -> usize {
+        self.max_queued_requests
+    }
+
+    fn shutdown_grace(&self) -> Duration {
+        self.shutdown_grace
+    }
+
+    fn machines(&self) -> &[MachineConfig] {
+        &self.machines
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
+    use std::time::Duration;
 
+    use crate::board::{BoardConfig, DEFAULT_CELL_CAPACITY, DEFAULT_DEPTH};
     use crate::config::FileConfig;
+    use crate::items::ItemsConfig;
+    use crate::jackpot::JackpotConfig;
+    use crate::machine::Overflow;
     use crate::server::traits::Config;
+    use common::payout::{FixedRtp, Logistic, PayoutConfig, PiecewiseLinear, Step};
 
     #[test]
     fn test_valid_file() {
@@ -78,7 +825,7 @@ mod tests {
         let config = FileConfig::new_from_file(cursor).unwrap();
         assert_eq!(config.port(), 8080);
         assert_eq!(config.host(), "localhost");
-        assert_eq!(config.initial_coins_count(), 200)
+        assert_eq!(config.machines()[0].initial_coins_count(), 200)
     }
 
     #[test]
@@ -92,7 +839,350 @@ mod tests {
         let config = FileConfig::new_from_file(cursor).unwrap();
         assert_eq!(config.port(), 8080);
         assert_eq!(config.host(), "localhost");
-        assert_eq!(config.initial_coins_count(), 200)
+        assert_eq!(config.machines()[0].initial_coins_count(), 200)
+    }
+
+    #[test]
+    fn test_default_optional_keys() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+        assert_eq!(config.max_batch_size(), super::DEFAULT_MAX_BATCH_SIZE);
+        assert_eq!(config.initial_balance(), super::DEFAULT_INITIAL_BALANCE);
+        assert_eq!(config.accounts_path(), None);
+        assert_eq!(config.snapshot_path(), None);
+        assert_eq!(
+            config.snapshot_interval(),
+            Duration::from_secs(super::DEFAULT_SNAPSHOT_INTERVAL_SECS)
+        );
+        assert!(!config.resume_from_snapshot());
+        assert_eq!(config.audit_path(), None);
+        assert_eq!(config.audit_max_bytes(), super::DEFAULT_AUDIT_MAX_BYTES);
+        assert_eq!(config.worker_threads(), super::DEFAULT_WORKER_THREADS);
+        assert_eq!(
+            config.max_queued_requests(),
+            super::DEFAULT_MAX_QUEUED_REQUESTS
+        );
+        assert_eq!(
+            config.shutdown_grace(),
+            Duration::from_secs(super::DEFAULT_SHUTDOWN_GRACE_SECS)
+        );
+        assert_eq!(config.machines().len(), 1);
+        assert_eq!(config.machines()[0].id(), super::DEFAULT_MACHINE_ID);
+        assert_eq!(config.machines()[0].initial_coins_count(), 200);
+        assert_eq!(
+            config.machines()[0].max_players(),
+            super::DEFAULT_MAX_PLAYERS
+        );
+        assert_eq!(config.machines()[0].payout(), &PayoutConfig::default());
+        assert_eq!(config.machines()[0].seed(), None)
+    }
+
+    #[test]
+    fn test_optional_keys() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+max_batch_size=25
+initial_balance=50
+accounts_path=accounts.jsonl
+snapshot_path=machine.json
+snapshot_interval_secs=5
+resume_from_snapshot=true
+audit_path=audit.jsonl
+audit_max_bytes=4096
+worker_threads=16
+max_queued_requests=0
+shutdown_grace_secs=0",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+        assert_eq!(config.max_batch_size(), 25);
+        assert_eq!(config.initial_balance(), 50);
+        assert_eq!(config.accounts_path(), Some("accounts.jsonl"));
+        assert_eq!(config.snapshot_path(), Some("machine.json"));
+        assert_eq!(config.snapshot_interval(), Duration::from_secs(5));
+        assert!(config.resume_from_snapshot());
+        assert_eq!(config.audit_path(), Some("audit.jsonl"));
+        assert_eq!(config.audit_max_bytes(), 4096);
+        assert_eq!(config.worker_threads(), 16);
+        assert_eq!(config.max_queued_requests(), 0);
+        assert_eq!(config.shutdown_grace(), Duration::ZERO)
+    }
+
+    #[test]
+    fn test_zero_snapshot_interval() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+snapshot_interval_secs=0",
+        );
+
+        assert!(FileConfig::new_from_file(cursor).is_err());
+    }
+
+    #[test]
+    fn test_zero_worker_threads() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+worker_threads=0",
+        );
+
+        assert!(FileConfig::new_from_file(cursor).is_err());
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let base = "port=8080\nhost=localhost\ninitial_coins_count=200\nmachines=1,2\n";
+        for (extra, key) in [
+            ("overflw=reject", "overflw"),
+            ("machine.2.capacty=900", "machine.2.capacty"),
+            ("machine.3.capacity=900", "machine.3.capacity"),
+        ] {
+            let cursor = Cursor::new(format!("{}{}", base, extra));
+
+            let err = FileConfig::new_from_file(cursor).err().unwrap();
+
+            assert_eq!(err, format!("Unknown key {}", key));
+        }
+        let cursor = Cursor::new(format!("{}machine.2.capacity=900", base));
+        FileConfig::new_from_file(cursor).unwrap();
+    }
+
+    #[test]
+    fn test_machines() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+max_players=4
+machines=1, 5
+machine.5.initial_coins_count=900
+machine.1.max_players=2",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+        let machines: Vec<(u32, u32, u32)> = config
+            .machines()
+            .iter()
+            .map(|m| (m.id(), m.initial_coins_count(), m.max_players()))
+            .collect();
+        assert_eq!(machines, [(1, 200, 2), (5, 900, 4)])
+    }
+
+    #[test]
+    fn test_payout_models() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+steepness=0.05
+threshold=600
+machines=1,2,3,4
+machine.2.payout_model=step
+machine.2.probability=0.5
+machine.3.payout_model=piecewise_linear
+machine.3.points=0:0, 500:0.1, 1000:0.8
+machine.3.payout_fraction=0.1
+machine.4.payout_model=fixed_rtp
+machine.4.rtp=0.95
+machine.4.probability=0.2",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+        let payouts: Vec<&PayoutConfig> = config.machines().iter().map(|m| m.payout()).collect();
+        assert_eq!(
+            payouts,
+            [
+                &PayoutConfig::Logistic(Logistic::new(0.05, 600.0, 0.05).unwrap()),
+                &PayoutConfig::Step(Step::new(600, 0.5, 0.05).unwrap()),
+                &PayoutConfig::PiecewiseLinear(
+                    PiecewiseLinear::new(vec![(0, 0.0), (500, 0.1), (1000, 0.8)], 0.1).unwrap()
+                ),
+                &PayoutConfig::FixedRtp(FixedRtp::new(0.95, 0.2).unwrap()),
+            ]
+        )
+    }
+
+    #[test]
+    fn test_invalid_payout_model() {
+        for model in [
+            "payout_model=quadratic",
+            "payout_model=fixed_rtp\nrtp=0.9",
+            "payout_model=step\nthreshold=100\nprobability=2",
+            "payout_model=piecewise_linear\npoints=500:0.1,100:0.2",
+        ] {
+            let cursor = Cursor::new(format!(
+                "port=8080
+host=localhost
+initial_coins_count=200
+{}",
+                model
+            ));
+
+            assert!(FileConfig::new_from_file(cursor).is_err(), "{}", model);
+        }
+    }
+
+    #[test]
+    fn test_seeds() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+seed=7
+machines=1,2
+machine.2.seed=18446744073709551615",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+        let seeds: Vec<Option<u64>> = config.machines().iter().map(|m| m.seed()).collect();
+        assert_eq!(seeds, [Some(7), Some(u64::MAX)])
+    }
+
+    #[test]
+    fn test_board_machines() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+board_width=5
+machines=1,2
+machine.2.kind=board
+machine.2.push_period=2",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+        assert_eq!(config.machines()[0].board(), None);
+        assert_eq!(
+            config.machines()[1].board(),
+            Some(&BoardConfig::new(5, DEFAULT_DEPTH, DEFAULT_CELL_CAPACITY, 2).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_invalid_board() {
+        for extra in ["kind=pinball", "kind=board\nboard_width=0"] {
+            let cursor = Cursor::new(format!(
+                "port=8080
+host=localhost
+initial_coins_count=200
+{}",
+                extra
+            ));
+
+            assert!(FileConfig::new_from_file(cursor).is_err());
+        }
+    }
+
+    #[test]
+    fn test_jackpots() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+machines=1,2,3
+jackpot_probability=0.01
+machine.2.jackpot_contribution=0.05
+machine.3.jackpot_contribution=0.1
+machine.3.jackpot_probability=0.5",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+        assert_eq!(config.machines()[0].jackpot(), None);
+        assert_eq!(
+            config.machines()[1].jackpot(),
+            Some(&JackpotConfig::new(0.05, 0.01).unwrap())
+        );
+        assert_eq!(
+            config.machines()[2].jackpot(),
+            Some(&JackpotConfig::new(0.1, 0.5).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_invalid_jackpot() {
+        for extra in [
+            "jackpot_contribution=2",
+            "jackpot_contribution=0.1\njackpot_probability=0",
+        ] {
+            let cursor = Cursor::new(format!(
+                "port=8080
+host=localhost
+initial_coins_count=200
+{}",
+                extra
+            ));
+
+            assert!(FileConfig::new_from_file(cursor).is_err());
+        }
+    }
+
+    #[test]
+    fn test_items() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+machines=1,2
+machine.2.free_drop_rate=0.05
+machine.2.badge_rate=0.01",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+        assert_eq!(config.machines()[0].items(), None);
+        assert_eq!(
+            config.machines()[1].items(),
+            Some(&ItemsConfig::new(0.0, 0.05, 0.01).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_invalid_items() {
+        for extra in ["badge_rate=x", "multiplier_rate=0.6\nfree_drop_rate=0.6"] {
+            let cursor = Cursor::new(format!(
+                "port=8080
+host=localhost
+initial_coins_count=200
+{}",
+                extra
+            ));
+
+            assert!(FileConfig::new_from_file(cursor).is_err());
+        }
+    }
+
+    #[test]
+    fn test_zero_max_players() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+machines=1
+machine.1.max_players=0",
+        );
+
+        assert!(FileConfig::new_from_file(cursor).is_err());
+    }
+
+    #[test]
+    fn test_repeated_machine_id() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+machines=1,2,1",
+        );
+
+        assert!(FileConfig::new_from_file(cursor).is_err());
     }
 
     #[test]
@@ -103,7 +1193,7 @@ host=localhost
 initial_coins_count=200",
         );
 
-        assert!(FileConfig::new_from_file(cursor).is_none());
+        assert!(FileConfig::new_from_file(cursor).is_err());
     }
 
     #[test]
@@ -114,6 +1204,191 @@ host=localhost
 initial_coins_count=200",
         );
 
-        assert!(FileConfig::new_from_file(cursor).is_none());
+        assert!(FileConfig::new_from_file(cursor).is_err());
+    }
+
+    #[test]
+    fn test_capacity_and_overflow() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+capacity=500
+threshold=400
+machines=1,2
+machine.2.overflow=spill_to_winner
+machine.2.capacity=2000",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+        let machines: Vec<(u32, Overflow)> = config
+            .machines()
+            .iter()
+            .map(|m| (m.capacity(), m.overflow()))
+            .collect();
+        assert_eq!(
+            machines,
+            [
+                (500, Overflow::SpillToHouse),
+                (2000, Overflow::SpillToWinner)
+            ]
+        );
+        assert!(config.machines().iter().all(|m| m.check().is_ok()));
+    }
+
+    #[test]
+    fn test_invalid_capacity_and_overflow() {
+        for (extra, error) in [
+            ("capacity=0", "capacity must be greater than 0"),
+            ("capacity=x", "Invalid value for capacity: 'x'"),
+            ("overflow=spill", "Unknown overflow 'spill'"),
+            (
+                "machines=1,2\nmachine.2.overflow=spill",
+                "Unknown machine.2.overflow 'spill'",
+            ),
+        ] {
+            let cursor = Cursor::new(format!(
+                "port=8080
+host=localhost
+initial_coins_count=200
+{}",
+                extra
+            ));
+
+            let result = FileConfig::new_from_file(cursor);
+            assert!(
+                result.as_ref().is_err_and(|e| e.contains(error)),
+                "{}: {:?}",
+                extra,
+                result.map(|_| ())
+            );
+        }
+    }
+
+    #[test]
+    fn test_inconsistent_machines() {
+        for (extra, error) in [
+            (
+                "capacity=100",
+                "initial_coins_count (200) is above capacity (100)",
+            ),
+            ("capacity=300", "threshold (700) is above capacity (300)"),
+            ("kind=board\ncapacity=300", "the board holds"),
+            (
+                "payout_model=piecewise_linear\npoints=0:0,900:0\noverflow=reject",
+                "would fill up and never empty",
+            ),
+        ] {
+            let cursor = Cursor::new(format!(
+                "port=8080
+host=localhost
+initial_coins_count=200
+{}",
+                extra
+            ));
+
+            let result = FileConfig::new_from_file(cursor).map(|_| ());
+            assert!(
+                result
+                    .as_ref()
+                    .is_err_and(|e| e.starts_with("Machine 1: ") && e.contains(error)),
+                "{:?}",
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_restart_keys() {
+        let current = FileConfig::new_from_file(Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+machines=1,2
+machine.2.kind=board
+seed=5",
+        ))
+        .unwrap();
+        let new = FileConfig::new_from_file(Cursor::new(
+            "port=9090
+host=localhost
+initial_coins_count=200
+machines=1,2,3
+machine.1.kind=board
+machine.2.kind=board
+machine.2.board_width=9
+seed=5
+machine.1.seed=6
+max_batch_size=50
+worker_threads=8
+capacity=2000",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            super::restart_keys(&current, &new),
+            vec![
+                "port",
+                "worker_threads",
+                "machines",
+                "machine.1.kind",
+                "machine.1.seed",
+                "machine.2.board_width",
+            ]
+        );
+        assert!(super::restart_keys(&current, &current).is_empty());
+    }
+
+    #[test]
+    fn test_live_keys() {
+        let current = FileConfig::new_from_file(Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=200
+machines=1,2",
+        ))
+        .unwrap();
+        let new = FileConfig::new_from_file(Cursor::new(
+            "port=9090
+host=localhost
+initial_coins_count=200
+machines=1,2,3
+max_batch_size=50
+machine.1.threshold=800
+machine.2.capacity=2000
+machine.2.jackpot_contribution=0.1
+machine.3.max_players=2",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            super::live_keys(&current, &new),
+            vec![
+                "max_batch_size",
+                "machine.1.payout",
+                "machine.2.capacity",
+                "machine.2.jackpot",
+            ]
+        );
+        assert!(super::live_keys(&current, &current).is_empty());
+    }
+
+    #[test]
+    fn test_live_machine_config() {
+        let config = |keys: &str| {
+            let cursor = Cursor::new(format!(
+                "port=8080\nhost=localhost\ninitial_coins_count=200\n{}",
+                keys
+            ));
+            FileConfig::new_from_file(cursor).unwrap().machines()[0].clone()
+        };
+        let running = config("seed=1");
+        let new = config("initial_coins_count=300\nseed=2\nkind=board\nmax_players=3");
+
+        let live = new.live(&running);
+        assert_eq!(live.initial_coins_count(), 200);
+        assert_eq!(live.seed(), Some(1));
+        assert_eq!(live.board(), None);
+        assert_eq!(live.max_players(), 3);
     }
 }
@@ -0,0 +1,216 @@
+use std::collections::VecDeque;
+
+use common::protocol::Item;
+use serde::{Deserialize, Serialize};
+
+/// Premios que entran a la vez en el estante. Mientras esté lleno no
+/// aparecen nuevos.
+pub const MAX_SHELF_ITEMS: usize = 10;
+
+/// Cuánto multiplica una ficha las monedas de una moneda que paga.
+pub const MULTIPLIER_FACTOR: u32 = 2;
+
+/// Probabilidad de que cada moneda arrojada deje un premio de cada tipo
+/// en el estante.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ItemsConfig {
+    multiplier: f64,
+    free_drop: f64,
+    badge: f64,
+}
+
+impl ItemsConfig {
+    /// Cada moneda deja a lo sumo un premio, así que las probabilidades
+    /// no pueden sumar más de 1.
+    pub fn new(multiplier: f64, free_drop: f64, badge: f64) -> Result<ItemsConfig, &'static str> {
+        let rates = [multiplier, free_drop, badge];
+        if rates.iter().any(|rate| !(0.0..=1.0).contains(rate)) {
+            return Err("item rates must be between 0 and 1");
+        }
+        if rates.iter().sum::<f64>() > 1.0 {
+            return Err("item rates must add up to at most 1");
+        }
+        Ok(ItemsConfig {
+            multiplier,
+            free_drop,
+            badge,
+        })
+    }
+
+    pub fn rate(&self, item: Item) -> f64 {
+        match item {
+            Item::Multiplier => self.multiplier,
+            Item::FreeDrop => self.free_drop,
+            Item::Badge => self.badge,
+        }
+    }
+
+    /// Premio que deja una moneda según `roll` (uniforme en [0, 1)), si deja alguno.
+    fn spawn(&self, roll: f64) -> Option<Item> {
+        let mut accumulated = 0.0;
+        Item::ALL.into_iter().find(|item| {
+            accumulated += self.rate(*item);
+            roll < accumulated
+        })
+    }
+}
+
+/// Premios apoyados en el estante, del más cercano al borde al más nuevo.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shelf {
+    config: ItemsConfig,
+    held: VecDeque<Item>,
+}
+
+impl Shelf {
+    pub fn with(config: ItemsConfig, held: impl IntoIterator<Item = Item>) -> Shelf {
+        Shelf {
+            config,
+            held: held.into_iter().take(MAX_SHELF_ITEMS).collect(),
+        }
+    }
+
+    pub fn config(&self) -> &ItemsConfig {
+        &self.config
+    }
+
+    pub fn held(&self) -> impl Iterator<Item = Item> + '_ {
+        self.held.iter().copied()
+    }
+
+    /// Si la moneda hizo caer monedas, cae con ellas el premio más cercano
+    /// al borde. Después la moneda puede dejar uno nuevo atrás de todo.
+    /// Devuelve el premio que cayó.
+    pub fn play(&mut self, fell: u32, roll: f64) -> Option<Item> {
+        let won = if fell > 0 {
+            self.held.pop_front()
+        } else {
+            None
+        };
+        if self.held.len() < MAX_SHELF_ITEMS {
+            self.held.extend(self.config.spawn(roll));
+        }
+        won
+    }
+}
+
+/// Premios que juntó un jugador y todavía no usó.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Inventory {
+    multipliers: u32,
+    free_drops: u32,
+    badges: u32,
+}
+
+impl Inventory {
+    pub fn is_empty(&self) -> bool {
+        *self == Inventory::default()
+    }
+
+    pub fn count(&self, item: Item) -> u32 {
+        match item {
+            Item::Multiplier => self.multipliers,
+            Item::FreeDrop => self.free_drops,
+            Item::Badge => self.badges,
+        }
+    }
+
+    fn count_mut(&mut self, item: Item) -> &mut u32 {
+        match item {
+            Item::Multiplier => &mut self.multipliers,
+            Item::FreeDrop => &mut self.free_drops,
+            Item::Badge => &mut self.badges,
+        }
+    }
+
+    pub fn add(&mut self, item: Item) {
+        let count = self.count_mut(item);
+        *count = count.saturating_add(1);
+    }
+
+    /// Usa hasta `n` premios de `item`. Devuelve cuántos usó.
+    pub fn take(&mut self, item: Item, n: u32) -> u32 {
+        let count = self.count_mut(item);
+        let taken = n.min(*count);
+        *count -= taken;
+        taken
+    }
+
+    /// Si la moneda pagó y queda alguna ficha multiplicadora, la usa.
+    /// Devuelve las monedas extra que paga la casa.
+    pub fn multiply(&mut self, fell: u32) -> u32 {
+        if fell == 0 || self.take(Item::Multiplier, 1) == 0 {
+            return 0;
+        }
+        fell.saturating_mul(MULTIPLIER_FACTOR - 1)
+    }
+
+    /// Cantidad de cada tipo de premio, incluidos los que no tiene.
+    pub fn items(&self) -> Vec<(Item, u32)> {
+        Item::ALL
+            .into_iter()
+            .map(|item| (item, self.count(item)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod items_tests {
+    use super::*;
+
+    #[test]
+    fn invalid_configs() {
+        assert!(ItemsConfig::new(-0.1, 0.0, 0.0).is_err());
+        assert!(ItemsConfig::new(0.0, f64::NAN, 0.0).is_err());
+        assert!(ItemsConfig::new(0.5, 0.3, 0.3).is_err());
+        assert!(ItemsConfig::new(0.5, 0.3, 0.2).is_ok());
+    }
+
+    #[test]
+    fn items_fall_in_order() {
+        let config = ItemsConfig::new(0.1, 0.2, 0.3).unwrap();
+        let mut shelf = Shelf::with(config, []);
+
+        assert_eq!(shelf.play(0, 0.05), None);
+        assert_eq!(shelf.play(0, 0.25), None);
+        assert_eq!(shelf.play(0, 0.55), None);
+        assert_eq!(shelf.play(0, 0.9), None);
+        assert_eq!(shelf.play(5, 0.9), Some(Item::Multiplier));
+        assert_eq!(shelf.play(1, 0.9), Some(Item::FreeDrop));
+        assert_eq!(shelf.held().collect::<Vec<_>>(), [Item::Badge]);
+    }
+
+    #[test]
+    fn full_shelf_holds_no_more_items() {
+        let config = ItemsConfig::new(0.0, 0.0, 1.0).unwrap();
+        let mut shelf = Shelf::with(config, []);
+
+        for _ in 0..MAX_SHELF_ITEMS + 5 {
+            shelf.play(0, 0.5);
+        }
+
+        assert_eq!(shelf.held().count(), MAX_SHELF_ITEMS);
+    }
+
+    #[test]
+    fn take_from_inventory() {
+        let mut inventory = Inventory::default();
+        assert!(inventory.is_empty());
+        inventory.add(Item::FreeDrop);
+        inventory.add(Item::FreeDrop);
+
+        assert_eq!(inventory.take(Item::FreeDrop, 5), 2);
+        assert_eq!(inventory.take(Item::Multiplier, 1), 0);
+        assert!(inventory.is_empty());
+    }
+
+    #[test]
+    fn multipliers_only_apply_to_winning_coins() {
+        let mut inventory = Inventory::default();
+        inventory.add(Item::Multiplier);
+
+        assert_eq!(inventory.multiply(0), 0);
+        assert_eq!(inventory.multiply(7), 7 * (MULTIPLIER_FACTOR - 1));
+        assert_eq!(inventory.multiply(7), 0);
+    }
+}
@@ -0,0 +1,117 @@
+/// El pozo se lleva en milésimos de moneda, así los aportes fraccionarios
+/// se guardan exactos en el snapshot.
+pub const JACKPOT_SCALE: u64 = 1000;
+
+pub const DEFAULT_JACKPOT_PROBABILITY: f64 = 0.0001;
+
+/// Parámetros del pozo acumulado de una máquina.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JackpotConfig {
+    contribution: f64,
+    probability: f64,
+}
+
+impl JackpotConfig {
+    /// `contribution` es la fracción de cada moneda arrojada que va al pozo
+    /// y `probability` la de que una moneda se lleve el pozo entero.
+    pub fn new(contribution: f64, probability: f64) -> Result<JackpotConfig, &'static str> {
+        if !(0.0..=1.0).contains(&contribution) || contribution * (JACKPOT_SCALE as f64) < 1.0 {
+            return Err("jackpot contribution must be between 0.001 and 1");
+        }
+        if !(probability > 0.0 && probability <= 1.0) {
+            return Err("jackpot probability must be in (0, 1]");
+        }
+        Ok(JackpotConfig {
+            contribution,
+            probability,
+        })
+    }
+
+    pub fn contribution(&self) -> f64 {
+        self.contribution
+    }
+
+    pub fn probability(&self) -> f64 {
+        self.probability
+    }
+
+    /// Aporte de cada moneda al pozo, en milésimos de moneda.
+    pub fn share(&self) -> u64 {
+        (self.contribution * JACKPOT_SCALE as f64).round() as u64
+    }
+}
+
+/// Pozo progresivo que crece con cada moneda arrojada.
+///
+/// Los aportes los pone la casa: no salen de las monedas de la máquina.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jackpot {
+    config: JackpotConfig,
+    value: u64,
+}
+
+impl Jackpot {
+    /// `value` en milésimos de moneda.
+    pub fn with(config: JackpotConfig, value: u64) -> Jackpot {
+        Jackpot { config, value }
+    }
+
+    pub fn config(&self) -> &JackpotConfig {
+        &self.config
+    }
+
+    /// Monedas enteras en el pozo.
+    pub fn coins(&self) -> u32 {
+        (self.value / JACKPOT_SCALE).min(u64::from(u32::MAX)) as u32
+    }
+
+    /// Valor en milésimos de moneda.
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+
+    /// Suma el aporte de una moneda y, si `roll` (uniforme en [0, 1)) sale
+    /// por debajo de la probabilidad, paga las monedas enteras del pozo.
+    /// Lo que sobra queda para el próximo.
+    pub fn play(&mut self, roll: f64) -> u32 {
+        self.value += self.config.share();
+        if roll >= self.config.probability {
+            return 0;
+        }
+        let won = self.coins();
+        self.value -= u64::from(won) * JACKPOT_SCALE;
+        won
+    }
+}
+
+#[cfg(test)]
+mod jackpot_tests {
+    use super::*;
+
+    #[test]
+    fn invalid_configs() {
+        assert!(JackpotConfig::new(0.0, 0.5).is_err());
+        assert!(JackpotConfig::new(0.0001, 0.5).is_err());
+        assert!(JackpotConfig::new(1.5, 0.5).is_err());
+        assert!(JackpotConfig::new(0.1, 0.0).is_err());
+        assert!(JackpotConfig::new(0.1, f64::NAN).is_err());
+        assert!(JackpotConfig::new(0.1, 1.0).is_ok());
+    }
+
+    #[test]
+    fn grows_until_it_is_won() {
+        let mut jackpot = Jackpot::with(JackpotConfig::new(0.25, 0.5).unwrap(), 0);
+
+        for _ in 0..10 {
+            assert_eq!(jackpot.play(0.9), 0);
+        }
+        assert_eq!(jackpot.value(), 2500);
+        assert_eq!(jackpot.coins(), 2);
+
+        // La moneda que gana también aporta
+        assert_eq!(jackpot.play(0.1), 2);
+        assert_eq!(jackpot.value(), 750);
+        assert_eq!(jackpot.play(0.1), 1);
+        assert_eq!(jackpot.value(), 0);
+    }
+}
@@ -0,0 +1,12 @@
+pub mod accounts;
+pub mod audit;
+pub mod board;
+pub mod config;
+pub mod items;
+pub mod jackpot;
+pub mod machine;
+pub mod replay;
+pub mod server;
+pub mod simulation;
+pub mod snapshot;
+pub mod wallet;
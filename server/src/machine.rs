@@ -1,108 +1,743 @@
-use rand::Rng;
+use common::{
+    fairness,
+    payout::{Logistic, PayoutConfig, PayoutModel},
+    protocol::Item,
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 
-const MACHINE_CAPACITY: u32 = 1000;
+use crate::{
+    board::{Board, BoardConfig, BoardSnapshot, CoinDrop},
+    config::MachineConfig,
+    items::Shelf,
+    jackpot::Jackpot,
+};
+
+pub const DEFAULT_CAPACITY: u32 = 1000;
+
+/// Qué pasa cuando una moneda deja la máquina con más monedas de las que entran.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Las que sobran caen al jugador.
+    SpillToWinner,
+    /// Las que sobran se las queda la casa.
+    #[default]
+    SpillToHouse,
+    /// No se aceptan monedas que no entran. Quien las arroja tiene que
+    /// preguntar antes con `room`; si no, se las queda la casa.
+    Reject,
+}
 
 pub struct Machine {
     pool: u32,
+    capacity: u32,
+    overflow: Overflow,
+    model: Box<dyn PayoutModel>,
+    // Parámetros de `model`, si salió de la configuración
+    payout: Option<PayoutConfig>,
+    // Si hay estante, las monedas caen según su geometría y no según `model`
+    board: Option<Board>,
+    jackpot: Option<Jackpot>,
+    items: Option<Shelf>,
+    // ChaCha da la misma secuencia en cualquier plataforma y versión de rand
+    rng: ChaCha8Rng,
+    // Monedas arrojadas con `rng` desde que se creó o se sembró
+    nonce: u64,
+    // Monedas arrojadas de cualquier forma; sigue entre reinicios
+    thrown: u64,
+}
+
+/// Lo que ganó el jugador con una moneda: las monedas que cayeron del
+/// carril `lane`, las que rebalsaron la máquina, las del pozo y el premio
+/// que cayó con ellas, si hubo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoinOutcome {
+    pub lane: u32,
+    pub fell: u32,
+    pub spilled: u32,
+    pub jackpot: u32,
+    pub item: Option<Item>,
+}
+
+/// Moneda arrojada con azar externo. `probability` es la que usó el modelo
+/// de pago con `pool` monedas; las máquinas con estante no tienen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeededCoin {
+    pub outcome: CoinOutcome,
+    pub pool: u32,
+    pub probability: Option<f64>,
+}
+
+/// Estado de la máquina que se guarda entre reinicios.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MachineSnapshot {
+    pool: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    board: Option<BoardSnapshot>,
+    /// Pozo acumulado, en milésimos de moneda.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jackpot: Option<u64>,
+    /// Premios en el estante, del más cercano al borde al más nuevo.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    items: Vec<Item>,
+    /// Monedas arrojadas en la máquina desde que se creó.
+    #[serde(default)]
+    thrown: u64,
+}
+
+impl MachineSnapshot {
+    pub fn pool(&self) -> u32 {
+        self.pool
+    }
+
+    /// Monedas en el estante, si la máquina tiene uno.
+    pub fn board_coins(&self) -> Option<u32> {
+        self.board.as_ref().map(BoardSnapshot::coins)
+    }
+
+    /// Pozo acumulado en milésimos de moneda, si la máquina tiene uno.
+    pub fn jackpot(&self) -> Option<u64> {
+        self.jackpot
+    }
 }
 
 impl Machine {
+    /// Máquina con el modelo de pago logístico por defecto.
     pub fn with(initial_pool: u32) -> Result<Machine, &'static str> {
-        if initial_pool > MACHINE_CAPACITY {
+        Machine::with_model(initial_pool, Box::new(Logistic::default()))
+    }
+
+    pub fn with_model(
+        initial_pool: u32,
+        model: Box<dyn PayoutModel>,
+    ) -> Result<Machine, &'static str> {
+        Machine::with_capacity(initial_pool, DEFAULT_CAPACITY, model)
+    }
+
+    pub fn with_capacity(
+        initial_pool: u32,
+        capacity: u32,
+        model: Box<dyn PayoutModel>,
+    ) -> Result<Machine, &'static str> {
+        if initial_pool > capacity {
             return Err("too many coins");
         }
 
-        Ok(Machine { pool: initial_pool })
+        Ok(Machine {
+            pool: initial_pool,
+            capacity,
+            overflow: Overflow::default(),
+            model,
+            payout: None,
+            board: None,
+            jackpot: None,
+            items: None,
+            rng: ChaCha8Rng::from_seed(rand::thread_rng().gen()),
+            nonce: 0,
+            thrown: 0,
+        })
+    }
+
+    /// Máquina con estante, con `initial_pool` monedas repartidas en él.
+    pub fn with_board(config: BoardConfig, initial_pool: u32) -> Result<Machine, &'static str> {
+        let board = Board::with(config, initial_pool)?;
+        Machine::with(initial_pool)?.on_board(board)
+    }
+
+    fn on_board(mut self, board: Board) -> Result<Machine, &'static str> {
+        self.pool = board.coins();
+        if self.pool > self.capacity {
+            return Err("too many coins");
+        }
+        self.board = Some(board);
+        Ok(self)
+    }
+
+    /// Máquina con el modelo de pago y la semilla de `config`.
+    /// Si hay snapshot, el pool sale de ahí en lugar de `initial_coins_count`.
+    /// Antes revisa que la combinación de parámetros tenga sentido.
+    pub fn from_config(
+        config: &MachineConfig,
+        snapshot: Option<&MachineSnapshot>,
+    ) -> Result<Machine, String> {
+        config.check()?;
+        let pool = snapshot.map_or(config.initial_coins_count(), |s| s.pool);
+        if pool > config.capacity() {
+            return Err(format!(
+                "the snapshot has {} coins but capacity is {}",
+                pool,
+                config.capacity()
+            ));
+        }
+
+        let mut machine = Machine::with_capacity(pool, config.capacity(), config.payout().build())?
+            .with_overflow(config.overflow());
+        machine.payout = Some(config.payout().clone());
+        if let Some(board) = config.board() {
+            let board = match snapshot.and_then(|s| s.board.as_ref()) {
+                Some(cells) => Board::restore(*board, cells)?,
+                // No hay snapshot o es de antes de tener estante: se reparte el pool
+                None => Board::with(*board, pool)?,
+            };
+            machine = machine.on_board(board)?;
+        }
+        if let Some(jackpot) = config.jackpot() {
+            let value = snapshot.and_then(|s| s.jackpot).unwrap_or(0);
+            machine = machine.with_jackpot(Jackpot::with(*jackpot, value));
+        }
+        if let Some(items) = config.items() {
+            let held = snapshot.map_or(&[][..], |s| &s.items);
+            machine = machine.with_items(Shelf::with(*items, held.iter().copied()));
+        }
+        if let Some(seed) = config.seed() {
+            machine = machine.seeded(seed);
+        }
+        machine.thrown = snapshot.map_or(0, |s| s.thrown);
+        Ok(machine)
+    }
+
+    /// Esta misma máquina, con sus monedas, estante, pozo y premios, pero
+    /// con los parámetros de `config`. El generador aleatorio sigue donde
+    /// estaba, así los números de moneda del registro de auditoría siguen.
+    pub fn retuned(&self, config: &MachineConfig) -> Result<Machine, String> {
+        let mut machine = Machine::from_config(config, Some(&self.snapshot()))?;
+        machine.rng = self.rng.clone();
+        machine.nonce = self.nonce;
+        Ok(machine)
+    }
+
+    pub fn with_overflow(mut self, overflow: Overflow) -> Machine {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn with_jackpot(mut self, jackpot: Jackpot) -> Machine {
+        self.jackpot = Some(jackpot);
+        self
+    }
+
+    pub fn with_items(mut self, items: Shelf) -> Machine {
+        self.items = Some(items);
+        self
+    }
+
+    /// Reemplaza el generador aleatorio por uno que arranca de `seed`,
+    /// así la misma secuencia de monedas da siempre los mismos resultados.
+    pub fn seeded(mut self, seed: u64) -> Machine {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self.nonce = 0;
+        self
+    }
+
+    /// El modelo de pago no forma parte del snapshot: sale de la configuración.
+    /// El estante tampoco se restaura acá, porque sus dimensiones salen de ahí.
+    pub fn restore(
+        snapshot: &MachineSnapshot,
+        model: Box<dyn PayoutModel>,
+    ) -> Result<Machine, &'static str> {
+        let mut machine = Machine::with_model(snapshot.pool, model)?;
+        machine.thrown = snapshot.thrown;
+        Ok(machine)
+    }
+
+    pub fn snapshot(&self) -> MachineSnapshot {
+        MachineSnapshot {
+            pool: self.pool,
+            board: self.board.as_ref().map(Board::snapshot),
+            jackpot: self.jackpot.as_ref().map(Jackpot::value),
+            items: self.items.iter().flat_map(Shelf::held).collect(),
+            thrown: self.thrown,
+        }
     }
 
     pub fn get_pool(&self) -> u32 {
         self.pool
     }
 
-    pub fn insert_coin(&mut self) -> u32 {
-        self.pool += 1;
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
 
-        let rng: f64 = rand::thread_rng().gen_range(0.0_f64..=1.0_f64);
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
 
-        if rng < self.p() {
-            let fell = self.f(rng);
-            self.pool -= fell;
-            fell
-        } else {
-            0
+    /// Cuántas monedas acepta en una jugada: con `Reject`, las que entran
+    /// aunque no caiga ninguna. Llena igual acepta una, porque si no no
+    /// volvería a pagar. Un estante acepta todas: las que no entran caen solas.
+    pub fn room(&self) -> u32 {
+        if self.overflow != Overflow::Reject || self.board.is_some() {
+            return u32::MAX;
         }
+        self.capacity.saturating_sub(self.pool).max(1)
     }
 
-    // probabilidad de que caigan monedas
-    // ver función Heaviside o escalón
-    fn p(&self) -> f64 {
-        // stepness
-        let k: f64 = 0.02;
+    /// Parámetros del modelo de pago, si la máquina salió de la configuración.
+    /// En un estante no deciden cuántas monedas caen.
+    pub fn payout(&self) -> Option<&PayoutConfig> {
+        self.payout.as_ref()
+    }
+
+    pub fn board(&self) -> Option<&Board> {
+        self.board.as_ref()
+    }
+
+    pub fn jackpot(&self) -> Option<&Jackpot> {
+        self.jackpot.as_ref()
+    }
 
-        // threshhold
-        let t: f64 = 700.0;
+    pub fn items(&self) -> Option<&Shelf> {
+        self.items.as_ref()
+    }
 
-        let n: f64 = f64::from(self.pool);
+    /// Número de la próxima moneda que se arroja con el generador de la
+    /// máquina. Junto con la semilla identifica su azar.
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
 
-        1.0_f64 / (1.0_f64 + (-k * (n - t)).exp())
+    /// Número de la próxima moneda que se arroja, con el generador de la
+    /// máquina o con semillas. Da el orden de las monedas en el registro
+    /// de auditoría.
+    pub fn thrown(&self) -> u64 {
+        self.thrown
     }
 
-    // cantidad de monedas que caen
-    fn f(&self, rng: f64) -> u32 {
-        let n: f64 = f64::from(self.pool);
+    /// En una máquina con estante la moneda cae en un carril al azar.
+    pub fn insert_coin(&mut self) -> CoinOutcome {
+        if let Some(width) = self.board.as_ref().map(|board| board.config().width()) {
+            let lane = self.rng.gen_range(0..width);
+            return self.insert_coin_at(lane);
+        }
 
-        // si bien es una función lineal,
-        // rng tiene como tope p() lo cual genera esa dependencia
-        let r = (0.05_f64 * rng * n).ceil();
+        let rng: f64 = self.rng.gen_range(0.0_f64..=1.0_f64);
+        self.nonce += 1;
+        self.thrown += 1;
+        let fell = self.insert_with_roll(rng);
+        let spilled = self.spill();
+        let drop = CoinDrop { lane: 0, fell };
+        CoinOutcome {
+            spilled,
+            ..prizes(drop, &mut self.jackpot, &mut self.items, &mut self.rng)
+        }
+    }
 
-        let r = r as u32;
+    fn insert_with_roll(&mut self, rng: f64) -> u32 {
+        self.pool += 1;
 
-        if r >= 1 {
-            r
+        if rng < self.model.probability(self.pool) {
+            // No pueden caer más monedas de las que hay
+            let fell = self.model.payout(self.pool, rng).min(self.pool);
+            self.pool -= fell;
+            fell
         } else {
-            1
+            0
         }
     }
+
+    /// Saca las monedas que pasan la capacidad después de una moneda que
+    /// no pagó lo suficiente. Devuelve las que le tocan al jugador.
+    fn spill(&mut self) -> u32 {
+        let excess = self.pool.saturating_sub(self.capacity);
+        self.pool -= excess;
+        match self.overflow {
+            Overflow::SpillToWinner => excess,
+            Overflow::SpillToHouse | Overflow::Reject => 0,
+        }
+    }
+
+    /// Arroja una moneda con el azar de `coin_seed` en lugar del generador
+    /// de la máquina. Sin estante alcanza con `fairness::roll`, así el
+    /// jugador puede recalcular si la moneda tenía que pagar.
+    /// Sin `lane`, en un estante la moneda cae en un carril al azar.
+    pub fn insert_seeded_coin(&mut self, lane: Option<u32>, coin_seed: &[u8; 32]) -> SeededCoin {
+        let mut rng = ChaCha8Rng::from_seed(*coin_seed);
+        self.thrown += 1;
+        let (drop, spilled, pool, probability) = match &mut self.board {
+            Some(board) => {
+                let lane = lane.unwrap_or_else(|| rng.gen_range(0..board.config().width()));
+                let drop = board.drop_coin(lane, &mut rng);
+                self.pool = board.coins();
+                (drop, 0, self.pool, None)
+            }
+            None => {
+                let pool = self.pool + 1;
+                let probability = self.model.probability(pool);
+                let fell = self.insert_with_roll(fairness::roll(coin_seed));
+                let lane = lane.unwrap_or(0);
+                (
+                    CoinDrop { lane, fell },
+                    self.spill(),
+                    pool,
+                    Some(probability),
+                )
+            }
+        };
+        // Los premios se sortean con el mismo azar, que sigue después del de la moneda
+        SeededCoin {
+            outcome: CoinOutcome {
+                spilled,
+                ..prizes(drop, &mut self.jackpot, &mut self.items, &mut rng)
+            },
+            pool,
+            probability,
+        }
+    }
+
+    /// Arroja una moneda sobre el carril `lane` del estante.
+    /// En una máquina sin estante el carril no importa.
+    pub fn insert_coin_at(&mut self, lane: u32) -> CoinOutcome {
+        let Some(board) = &mut self.board else {
+            return CoinOutcome {
+                lane,
+                ..self.insert_coin()
+            };
+        };
+        let drop = board.drop_coin(lane, &mut self.rng);
+        self.pool = board.coins();
+        self.nonce += 1;
+        self.thrown += 1;
+        prizes(drop, &mut self.jackpot, &mut self.items, &mut self.rng)
+    }
+
+    /// Arroja `n` monedas, una detrás de otra.
+    /// Devuelve lo que ganó cada una.
+    pub fn insert_coins(&mut self, n: u32) -> Vec<CoinOutcome> {
+        (0..n).map(|_| self.insert_coin()).collect()
+    }
+}
+
+/// Sortea el pozo y los premios del estante después de una moneda, en ese
+/// orden. Lo que la máquina no tiene no consume azar, así las semillas
+/// repiten lo de antes.
+fn prizes(
+    drop: CoinDrop,
+    jackpot: &mut Option<Jackpot>,
+    items: &mut Option<Shelf>,
+    rng: &mut ChaCha8Rng,
+) -> CoinOutcome {
+    CoinOutcome {
+        lane: drop.lane,
+        fell: drop.fell,
+        spilled: 0,
+        jackpot: jackpot
+            .as_mut()
+            .map_or(0, |jackpot| jackpot.play(rng.gen())),
+        item: items
+            .as_mut()
+            .and_then(|items| items.play(drop.fell, rng.gen())),
+    }
 }
 
 #[cfg(test)]
 mod machine_tests {
     use super::*;
+    use crate::config::FileConfig;
+    use crate::items::ItemsConfig;
+    use crate::jackpot::{JackpotConfig, JACKPOT_SCALE};
+    use crate::server::traits::Config;
+    use common::payout::{FixedRtp, Step};
+    use std::io::Cursor;
+
+    fn machine_config(extra: &str) -> MachineConfig {
+        let cursor = Cursor::new(format!(
+            "port=8080
+host=localhost
+initial_coins_count=10
+{}",
+            extra
+        ));
+        FileConfig::new_from_file(cursor).unwrap().machines()[0].clone()
+    }
 
     #[test]
     fn create_machine_with_max_coins() {
-        let m = Machine::with(MACHINE_CAPACITY);
+        let m = Machine::with(DEFAULT_CAPACITY);
 
         assert!(m.is_ok());
-        assert_eq!(m.unwrap().get_pool(), MACHINE_CAPACITY);
+        assert_eq!(m.unwrap().get_pool(), DEFAULT_CAPACITY);
     }
 
     #[test]
     fn create_machine_with_too_many_coins() {
-        let m = Machine::with(MACHINE_CAPACITY + 1);
+        let m = Machine::with(DEFAULT_CAPACITY + 1);
 
         assert!(m.is_err());
     }
 
     #[test]
-    fn prob_with_one_coin() {
-        let m = Machine::with(1).unwrap();
+    fn restore_from_snapshot() {
+        let mut m = Machine::with(100).unwrap();
+        m.insert_coins(10);
+
+        let restored = Machine::restore(&m.snapshot(), Box::new(Logistic::default())).unwrap();
+
+        assert_eq!(restored.get_pool(), m.get_pool());
+    }
+
+    #[test]
+    fn restore_from_invalid_snapshot() {
+        let snapshot = MachineSnapshot {
+            pool: DEFAULT_CAPACITY + 1,
+            board: None,
+            jackpot: None,
+            items: Vec::new(),
+            thrown: 0,
+        };
+
+        assert!(Machine::restore(&snapshot, Box::new(Logistic::default())).is_err());
+    }
+
+    #[test]
+    fn insert_many_coins() {
+        let mut m = Machine::with(100).unwrap();
+
+        let outcomes = m.insert_coins(50);
+        let fell: u32 = outcomes.iter().map(|outcome| outcome.fell).sum();
+
+        assert_eq!(outcomes.len(), 50);
+        assert_eq!(m.get_pool(), 100 + 50 - fell);
+    }
+
+    #[test]
+    fn same_seed_same_outcomes() {
+        let mut a = Machine::with(700).unwrap().seeded(42);
+        let mut b = Machine::with(700).unwrap().seeded(42);
+
+        assert_eq!(a.insert_coins(200), b.insert_coins(200));
+        assert_eq!(a.get_pool(), b.get_pool());
+    }
+
+    #[test]
+    fn different_seeds_different_outcomes() {
+        let mut a = Machine::with(700).unwrap().seeded(1);
+        let mut b = Machine::with(700).unwrap().seeded(2);
 
-        let r = m.p();
+        assert_ne!(a.insert_coins(200), b.insert_coins(200));
+    }
+
+    #[test]
+    fn payout_never_exceeds_pool() {
+        let model = FixedRtp::new(50.0, 1.0).unwrap();
+        let mut m = Machine::with_model(10, Box::new(model)).unwrap();
 
-        // Si solo hay una moneda, la prob de que caigan
-        // debe ser menor a 1 en 1 millon
-        assert!(r < 0.000001_f64);
+        assert_eq!(m.insert_coin().fell, 11);
+        assert_eq!(m.get_pool(), 0);
     }
 
     #[test]
-    fn prob_with_max_coins() {
-        let m = Machine::with(MACHINE_CAPACITY).unwrap();
+    fn board_machine_pool_follows_the_board() {
+        let mut m = Machine::with_board(BoardConfig::new(3, 2, 10, 1).unwrap(), 50)
+            .unwrap()
+            .seeded(7);
+
+        for lane in 0..30 {
+            let drop = m.insert_coin_at(lane % 3);
+            assert!(drop.lane < 3);
+        }
+        m.insert_coins(30);
+
+        assert_eq!(m.get_pool(), m.board().unwrap().coins());
+    }
+
+    #[test]
+    fn board_machine_too_large_for_the_machine() {
+        let config = BoardConfig::new(10, 10, 20, 1).unwrap();
+
+        assert!(Machine::with_board(config, DEFAULT_CAPACITY + 1).is_err());
+    }
+
+    #[test]
+    fn same_seed_same_board() {
+        let config = BoardConfig::default();
+        let mut a = Machine::with_board(config, 300).unwrap().seeded(3);
+        let mut b = Machine::with_board(config, 300).unwrap().seeded(3);
+
+        assert_eq!(a.insert_coins(500), b.insert_coins(500));
+        assert_eq!(a.board(), b.board());
+    }
+
+    #[test]
+    fn seeded_coin_follows_the_roll() {
+        let model = FixedRtp::new(1.0, 0.5).unwrap();
+        let mut m = Machine::with_model(100, Box::new(model)).unwrap();
+
+        for nonce in 0..50 {
+            let coin_seed = fairness::coin_seed(&[1; 32], "client", nonce);
+            let coin = m.insert_seeded_coin(None, &coin_seed);
+
+            assert_eq!(coin.probability, Some(0.5));
+            assert_eq!(coin.outcome.fell > 0, fairness::roll(&coin_seed) < 0.5);
+        }
+    }
 
-        let r = m.p();
+    #[test]
+    fn seeded_coin_ignores_the_machine_rng() {
+        let coin_seed = fairness::coin_seed(&[1; 32], "client", 0);
+        let mut a = Machine::with_board(BoardConfig::default(), 600)
+            .unwrap()
+            .seeded(1);
+        let mut b = Machine::with_board(BoardConfig::default(), 600)
+            .unwrap()
+            .seeded(2);
+
+        assert_eq!(
+            a.insert_seeded_coin(Some(3), &coin_seed),
+            b.insert_seeded_coin(Some(3), &coin_seed)
+        );
+        assert_eq!(a.board(), b.board());
+    }
+
+    #[test]
+    fn nonce_counts_coins_from_the_machine_rng() {
+        let mut curve = Machine::with(100).unwrap();
+        let mut board = Machine::with_board(BoardConfig::default(), 100).unwrap();
+        let coin_seed = fairness::coin_seed(&[1; 32], "client", 0);
+
+        for machine in [&mut curve, &mut board] {
+            machine.insert_coins(3);
+            machine.insert_coin_at(0);
+            machine.insert_seeded_coin(None, &coin_seed);
+            assert_eq!(machine.nonce(), 4);
+        }
+        assert_eq!(curve.seeded(7).nonce(), 0);
+    }
+
+    #[test]
+    fn jackpot_survives_a_snapshot() {
+        let config = JackpotConfig::new(0.5, 0.000001).unwrap();
+        let mut machine = Machine::with(100)
+            .unwrap()
+            .with_jackpot(Jackpot::with(config, 0))
+            .seeded(1);
+        machine.insert_coins(10);
+        assert_eq!(machine.jackpot().unwrap().coins(), 5);
+
+        let snapshot = machine.snapshot();
+        let restored = Machine::restore(&snapshot, Box::new(Logistic::default()))
+            .unwrap()
+            .with_jackpot(Jackpot::with(config, snapshot.jackpot.unwrap()));
+        assert_eq!(restored.jackpot(), machine.jackpot());
+    }
+
+    #[test]
+    fn seeded_coin_draws_the_jackpot() {
+        let config = JackpotConfig::new(1.0, 1.0).unwrap();
+        let mut machine = Machine::with(100)
+            .unwrap()
+            .with_jackpot(Jackpot::with(config, 4 * JACKPOT_SCALE));
+        let coin_seed = fairness::coin_seed(&[1; 32], "client", 0);
+
+        let coin = machine.insert_seeded_coin(None, &coin_seed);
+        assert_eq!(coin.outcome.jackpot, 5);
+        assert_eq!(machine.jackpot().unwrap().coins(), 0);
+    }
+
+    #[test]
+    fn items_survive_a_snapshot() {
+        let config = ItemsConfig::new(0.0, 0.0, 1.0).unwrap();
+        let never_pays = Step::new(1000, 1.0, 0.5).unwrap();
+        let mut machine = Machine::with_model(10, Box::new(never_pays))
+            .unwrap()
+            .with_items(Shelf::with(config, []));
+        machine.insert_coins(3);
+
+        let snapshot = machine.snapshot();
+        assert_eq!(snapshot.items, [Item::Badge; 3]);
+
+        let always_pays = FixedRtp::new(50.0, 1.0).unwrap();
+        let mut restored = Machine::restore(&snapshot, Box::new(always_pays))
+            .unwrap()
+            .with_items(Shelf::with(config, snapshot.items.iter().copied()));
+        assert_eq!(restored.insert_coin().item, Some(Item::Badge));
+        assert_eq!(restored.items().unwrap().held().count(), 3);
+    }
+
+    #[test]
+    fn overflow_spills_to_the_winner_or_the_house() {
+        let never_pays = || Box::new(Step::new(1000, 1.0, 0.5).unwrap());
+        let mut winner = Machine::with_capacity(10, 10, never_pays())
+            .unwrap()
+            .with_overflow(Overflow::SpillToWinner);
+        let mut house = Machine::with_capacity(10, 10, never_pays()).unwrap();
+
+        assert_eq!(winner.insert_coin().spilled, 1);
+        assert_eq!(house.insert_coin().spilled, 0);
+        assert_eq!((winner.get_pool(), house.get_pool()), (10, 10));
+    }
+
+    #[test]
+    fn reject_limits_the_coins_per_play() {
+        let never_pays = || Box::new(Step::new(1000, 1.0, 0.5).unwrap());
+        let mut m = Machine::with_capacity(7, 10, never_pays())
+            .unwrap()
+            .with_overflow(Overflow::Reject);
+        assert_eq!(m.room(), 3);
+
+        m.insert_coins(3);
+        // Llena igual acepta de a una moneda
+        assert_eq!(m.room(), 1);
+        assert_eq!(
+            Machine::with_capacity(7, 10, never_pays()).unwrap().room(),
+            u32::MAX
+        );
+    }
+
+    #[test]
+    fn every_coin_is_counted_across_snapshots() {
+        let config = machine_config("kind=board");
+        let mut m = Machine::from_config(&config, None).unwrap();
+        m.insert_coin();
+        m.insert_coin_at(1);
+        m.insert_seeded_coin(None, &[7; 32]);
+
+        let restored = Machine::from_config(&config, Some(&m.snapshot())).unwrap();
+
+        assert_eq!(m.thrown(), 3);
+        assert_eq!(restored.thrown(), 3);
+    }
+
+    #[test]
+    fn retuned_machine_keeps_its_coins_and_randomness() {
+        let config = machine_config("jackpot_contribution=0.5\nseed=7");
+        let mut m = Machine::from_config(&config, None).unwrap();
+        m.insert_coins(4);
+
+        let retuned = m
+            .retuned(&machine_config(
+                "jackpot_contribution=0.5\nseed=7\ncapacity=2000\noverflow=reject",
+            ))
+            .unwrap();
+        assert_eq!(retuned.get_pool(), m.get_pool());
+        assert_eq!(
+            retuned.jackpot().map(Jackpot::value),
+            Some(2 * JACKPOT_SCALE)
+        );
+        assert_eq!(
+            (retuned.capacity(), retuned.overflow()),
+            (2000, Overflow::Reject)
+        );
+        assert_eq!(retuned.nonce(), 4);
+
+        // El generador no vuelve a arrancar de la semilla
+        let mut same = m.retuned(&config).unwrap();
+        assert_eq!(same.insert_coins(20), m.insert_coins(20));
+    }
+
+    #[test]
+    fn retuning_fails_if_the_coins_do_not_fit() {
+        let mut m =
+            Machine::from_config(&machine_config("payout_model=step\nthreshold=1000"), None)
+                .unwrap();
+        m.insert_coins(20);
 
-        // Si las monedas estan al máximo, la prob de que caigan
-        // debe ser mayor a 99%
-        assert!(r > 0.99_f64);
+        let err = m
+            .retuned(&machine_config(
+                "payout_model=step\nthreshold=20\ncapacity=20",
+            ))
+            .err()
+            .unwrap();
+        assert_eq!(err, "the snapshot has 30 coins but capacity is 20");
     }
 }
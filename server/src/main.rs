@@ -1,16 +1,24 @@
-use crate::config::FileConfig;
-use crate::server::Server;
+use server::config::FileConfig;
+use server::server::Server;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
 use std::env;
 use std::io::Read;
+use std::process;
+use std::sync::Arc;
+use std::thread;
 
-mod config;
-mod machine;
-mod server;
+/// Sin este flag el servidor también se detiene con ENTER. Con él no lee
+/// stdin, así puede correr bajo systemd, en un contenedor o con nohup.
+const DAEMON_FLAG: &str = "--daemon";
 
 fn get_config_path(default_path: Option<String>) -> String {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        return String::from(&args[1]);
+    let args: Vec<String> = env::args()
+        .skip(1)
+        .filter(|arg| arg != DAEMON_FLAG)
+        .collect();
+    if let Some(path) = args.first() {
+        return path.clone();
     }
     if let Some(path) = default_path {
         return path;
@@ -18,20 +26,88 @@ fn get_config_path(default_path: Option<String>) -> String {
     panic!("Error: should provide a config file path")
 }
 
-pub fn init(config_path: &str) {
-    let config = FileConfig::new(config_path).expect("Error while reading config file");
+fn is_daemon() -> bool {
+    env::args().skip(1).any(|arg| arg == DAEMON_FLAG)
+}
+
+/// SIGINT y SIGTERM apagan el servidor; SIGHUP vuelve a leer la configuración.
+pub fn init(config_path: &str, daemon: bool) {
+    let config = FileConfig::new(config_path).unwrap_or_else(|e| {
+        eprintln!("Error while reading config file: {}", e);
+        process::exit(1);
+    });
+    let mut signals =
+        Signals::new([SIGINT, SIGTERM, SIGHUP]).expect("Error while registering signal handlers");
 
-    let server = Server::new(config);
-    let controller = server.run().expect("Error while running server");
+    let server = Server::new(config).expect("Error while creating server");
+    let mut controller = server.clone().run().expect("Error while running server");
+    // Si el servidor se detiene solo, no hay más señales que esperar
+    let handle = signals.handle();
+    controller
+        .on_stop(move || handle.close())
+        .expect("Error while watching the server");
 
-    println!("Press [ENTER] to stop the server");
+    if daemon {
+        println!("Send SIGINT or SIGTERM to stop the server, SIGHUP to reload its config");
+    } else {
+        println!("Press [ENTER] to stop the server");
+        let handle = signals.handle();
+        thread::spawn(move || {
+            let mut buf = [0u8; 1];
+            std::io::stdin().read_exact(&mut buf).unwrap_or(());
+            handle.close();
+        });
+    }
 
-    let mut buf = [0u8; 1];
-    std::io::stdin().read_exact(&mut buf).unwrap_or(());
+    for signal in signals.forever() {
+        match signal {
+            SIGHUP => reload(&server, config_path),
+            SIGINT => {
+                println!("Received SIGINT");
+                break;
+            }
+            _ => {
+                println!("Received SIGTERM");
+                break;
+            }
+        }
+    }
     drop(controller);
 }
 
+/// Aplica en vivo lo que se pueda del archivo de configuración y avisa
+/// qué cambios recién se aplican al reiniciar. Si el archivo no es válido,
+/// sigue con la configuración que tenía.
+fn reload(server: &Arc<Server<FileConfig>>, config_path: &str) {
+    println!("Reloading config from {}", config_path);
+    let config = match FileConfig::new(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid config file: {} - keeping the current config", e);
+            return;
+        }
+    };
+    let changes = match server.reload(config) {
+        Ok(changes) => changes,
+        Err(e) => {
+            eprintln!("{} - keeping the current config", e);
+            return;
+        }
+    };
+    if changes.applied.is_empty() {
+        println!("Config reloaded, nothing to apply");
+    } else {
+        println!("Config reloaded, applied: {}", changes.applied.join(", "));
+    }
+    if !changes.restart.is_empty() {
+        println!(
+            "Restart the server to apply: {}",
+            changes.restart.join(", ")
+        );
+    }
+}
+
 fn main() {
     let config_path: String = get_config_path(Some("server/resources/config.txt".to_string()));
-    init(&config_path);
+    init(&config_path, is_daemon());
 }
@@ -0,0 +1,477 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    audit::AuditEntry,
+    config::MachineConfig,
+    jackpot::{Jackpot, JACKPOT_SCALE},
+    machine::{Machine, MachineSnapshot},
+    snapshot::Snapshot,
+};
+
+/// Estado de una máquina reconstruido a partir del registro de auditoría.
+///
+/// No se vuelve a sortear ninguna moneda: se aplica lo que dice el registro.
+/// No alcanza para más, porque no guarda las semillas de las jugadas
+/// verificables, el carril que eligió cada jugador ni el estado del
+/// generador de la máquina. Así se reconstruyen el pool y el pozo
+/// (con el aporte por moneda de la configuración), pero no en qué celda del
+/// estante quedó cada moneda ni qué premios aparecieron en él.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayedMachine {
+    pool: u32,
+    board: bool,
+    jackpot: Option<u64>,
+    share: u64,
+    inserted: u64,
+    paid: u64,
+    last_timestamp: Option<u64>,
+}
+
+impl ReplayedMachine {
+    pub fn pool(&self) -> u32 {
+        self.pool
+    }
+
+    /// Pozo acumulado en milésimos de moneda, si la máquina tiene uno.
+    pub fn jackpot(&self) -> Option<u64> {
+        self.jackpot
+    }
+
+    pub fn inserted(&self) -> u64 {
+        self.inserted
+    }
+
+    pub fn paid(&self) -> u64 {
+        self.paid
+    }
+
+    /// Hora de la última moneda aplicada, en milisegundos.
+    pub fn last_timestamp(&self) -> Option<u64> {
+        self.last_timestamp
+    }
+
+    /// En un estante todas las monedas de la máquina están sobre él.
+    pub fn state(&self) -> MachineState {
+        MachineState {
+            pool: self.pool,
+            board: self.board.then_some(self.pool),
+            jackpot: self.jackpot,
+        }
+    }
+}
+
+/// Algo del registro que no cierra con el estado reconstruido.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// El pool antes de la moneda no es el que dejó la anterior, por ejemplo
+    /// porque el servidor retomó desde un snapshot viejo después de caerse.
+    Gap {
+        seq: u64,
+        machine: u32,
+        expected: u32,
+        found: u32,
+    },
+    /// Después de la moneda hay más monedas de las que pudo dejar.
+    Inconsistent {
+        seq: u64,
+        machine: u32,
+    },
+    /// La moneda se llevó más de lo que había en el pozo, o la máquina no
+    /// tiene pozo.
+    Jackpot {
+        seq: u64,
+        machine: u32,
+        available: u32,
+        won: u32,
+    },
+    UnknownMachine {
+        seq: u64,
+        machine: u32,
+    },
+}
+
+/// Lo que se puede comparar entre una máquina reconstruida y la guardada.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachineState {
+    pub pool: u32,
+    /// Monedas en el estante, si la máquina tiene uno.
+    pub board: Option<u32>,
+    /// Pozo acumulado en milésimos de moneda, si la máquina tiene uno.
+    pub jackpot: Option<u64>,
+}
+
+impl From<&MachineSnapshot> for MachineState {
+    fn from(snapshot: &MachineSnapshot) -> MachineState {
+        MachineState {
+            pool: snapshot.pool(),
+            board: snapshot.board_coins(),
+            jackpot: snapshot.jackpot(),
+        }
+    }
+}
+
+/// Diferencia entre una máquina reconstruida y la guardada en un snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Comparison {
+    pub machine: u32,
+    pub replayed: MachineState,
+    pub stored: Option<MachineState>,
+}
+
+impl Comparison {
+    pub fn matches(&self) -> bool {
+        self.stored == Some(self.replayed)
+    }
+}
+
+/// Máquinas reconstruidas moneda por moneda.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    machines: BTreeMap<u32, ReplayedMachine>,
+}
+
+impl Replay {
+    /// Las máquinas arrancan como lo haría el servidor: desde `base` si la
+    /// incluye, o con sus `initial_coins_count` monedas.
+    pub fn new(configs: &[MachineConfig], base: Option<&Snapshot>) -> Result<Replay, String> {
+        let machines = configs
+            .iter()
+            .map(|config| {
+                let id = config.id();
+                let machine = Machine::from_config(config, base.and_then(|s| s.machine(id)))
+                    .map_err(|e| format!("Machine {}: {}", id, e))?;
+                let replayed = ReplayedMachine {
+                    pool: machine.get_pool(),
+                    board: machine.board().is_some(),
+                    jackpot: machine.jackpot().map(Jackpot::value),
+                    share: machine
+                        .jackpot()
+                        .map_or(0, |jackpot| jackpot.config().share()),
+                    inserted: 0,
+                    paid: 0,
+                    last_timestamp: None,
+                };
+                Ok((id, replayed))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Replay { machines })
+    }
+
+    pub fn machine(&self, id: u32) -> Option<&ReplayedMachine> {
+        self.machines.get(&id)
+    }
+
+    pub fn machines(&self) -> impl Iterator<Item = (u32, &ReplayedMachine)> {
+        self.machines.iter().map(|(id, machine)| (*id, machine))
+    }
+
+    /// Aplica una moneda. Si no cierra con el estado, igual se aplica
+    /// (el registro manda) y se devuelve la discrepancia.
+    pub fn apply(&mut self, entry: &AuditEntry) -> Option<Discrepancy> {
+        let event = &entry.event;
+        let (seq, id) = (entry.seq, event.machine);
+        let Some(machine) = self.machines.get_mut(&id) else {
+            return Some(Discrepancy::UnknownMachine { seq, machine: id });
+        };
+
+        let mut discrepancy = None;
+        if event.pool_before != machine.pool {
+            discrepancy = Some(Discrepancy::Gap {
+                seq,
+                machine: id,
+                expected: machine.pool,
+                found: event.pool_before,
+            });
+        }
+        // En un estante también se pierden monedas por los costados, y la
+        // casa se queda las que rebalsan
+        let left = u64::from(event.pool_after) + u64::from(event.fell) + u64::from(event.spilled);
+        if left > u64::from(event.pool_before) + 1 {
+            discrepancy = discrepancy.or(Some(Discrepancy::Inconsistent { seq, machine: id }));
+        }
+        // Cada moneda aporta al pozo, gane o no
+        let won = u64::from(event.jackpot) * JACKPOT_SCALE;
+        let available = machine.jackpot.map_or(0, |value| value + machine.share);
+        if won > available {
+            discrepancy = discrepancy.or(Some(Discrepancy::Jackpot {
+                seq,
+                machine: id,
+                available: (available / JACKPOT_SCALE) as u32,
+                won: event.jackpot,
+            }));
+        }
+        if let Some(value) = &mut machine.jackpot {
+            *value = available.saturating_sub(won);
+        }
+
+        machine.pool = event.pool_after;
+        machine.inserted += 1;
+        machine.paid += u64::from(event.fell) + u64::from(event.spilled);
+        machine.last_timestamp = Some(event.timestamp);
+        discrepancy
+    }
+
+    /// Aplica las monedas arrojadas hasta `until` (en milisegundos), o todas.
+    pub fn apply_all<'a>(
+        &mut self,
+        entries: impl IntoIterator<Item = &'a AuditEntry>,
+        until: Option<u64>,
+    ) -> Vec<Discrepancy> {
+        let entries = entries
+            .into_iter()
+            .filter(|entry| until.is_none_or(|until| entry.event.timestamp <= until));
+        in_machine_order(entries)
+            .into_iter()
+            .filter_map(|entry| self.apply(entry))
+            .collect()
+    }
+
+    /// Compara cada máquina reconstruida con la del snapshot: el pool, las
+    /// monedas del estante y el pozo.
+    pub fn compare(&self, snapshot: &Snapshot) -> Vec<Comparison> {
+        self.machines()
+            .map(|(id, machine)| Comparison {
+                machine: id,
+                replayed: machine.state(),
+                stored: snapshot.machine(id).map(MachineState::from),
+            })
+            .collect()
+    }
+}
+
+/// Las monedas de cada máquina en el orden en que se arrojaron, según su
+/// `machine_seq`. Las que llegan antes de tiempo esperan a las anteriores.
+/// Si el número vuelve atrás, el servidor arrancó de nuevo: lo que esperaba
+/// se aplica como está y se empieza a contar desde ahí.
+fn in_machine_order<'a>(entries: impl IntoIterator<Item = &'a AuditEntry>) -> Vec<&'a AuditEntry> {
+    let mut ordered = Vec::new();
+    // Por máquina, el próximo número y las monedas que llegaron antes
+    let mut machines: BTreeMap<u32, (u64, BTreeMap<u64, &AuditEntry>)> = BTreeMap::new();
+    for entry in entries {
+        let seq = entry.event.machine_seq;
+        let (next, early) = machines
+            .entry(entry.event.machine)
+            .or_insert((seq, BTreeMap::new()));
+        if seq < *next || early.contains_key(&seq) {
+            ordered.extend(std::mem::take(early).into_values());
+            *next = seq;
+        }
+        if seq > *next {
+            early.insert(seq, entry);
+            continue;
+        }
+        ordered.push(entry);
+        *next += 1;
+        while let Some(entry) = early.remove(next) {
+            ordered.push(entry);
+            *next += 1;
+        }
+    }
+    for (_, early) in machines.into_values() {
+        ordered.extend(early.into_values());
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod replay_tests {
+    use super::*;
+    use crate::audit::{CoinEvent, GENESIS_HASH};
+    use crate::config::FileConfig;
+    use crate::server::traits::Config;
+    use std::io::Cursor;
+
+    fn config() -> FileConfig {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=100
+machines=1,2
+machine.2.initial_coins_count=500",
+        );
+        FileConfig::new_from_file(cursor).unwrap()
+    }
+
+    fn entry(seq: u64, machine: u32, pool_before: u32, fell: u32) -> AuditEntry {
+        AuditEntry {
+            seq,
+            event: CoinEvent {
+                timestamp: 1000 + seq,
+                connection: "127.0.0.1:4000".to_string(),
+                session: 1,
+                player: "ana".to_string(),
+                machine,
+                pool_before,
+                pool_after: pool_before + 1 - fell,
+                fell,
+                spilled: 0,
+                jackpot: 0,
+                item: None,
+                nonce: seq,
+                fair: false,
+                machine_seq: seq,
+            },
+            prev_hash: GENESIS_HASH.to_string(),
+            hash: GENESIS_HASH.to_string(),
+        }
+    }
+
+    #[test]
+    fn replays_every_machine() {
+        let mut replay = Replay::new(config().machines(), None).unwrap();
+        let entries = [
+            entry(0, 1, 100, 0),
+            entry(1, 2, 500, 20),
+            entry(2, 1, 101, 5),
+        ];
+
+        let discrepancies = replay.apply_all(&entries, None);
+
+        assert!(discrepancies.is_empty());
+        let one = replay.machine(1).unwrap();
+        assert_eq!((one.pool(), one.inserted(), one.paid()), (97, 2, 5));
+        assert_eq!(one.last_timestamp(), Some(1002));
+        assert_eq!(replay.machine(2).unwrap().pool(), 481);
+    }
+
+    #[test]
+    fn replays_each_machine_in_coin_order() {
+        let mut replay = Replay::new(config().machines(), None).unwrap();
+        let mut entries = [
+            entry(0, 1, 100, 0),
+            entry(1, 1, 102, 0),
+            entry(2, 1, 101, 0),
+            // El servidor arrancó de nuevo
+            entry(3, 1, 103, 0),
+        ];
+        entries[1].event.machine_seq = 2;
+        entries[2].event.machine_seq = 1;
+        entries[3].event.machine_seq = 0;
+
+        let discrepancies = replay.apply_all(&entries, None);
+
+        assert!(discrepancies.is_empty());
+        assert_eq!(replay.machine(1).unwrap().pool(), 104);
+        assert_eq!(replay.machine(1).unwrap().last_timestamp(), Some(1003));
+    }
+
+    #[test]
+    fn replays_until_a_timestamp() {
+        let mut replay = Replay::new(config().machines(), None).unwrap();
+        let entries: Vec<AuditEntry> = (0..10).map(|i| entry(i, 1, 100 + i as u32, 0)).collect();
+
+        replay.apply_all(&entries, Some(1003));
+
+        assert_eq!(replay.machine(1).unwrap().pool(), 104);
+    }
+
+    #[test]
+    fn reports_gaps_and_inconsistencies() {
+        let mut replay = Replay::new(config().machines(), None).unwrap();
+        let mut inconsistent = entry(2, 1, 91, 0);
+        inconsistent.event.pool_after = 110;
+        let entries = [
+            entry(0, 1, 100, 0),
+            entry(1, 1, 90, 0),
+            inconsistent,
+            entry(3, 7, 100, 0),
+        ];
+
+        let discrepancies = replay.apply_all(&entries, None);
+
+        assert_eq!(
+            discrepancies,
+            vec![
+                Discrepancy::Gap {
+                    seq: 1,
+                    machine: 1,
+                    expected: 101,
+                    found: 90
+                },
+                Discrepancy::Inconsistent { seq: 2, machine: 1 },
+                Discrepancy::UnknownMachine { seq: 3, machine: 7 },
+            ]
+        );
+        assert_eq!(replay.machine(1).unwrap().pool(), 110);
+    }
+
+    #[test]
+    fn replays_the_jackpot() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=100
+machines=1
+machine.1.jackpot_contribution=0.25",
+        );
+        let config = FileConfig::new_from_file(cursor).unwrap();
+        let mut replay = Replay::new(config.machines(), None).unwrap();
+        let mut entries: Vec<AuditEntry> = (0..6).map(|i| entry(i, 1, 100 + i as u32, 0)).collect();
+        // La cuarta moneda junta una moneda entera en el pozo y se la lleva
+        entries[3].event.jackpot = 1;
+        entries[4].event.jackpot = 1;
+
+        let discrepancies = replay.apply_all(&entries, None);
+
+        assert_eq!(
+            discrepancies,
+            vec![Discrepancy::Jackpot {
+                seq: 4,
+                machine: 1,
+                available: 0,
+                won: 1
+            }]
+        );
+        assert_eq!(replay.machine(1).unwrap().jackpot(), Some(250));
+    }
+
+    #[test]
+    fn compare_board_and_jackpot() {
+        let cursor = Cursor::new(
+            "port=8080
+host=localhost
+initial_coins_count=100
+machines=1
+machine.1.kind=board
+machine.1.jackpot_contribution=0.5",
+        );
+        let config = FileConfig::new_from_file(cursor).unwrap();
+        let machine = Machine::from_config(&config.machines()[0], None).unwrap();
+        let mut replay = Replay::new(config.machines(), None).unwrap();
+        assert!(
+            replay.compare(&Snapshot::new(BTreeMap::from([(1, machine.snapshot())])))[0].matches()
+        );
+
+        replay.apply_all(&[entry(0, 1, 100, 0)], None);
+        let stored: MachineSnapshot =
+            serde_json::from_str(r#"{"pool":101,"jackpot":700}"#).unwrap();
+        let comparison = replay.compare(&Snapshot::new(BTreeMap::from([(1, stored)])))[0];
+
+        assert!(!comparison.matches());
+        assert_eq!(
+            comparison.replayed,
+            MachineState {
+                pool: 101,
+                board: Some(101),
+                jackpot: Some(500)
+            }
+        );
+        let stored = comparison.stored.unwrap();
+        assert_eq!((stored.board, stored.jackpot), (None, Some(700)));
+    }
+
+    #[test]
+    fn compare_with_snapshot() {
+        let config = config();
+        let base = Snapshot::new(BTreeMap::from([(1, Machine::with(40).unwrap().snapshot())]));
+        let mut replay = Replay::new(config.machines(), Some(&base)).unwrap();
+        replay.apply_all(&[entry(0, 1, 40, 0)], None);
+
+        let stored = Snapshot::new(BTreeMap::from([(1, Machine::with(41).unwrap().snapshot())]));
+        let comparisons = replay.compare(&stored);
+
+        assert!(comparisons[0].matches());
+        assert_eq!(comparisons[1].stored, None);
+        assert!(!comparisons[1].matches());
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    net::{Shutdown, SocketAddr},
+    sync::{Arc, Mutex},
+};
+
+use common::protocol::{ProtocolErrorKind, ServerMessage, Wire};
+use mio::net::TcpStream;
+
+use crate::server::server_error::{ServerError, ServerErrorKind};
+use crate::server::ServerResult;
+
+/// Bytes sin enviar que se le toleran a un cliente que no lee antes de cortarlo.
+const MAX_PENDING_BYTES: usize = 1024 * 1024;
+
+/// Extremo de escritura de una conexión. El socket no bloquea: lo que no
+/// entra en el momento queda pendiente hasta que el loop de eventos avise
+/// que hay lugar y llame a `flush`.
+pub struct Outbox {
+    stream: Arc<TcpStream>,
+    pending: Mutex<Vec<u8>>,
+}
+
+impl Outbox {
+    pub fn new(stream: Arc<TcpStream>) -> Outbox {
+        Outbox {
+            stream,
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Encola `bytes` detrás de lo pendiente y escribe lo que se pueda.
+    pub fn write(&self, bytes: &[u8]) -> ServerResult<()> {
+        let mut pending = self.pending.lock()?;
+        pending.extend_from_slice(bytes);
+        self.write_pending(&mut pending)?;
+        if pending.len() > MAX_PENDING_BYTES {
+            pending.clear();
+            self.close();
+            return Err(ServerError::new_kind(
+                "Client is not reading its messages",
+                ServerErrorKind::ClientDisconnected,
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn flush(&self) -> ServerResult<()> {
+        let mut pending = self.pending.lock()?;
+        self.write_pending(&mut pending)?;
+        Ok(())
+    }
+
+    /// Corta la conexión. El loop de eventos se entera y la da de baja.
+    pub fn close(&self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+
+    fn write_pending(&self, pending: &mut Vec<u8>) -> io::Result<()> {
+        let mut written = 0;
+        let result = loop {
+            if written == pending.len() {
+                break Ok(());
+            }
+            match (&*self.stream).write(&pending[written..]) {
+                Ok(0) => break Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => break Err(e),
+            }
+        };
+        pending.drain(..written);
+        result
+    }
+}
+
+#[derive(Clone)]
+struct ClientWriter {
+    outbox: Arc<Outbox>,
+    wire: Wire,
+}
+
+/// Extremos de escritura de los clientes conectados.
+/// Tanto las respuestas como las notificaciones pasan por acá,
+/// así los mensajes de distintos threads no se intercalan.
+#[derive(Default)]
+pub struct Clients {
+    writers: Mutex<HashMap<SocketAddr, ClientWriter>>,
+}
+
+impl Clients {
+    /// `wire` es el formato que se acordó con el cliente en el handshake.
+    pub fn add(&self, id: SocketAddr, outbox: Arc<Outbox>, wire: Wire) -> ServerResult<()> {
+        self.writers
+            .lock()?
+            .insert(id, ClientWriter { outbox, wire });
+        Ok(())
+    }
+
+    /// Formato que habla el cliente, si está conectado.
+    pub fn wire(&self, id: &SocketAddr) -> ServerResult<Option<Wire>> {
+        Ok(self.writers.lock()?.get(id).map(|writer| writer.wire))
+    }
+
+    pub fn remove(&self, id: &SocketAddr) -> ServerResult<()> {
+        self.writers.lock()?.remove(id);
+        Ok(())
+    }
+
+    pub fn send_to(&self, id: &SocketAddr, msg: ServerMessage) -> ServerResult<()> {
+        let writer = self.writers.lock()?.get(id).cloned();
+        if let Some(writer) = writer {
+            writer.outbox.write(&writer.wire.encode(msg)?)?;
+        }
+        Ok(())
+    }
+
+    /// Envía la notificación (o el aviso) a un cliente, si la entiende.
+    pub fn notify(&self, id: &SocketAddr, msg: impl Into<ServerMessage>) {
+        let msg = msg.into();
+        let writer = match self.writers.lock() {
+            Ok(writers) => writers.get(id).cloned(),
+            Err(e) => {
+                eprintln!("Could not notify {:?}: {}", msg, e);
+                return;
+            }
+        };
+        if let Some(writer) = writer {
+            send_unsolicited(id, &writer, msg);
+        }
+    }
+
+    /// Envía la notificación (o el anuncio) a todos los clientes salvo `except`.
+    /// Los clientes que no entienden notificaciones se saltean.
+    pub fn broadcast(&self, msg: impl Into<ServerMessage>, except: Option<&SocketAddr>) {
+        let msg = msg.into();
+        let writers: Vec<(SocketAddr, ClientWriter)> = match self.writers.lock() {
+            Ok(writers) => writers
+                .iter()
+                .filter(|(id, _)| Some(*id) != except)
+                .map(|(id, writer)| (*id, writer.clone()))
+                .collect(),
+            Err(e) => {
+                eprintln!("Could not broadcast {:?}: {}", msg, e);
+                return;
+            }
+        };
+
+        for (id, writer) in writers {
+            send_unsolicited(&id, &writer, msg.clone());
+        }
+    }
+}
+
+/// Envía un mensaje que el cliente no pidió.
+fn send_unsolicited(id: &SocketAddr, writer: &ClientWriter, msg: ServerMessage) {
+    let bytes = match writer.wire.encode(msg) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ProtocolErrorKind::Unsupported => return,
+        Err(e) => {
+            eprintln!("{}: Error encoding notification - {}", id, e);
+            return;
+        }
+    };
+    if let Err(e) = writer.outbox.write(&bytes) {
+        eprintln!("{}: Error sending notification - {}", id, e);
+    }
+}
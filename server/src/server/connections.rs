@@ -0,0 +1,250 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    net::SocketAddr,
+    os::fd::AsRawFd,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use common::protocol::{ClientDecoder, Incoming, ProtocolError, Wire};
+use mio::{
+    net::{TcpListener, TcpStream},
+    unix::SourceFd,
+    Interest, Registry, Token,
+};
+
+use crate::server::clients::Outbox;
+use crate::server::network_connection::NetworkConnection;
+
+pub const LISTENER: Token = Token(0);
+pub const WAKER: Token = Token(1);
+const FIRST_CONNECTION: usize = 2;
+
+pub type PlayerConnection = NetworkConnection<Arc<TcpStream>, SocketAddr>;
+
+/// Conexión atendida por el loop de eventos: lo que llegó y todavía no
+/// forma un mensaje, y lo que falta enviarle.
+/// Sus pedidos se atienden de a uno en los workers, que comparten `network`.
+pub struct Connection {
+    id: SocketAddr,
+    stream: Arc<TcpStream>,
+    network: Arc<Mutex<PlayerConnection>>,
+    decoder: ClientDecoder,
+    outbox: Arc<Outbox>,
+    last_read: Instant,
+    busy: bool,
+    eof: bool,
+    /// Si está registrada para enterarse de lo que llega.
+    reading: bool,
+}
+
+impl Connection {
+    fn new(id: SocketAddr, stream: TcpStream) -> Connection {
+        let stream = Arc::new(stream);
+        Connection {
+            id,
+            stream: stream.clone(),
+            outbox: Arc::new(Outbox::new(stream.clone())),
+            network: Arc::new(Mutex::new(NetworkConnection::new(id, stream))),
+            decoder: ClientDecoder::new(),
+            last_read: Instant::now(),
+            busy: false,
+            eof: false,
+            reading: true,
+        }
+    }
+
+    pub fn id(&self) -> &SocketAddr {
+        &self.id
+    }
+
+    pub fn network(&self) -> &Arc<Mutex<PlayerConnection>> {
+        &self.network
+    }
+
+    pub fn outbox(&self) -> &Arc<Outbox> {
+        &self.outbox
+    }
+
+    /// Formato acordado en el handshake, si ya se hizo.
+    pub fn wire(&self) -> Option<Wire> {
+        self.decoder.wire()
+    }
+
+    /// Si un worker está atendiendo un pedido suyo.
+    pub fn busy(&self) -> bool {
+        self.busy
+    }
+
+    pub fn set_busy(&mut self, busy: bool) {
+        self.busy = busy;
+    }
+
+    /// Si el cliente ya no va a mandar nada más.
+    pub fn eof(&self) -> bool {
+        self.eof
+    }
+
+    /// Si lo recibido y sin procesar ya llegó al máximo.
+    pub fn is_full(&self) -> bool {
+        self.decoder.room() == 0
+    }
+
+    /// Lee lo que haya en el socket sin bloquear y lo agrega a lo recibido,
+    /// sin pasarse del máximo: con el buffer lleno devuelve `WouldBlock`.
+    pub fn receive(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let len = buffer.len().min(self.decoder.room());
+        if len == 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = (&*self.stream).read(&mut buffer[..len])?;
+        self.decoder.push(&buffer[..n]);
+        self.last_read = Instant::now();
+        if n == 0 {
+            self.eof = true;
+        }
+        Ok(n)
+    }
+
+    /// Deja de escuchar al cliente mientras se atiende un pedido suyo o
+    /// tiene el buffer lleno, y vuelve a hacerlo cuando no. Así el que
+    /// manda de más espera en su socket en vez de despertar al loop.
+    pub fn update_interest(&mut self, registry: &Registry, token: Token) -> io::Result<()> {
+        let reading = !self.busy && !self.is_full();
+        if reading == self.reading {
+            return Ok(());
+        }
+        let interests = if reading {
+            Interest::READABLE | Interest::WRITABLE
+        } else {
+            Interest::WRITABLE
+        };
+        let fd = self.stream.as_raw_fd();
+        registry.reregister(&mut SourceFd(&fd), token, interests)?;
+        self.reading = reading;
+        Ok(())
+    }
+
+    /// Próximo handshake o mensaje completo de lo recibido, si hay.
+    pub fn next_incoming(&mut self) -> Result<Option<Incoming>, ProtocolError> {
+        self.decoder.next_incoming()
+    }
+}
+
+/// Conexiones abiertas, por el token con el que se registraron.
+#[derive(Default)]
+pub struct Connections {
+    connections: HashMap<Token, Connection>,
+    accepted: usize,
+}
+
+impl Connections {
+    /// Acepta todas las conexiones pendientes y las registra para
+    /// enterarse cuando tengan algo para leer o lugar para escribir.
+    pub fn accept(&mut self, listener: &TcpListener, registry: &Registry) {
+        loop {
+            let (mut stream, id) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    eprintln!("Error accepting TCP connection: {}", e);
+                    return;
+                }
+            };
+
+            let token = Token(FIRST_CONNECTION + self.accepted);
+            self.accepted += 1;
+            let interests = Interest::READABLE | Interest::WRITABLE;
+            if let Err(e) = registry.register(&mut stream, token, interests) {
+                eprintln!("{}: Error registering connection - {}", id, e);
+                continue;
+            }
+            println!("New connection from: {:?}", id);
+            self.connections.insert(token, Connection::new(id, stream));
+        }
+    }
+
+    pub fn get_mut(&mut self, token: Token) -> Option<&mut Connection> {
+        self.connections.get_mut(&token)
+    }
+
+    pub fn remove(&mut self, token: Token) -> Option<Connection> {
+        self.connections.remove(&token)
+    }
+
+    /// Saca las conexiones que no mandaron nada en `timeout` y no
+    /// esperan la respuesta de un pedido.
+    pub fn remove_idle(&mut self, timeout: Duration) -> Vec<Connection> {
+        let idle: Vec<Token> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| !connection.busy && connection.last_read.elapsed() > timeout)
+            .map(|(token, _)| *token)
+            .collect();
+        idle.into_iter()
+            .filter_map(|token| self.connections.remove(&token))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    pub fn drain(&mut self) -> Vec<Connection> {
+        self.connections
+            .drain()
+            .map(|(_, connection)| connection)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod connections_tests {
+    use std::{
+        io::{self, Write},
+        net::{TcpListener, TcpStream as StdTcpStream},
+        thread,
+        time::Duration,
+    };
+
+    use common::protocol::MAX_BUFFERED_LEN;
+    use mio::net::TcpStream;
+
+    use super::Connection;
+
+    #[test]
+    fn stops_reading_when_the_buffer_is_full() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = StdTcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, id) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut connection = Connection::new(id, TcpStream::from_std(stream));
+
+        // Más de lo que entra en el buffer; el resto queda en el socket
+        let writer = thread::spawn(move || {
+            let _ = client.write_all(&vec![b'a'; 2 * MAX_BUFFERED_LEN]);
+            client
+        });
+
+        let mut buffer = [0u8; 4096];
+        let mut received = 0;
+        while !connection.is_full() {
+            match connection.receive(&mut buffer) {
+                Ok(n) => received += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(1))
+                }
+                Err(e) => panic!("{}", e),
+            }
+        }
+        assert_eq!(received, MAX_BUFFERED_LEN);
+        let err = connection.receive(&mut buffer).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(!connection.eof());
+
+        drop(connection);
+        drop(writer.join().unwrap());
+    }
+}
@@ -0,0 +1,301 @@
+use common::{
+    fairness::{self, SEED_LEN},
+    payout::PayoutConfig,
+    protocol::{FairPlay, ServerMessage},
+};
+use rand::Rng;
+use std::fmt;
+
+use crate::machine::{Machine, SeededCoin};
+
+/// Jugadas que se guardan por semilla antes de exigir que se revele.
+/// Todas tienen que entrar en un solo mensaje.
+pub const MAX_UNREVEALED_PLAYS: usize = 1000;
+pub const MAX_CLIENT_SEED_LEN: usize = 64;
+
+/// Semillas con las que se juega de manera verificable (commit-reveal).
+///
+/// El servidor publica el hash de su semilla antes de que el cliente elija
+/// la suya. El azar de cada moneda sale de las dos semillas y un nonce, y al
+/// revelar la semilla del servidor el cliente puede recalcularlo. Con el
+/// compromiso también recibe el modelo de pago, para recalcular el premio.
+pub struct Fairness {
+    server_seed: [u8; SEED_LEN],
+    client_seed: String,
+    nonce: u64,
+    plays: Vec<FairPlay>,
+    payout: Option<PayoutConfig>,
+    // Si el cliente recibió `payout` y espera que se respete
+    binding: bool,
+}
+
+impl Fairness {
+    pub fn new() -> Fairness {
+        Fairness {
+            server_seed: rand::thread_rng().gen(),
+            client_seed: String::new(),
+            nonce: 0,
+            plays: Vec::new(),
+            payout: None,
+            binding: false,
+        }
+    }
+
+    /// Sin semilla del cliente se juega con el generador de la máquina.
+    pub fn is_active(&self) -> bool {
+        !self.client_seed.is_empty()
+    }
+
+    /// Nonce de la próxima moneda.
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Una semilla vacía vuelve a jugar con el generador de la máquina.
+    /// Las jugadas hechas con la semilla anterior se tienen que revelar
+    /// antes, porque se revelan junto con la semilla del cliente.
+    pub fn set_client_seed(&mut self, seed: String) -> Result<(), ServerMessage> {
+        if seed.len() > MAX_CLIENT_SEED_LEN {
+            return Err(ServerMessage::InvalidClientSeed(MAX_CLIENT_SEED_LEN as u32));
+        }
+        if !self.plays.is_empty() && seed != self.client_seed {
+            return Err(ServerMessage::RevealRequired(MAX_UNREVEALED_PLAYS as u32));
+        }
+        self.client_seed = seed;
+        Ok(())
+    }
+
+    /// Si quedan menos de `n` jugadas antes de revelar, devuelve la respuesta.
+    pub fn check_room(&self, n: u32) -> Result<(), ServerMessage> {
+        if self.plays.len() + n as usize > MAX_UNREVEALED_PLAYS {
+            return Err(ServerMessage::RevealRequired(MAX_UNREVEALED_PLAYS as u32));
+        }
+        Ok(())
+    }
+
+    /// Compromete la semilla con el modelo de pago de la máquina del
+    /// jugador, si todavía no se jugó con ella. Desde entonces sus monedas
+    /// solo se juegan en máquinas que paguen así.
+    pub fn commit_payout(&mut self, payout: Option<PayoutConfig>) {
+        self.binding = true;
+        if self.plays.is_empty() {
+            self.payout = payout;
+        }
+    }
+
+    /// Si una moneda arrojada en `machine` paga como se comprometió.
+    /// En un estante el modelo de pago no decide nada.
+    pub fn pays_as_committed(&self, machine: &Machine) -> bool {
+        !self.binding || machine.board().is_some() || machine.payout() == self.payout.as_ref()
+    }
+
+    pub fn commitment(&self) -> ServerMessage {
+        ServerMessage::Commitment {
+            server_seed_hash: fairness::commitment(&self.server_seed),
+            client_seed: self.client_seed.clone(),
+            nonce: self.nonce,
+            payout: self.payout.clone(),
+        }
+    }
+
+    /// Arroja una moneda con el azar del próximo nonce y la anota.
+    pub fn throw(&mut self, machine: &mut Machine, lane: Option<u32>) -> SeededCoin {
+        let coin_seed = fairness::coin_seed(&self.server_seed, &self.client_seed, self.nonce);
+        let coin = machine.insert_seeded_coin(lane, &coin_seed);
+        self.plays.push(FairPlay {
+            nonce: self.nonce,
+            pool: coin.pool,
+            probability: coin.probability,
+            fell: coin.outcome.fell,
+        });
+        self.nonce += 1;
+        coin
+    }
+
+    /// Revela la semilla del servidor junto con las jugadas hechas con ella
+    /// y la reemplaza por una nueva. La semilla del cliente y el modelo de
+    /// pago comprometido se mantienen.
+    pub fn reveal(&mut self) -> ServerMessage {
+        let revealed = std::mem::replace(self, Fairness::new());
+        self.client_seed = revealed.client_seed.clone();
+        self.payout = revealed.payout.clone();
+        self.binding = revealed.binding;
+
+        ServerMessage::SeedRevealed {
+            server_seed: fairness::to_hex(&revealed.server_seed),
+            client_seed: revealed.client_seed,
+            plays: revealed.plays,
+            next_hash: fairness::commitment(&self.server_seed),
+        }
+    }
+}
+
+// La semilla del servidor no puede aparecer en los logs antes de revelarla
+impl fmt::Debug for Fairness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Fairness")
+            .field("client_seed", &self.client_seed)
+            .field("nonce", &self.nonce)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod fairness_tests {
+    use super::*;
+    use crate::config::FileConfig;
+    use crate::server::traits::Config;
+    use common::payout::FixedRtp;
+    use std::io::Cursor;
+
+    fn machine() -> Machine {
+        let model = FixedRtp::new(0.9, 0.3).unwrap();
+        Machine::with_model(500, Box::new(model)).unwrap()
+    }
+
+    fn configured_machine(extra: &str) -> Machine {
+        let cursor = Cursor::new(format!(
+            "port=8080\nhost=localhost\ninitial_coins_count=100\n{}",
+            extra
+        ));
+        let config = FileConfig::new_from_file(cursor).unwrap().machines()[0].clone();
+        Machine::from_config(&config, None).unwrap()
+    }
+
+    #[test]
+    fn inactive_without_client_seed() {
+        let mut fairness = Fairness::new();
+        assert!(!fairness.is_active());
+
+        fairness.set_client_seed("lucky".to_string()).unwrap();
+        assert!(fairness.is_active());
+
+        assert!(fairness.set_client_seed("x".repeat(65)).is_err());
+        assert!(fairness.is_active());
+    }
+
+    #[test]
+    fn revealed_seed_matches_commitment_and_plays() {
+        let mut fairness = Fairness::new();
+        fairness.set_client_seed("lucky".to_string()).unwrap();
+        let hash = match fairness.commitment() {
+            ServerMessage::Commitment {
+                server_seed_hash, ..
+            } => server_seed_hash,
+            other => panic!("Unexpected message {:?}", other),
+        };
+        let mut machine = machine();
+        let seen: Vec<u32> = (0..20)
+            .map(|_| fairness.throw(&mut machine, None).outcome.fell)
+            .collect();
+
+        match fairness.reveal() {
+            ServerMessage::SeedRevealed {
+                server_seed,
+                client_seed,
+                plays,
+                next_hash,
+            } => {
+                let server_seed = fairness::from_hex(&server_seed).unwrap();
+                assert_eq!(fairness::commitment(&server_seed), hash);
+                assert_ne!(next_hash, hash);
+                for (nonce, play) in plays.iter().enumerate() {
+                    let coin = fairness::coin_seed(&server_seed, &client_seed, nonce as u64);
+                    assert_eq!(play.nonce, nonce as u64);
+                    assert_eq!(play.fell, seen[nonce]);
+                    assert_eq!(play.fell > 0, fairness::roll(&coin) < 0.3);
+                }
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+        // La semilla nueva empieza de cero y conserva la del cliente
+        assert!(fairness.is_active());
+        assert!(matches!(
+            fairness.commitment(),
+            ServerMessage::Commitment { nonce: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn client_seed_changes_only_after_reveal() {
+        let mut fairness = Fairness::new();
+        fairness.set_client_seed("lucky".to_string()).unwrap();
+        let mut machine = machine();
+        fairness.throw(&mut machine, None);
+
+        assert!(matches!(
+            fairness.set_client_seed("other".to_string()),
+            Err(ServerMessage::RevealRequired(_))
+        ));
+        assert!(fairness.set_client_seed(String::new()).is_err());
+        // Repetir la misma semilla no cambia nada
+        assert!(fairness.set_client_seed("lucky".to_string()).is_ok());
+
+        match fairness.reveal() {
+            ServerMessage::SeedRevealed {
+                client_seed, plays, ..
+            } => {
+                assert_eq!(client_seed, "lucky");
+                assert_eq!(plays.len(), 1);
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+        fairness.set_client_seed("other".to_string()).unwrap();
+        fairness.throw(&mut machine, None);
+        match fairness.reveal() {
+            ServerMessage::SeedRevealed {
+                client_seed, plays, ..
+            } => {
+                assert_eq!(client_seed, "other");
+                assert_eq!(plays.len(), 1);
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn too_many_unrevealed_plays() {
+        let mut fairness = Fairness::new();
+        fairness.set_client_seed("lucky".to_string()).unwrap();
+        let mut machine = machine();
+        for _ in 0..MAX_UNREVEALED_PLAYS - 1 {
+            fairness.throw(&mut machine, None);
+        }
+
+        assert!(fairness.check_room(1).is_ok());
+        assert!(fairness.check_room(2).is_err());
+        fairness.reveal();
+        assert!(fairness.check_room(10).is_ok());
+    }
+
+    #[test]
+    fn plays_only_where_the_committed_payout_holds() {
+        let mut step = configured_machine("payout_model=step\nthreshold=50");
+        let other = configured_machine("payout_model=step\nthreshold=60");
+        let mut fairness = Fairness::new();
+        fairness.set_client_seed("lucky".to_string()).unwrap();
+        // Sin compromiso vale cualquier máquina, como con clientes viejos
+        assert!(fairness.pays_as_committed(&other));
+
+        fairness.commit_payout(step.payout().cloned());
+        assert!(matches!(
+            fairness.commitment(),
+            ServerMessage::Commitment {
+                payout: Some(_),
+                ..
+            }
+        ));
+        assert!(fairness.pays_as_committed(&step));
+        assert!(!fairness.pays_as_committed(&other));
+
+        // Con jugadas hechas el compromiso ya no cambia, ni al revelar
+        fairness.throw(&mut step, None);
+        fairness.commit_payout(other.payout().cloned());
+        assert!(!fairness.pays_as_committed(&other));
+        fairness.reveal();
+        assert!(fairness.pays_as_committed(&step));
+
+        fairness.commit_payout(other.payout().cloned());
+        assert!(fairness.pays_as_committed(&other));
+    }
+}
@@ -0,0 +1,434 @@
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    net::SocketAddr,
+    sync::{Mutex, MutexGuard},
+};
+
+use common::protocol::MachineInfo;
+
+use crate::{
+    config::MachineConfig,
+    machine::{Machine, MachineSnapshot},
+    server::{ServerError, ServerErrorKind, ServerResult},
+};
+
+pub type MachineId = u32;
+
+/// Resultado de pedir lugar en una máquina.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Join {
+    Seated,
+    /// La máquina está llena; la posición en la fila empieza en 1.
+    Queued(u32),
+}
+
+/// Lugar que dejó una conexión al levantarse.
+/// Si había alguien esperando, `promoted` ya quedó sentado en ese lugar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vacated {
+    pub machine: MachineId,
+    pub promoted: Option<SocketAddr>,
+}
+
+struct Table {
+    max_players: u32,
+    players: HashSet<SocketAddr>,
+    queue: VecDeque<SocketAddr>,
+}
+
+impl Table {
+    fn position(&self, who: &SocketAddr) -> Option<u32> {
+        self.queue
+            .iter()
+            .position(|queued| queued == who)
+            .map(|i| i as u32 + 1)
+    }
+
+    /// Saca a `who` de la mesa o de la fila. Devuelve `None` si no estaba.
+    fn remove(&mut self, who: &SocketAddr) -> Option<Option<SocketAddr>> {
+        if self.players.remove(who) {
+            let promoted = self.queue.pop_front();
+            if let Some(promoted) = promoted {
+                self.players.insert(promoted);
+            }
+            return Some(promoted);
+        }
+        let i = self.queue.iter().position(|queued| queued == who)?;
+        self.queue.remove(i);
+        Some(None)
+    }
+}
+
+/// Máquinas del servidor y las conexiones sentadas en cada una.
+/// El conjunto de máquinas no cambia mientras el servidor corre, aunque
+/// sus parámetros sí (ver `retune`); cada una
+/// tiene su propio lock, así las jugadas en máquinas distintas no se esperan.
+/// Los lugares se manejan aparte, bajo un único lock.
+pub struct Machines {
+    machines: BTreeMap<MachineId, Mutex<Machine>>,
+    tables: Mutex<BTreeMap<MachineId, Table>>,
+    default_id: MachineId,
+}
+
+impl Machines {
+    /// Recibe el id, la máquina y la cantidad máxima de jugadores de cada una.
+    /// `machines` no puede estar vacío: la primera es la máquina por defecto.
+    pub fn new(machines: Vec<(MachineId, Machine, u32)>) -> ServerResult<Machines> {
+        let default_id = match machines.first() {
+            Some((id, _, _)) => *id,
+            None => return Err(ServerError::new_msg("At least one machine is required")),
+        };
+
+        let mut tables = BTreeMap::new();
+        let machines = machines
+            .into_iter()
+            .map(|(id, machine, max_players)| {
+                let table = Table {
+                    max_players,
+                    players: HashSet::new(),
+                    queue: VecDeque::new(),
+                };
+                tables.insert(id, table);
+                (id, Mutex::new(machine))
+            })
+            .collect();
+
+        Ok(Machines {
+            machines,
+            tables: Mutex::new(tables),
+            default_id,
+        })
+    }
+
+    pub fn default_id(&self) -> MachineId {
+        self.default_id
+    }
+
+    pub fn contains(&self, id: MachineId) -> bool {
+        self.machines.contains_key(&id)
+    }
+
+    pub fn lock(&self, id: MachineId) -> ServerResult<MutexGuard<'_, Machine>> {
+        let machine = self.machines.get(&id).ok_or_else(|| unknown_machine(id))?;
+        Ok(machine.lock()?)
+    }
+
+    /// Máquina en la que está sentada la conexión. Esperar en una fila no cuenta.
+    pub fn seat_of(&self, who: &SocketAddr) -> ServerResult<Option<MachineId>> {
+        let tables = self.tables.lock()?;
+        Ok(tables
+            .iter()
+            .find(|(_, table)| table.players.contains(who))
+            .map(|(id, _)| *id))
+    }
+
+    /// Sienta a la conexión en `id`, o la pone en su fila si está llena.
+    /// Antes la levanta del lugar o la fila en la que estuviera,
+    /// salvo que ya esté en esta misma máquina.
+    pub fn join(&self, id: MachineId, who: SocketAddr) -> ServerResult<(Join, Option<Vacated>)> {
+        let mut tables = self.tables.lock()?;
+        let table = tables.get(&id).ok_or_else(|| unknown_machine(id))?;
+        if table.players.contains(&who) {
+            return Ok((Join::Seated, None));
+        }
+        if let Some(position) = table.position(&who) {
+            return Ok((Join::Queued(position), None));
+        }
+
+        let vacated = vacate(&mut tables, &who);
+        let table = tables.get_mut(&id).ok_or_else(|| unknown_machine(id))?;
+        let join = if (table.players.len() as u32) < table.max_players {
+            table.players.insert(who);
+            Join::Seated
+        } else {
+            table.queue.push_back(who);
+            Join::Queued(table.queue.len() as u32)
+        };
+        Ok((join, vacated))
+    }
+
+    /// Levanta a la conexión de su lugar o de su fila.
+    /// Devuelve `None` si no estaba en ninguna máquina.
+    pub fn leave(&self, who: &SocketAddr) -> ServerResult<Option<Vacated>> {
+        Ok(vacate(&mut *self.tables.lock()?, who))
+    }
+
+    pub fn list(&self) -> ServerResult<Vec<MachineInfo>> {
+        let tables = self.tables.lock()?;
+        self.machines
+            .iter()
+            .map(|(id, machine)| {
+                Ok(MachineInfo {
+                    id: *id,
+                    pool: machine.lock()?.get_pool(),
+                    players: tables.get(id).map_or(0, |table| table.players.len() as u32),
+                })
+            })
+            .collect()
+    }
+
+    /// Pasa las máquinas que aparecen en `configs` a sus nuevos parámetros.
+    /// Si alguna no los acepta no cambia ninguna, y se devuelve el error.
+    /// Si ahora entran más jugadores, se sienta a los que esperaban:
+    /// se devuelve un `Vacated` por cada uno.
+    pub fn retune(&self, configs: &[MachineConfig]) -> ServerResult<Vec<Vacated>> {
+        // Se toman todas a la vez, en orden de id, así nadie juega a mitad del cambio
+        let mut machines = Vec::new();
+        for (id, machine) in &self.machines {
+            if let Some(config) = configs.iter().find(|config| config.id() == *id) {
+                machines.push((machine.lock()?, config));
+            }
+        }
+        let retuned = machines
+            .iter()
+            .map(|(machine, config)| {
+                machine
+                    .retuned(config)
+                    .map_err(|e| ServerError::new_msg(format!("Machine {}: {}", config.id(), e)))
+            })
+            .collect::<ServerResult<Vec<_>>>()?;
+        for ((machine, _), new) in machines.iter_mut().zip(retuned) {
+            **machine = new;
+        }
+        drop(machines);
+
+        let mut tables = self.tables.lock()?;
+        let mut seated = Vec::new();
+        for config in configs {
+            let Some(table) = tables.get_mut(&config.id()) else {
+                continue;
+            };
+            table.max_players = config.max_players();
+            while (table.players.len() as u32) < table.max_players {
+                let Some(promoted) = table.queue.pop_front() else {
+                    break;
+                };
+                table.players.insert(promoted);
+                seated.push(Vacated {
+                    machine: config.id(),
+                    promoted: Some(promoted),
+                });
+            }
+        }
+        Ok(seated)
+    }
+
+    pub fn snapshot(&self) -> ServerResult<BTreeMap<MachineId, MachineSnapshot>> {
+        self.machines
+            .iter()
+            .map(|(id, machine)| Ok((*id, machine.lock()?.snapshot())))
+            .collect()
+    }
+}
+
+fn vacate(tables: &mut BTreeMap<MachineId, Table>, who: &SocketAddr) -> Option<Vacated> {
+    tables.iter_mut().find_map(|(id, table)| {
+        let promoted = table.remove(who)?;
+        Some(Vacated {
+            machine: *id,
+            promoted,
+        })
+    })
+}
+
+fn unknown_machine(id: MachineId) -> ServerError {
+    ServerError::new_kind(
+        format!("Unknown machine {}", id),
+        ServerErrorKind::Irrecoverable,
+    )
+}
+
+#[cfg(test)]
+mod machines_tests {
+    use super::*;
+
+    fn machines() -> Machines {
+        Machines::new(vec![
+            (3, Machine::with(30).unwrap(), 10),
+            (1, Machine::with(10).unwrap(), 2),
+        ])
+        .unwrap()
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn first_machine_is_the_default() {
+        assert_eq!(machines().default_id(), 3);
+    }
+
+    #[test]
+    fn no_machines() {
+        assert!(Machines::new(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn machines_are_independent() {
+        let machines = machines();
+
+        machines.lock(1).unwrap().insert_coins(5);
+
+        assert_eq!(machines.lock(3).unwrap().get_pool(), 30);
+        assert!(machines.lock(2).is_err());
+    }
+
+    #[test]
+    fn join_another_machine_leaves_the_first() {
+        let machines = machines();
+        machines.join(3, addr(1)).unwrap();
+
+        let (join, vacated) = machines.join(1, addr(1)).unwrap();
+
+        assert_eq!(join, Join::Seated);
+        assert_eq!(
+            vacated,
+            Some(Vacated {
+                machine: 3,
+                promoted: None
+            })
+        );
+        assert_eq!(machines.seat_of(&addr(1)).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn full_machine_queues_players() {
+        let machines = machines();
+        machines.join(1, addr(1)).unwrap();
+        machines.join(1, addr(2)).unwrap();
+
+        assert_eq!(machines.join(1, addr(3)).unwrap().0, Join::Queued(1));
+        assert_eq!(machines.join(1, addr(4)).unwrap().0, Join::Queued(2));
+        // Volver a pedir lugar no lo manda al final de la fila
+        assert_eq!(machines.join(1, addr(3)).unwrap().0, Join::Queued(1));
+        assert_eq!(machines.seat_of(&addr(3)).unwrap(), None);
+    }
+
+    #[test]
+    fn freed_seat_goes_to_the_first_in_queue() {
+        let machines = machines();
+        machines.join(1, addr(1)).unwrap();
+        machines.join(1, addr(2)).unwrap();
+        machines.join(1, addr(3)).unwrap();
+        machines.join(1, addr(4)).unwrap();
+
+        let vacated = machines.leave(&addr(1)).unwrap();
+
+        assert_eq!(
+            vacated,
+            Some(Vacated {
+                machine: 1,
+                promoted: Some(addr(3))
+            })
+        );
+        assert_eq!(machines.seat_of(&addr(3)).unwrap(), Some(1));
+        assert_eq!(machines.join(1, addr(4)).unwrap().0, Join::Queued(1));
+    }
+
+    #[test]
+    fn leave_the_queue() {
+        let machines = machines();
+        machines.join(1, addr(1)).unwrap();
+        machines.join(1, addr(2)).unwrap();
+        machines.join(1, addr(3)).unwrap();
+
+        let vacated = machines.leave(&addr(3)).unwrap();
+
+        assert_eq!(
+            vacated,
+            Some(Vacated {
+                machine: 1,
+                promoted: None
+            })
+        );
+        assert_eq!(machines.leave(&addr(3)).unwrap(), None);
+    }
+
+    #[test]
+    fn list_counts_seated_players() {
+        let machines = machines();
+        machines.join(1, addr(1)).unwrap();
+        machines.join(1, addr(2)).unwrap();
+        machines.join(1, addr(3)).unwrap();
+        machines.join(3, addr(4)).unwrap();
+
+        let list = machines.list().unwrap();
+
+        assert_eq!(
+            list,
+            [
+                MachineInfo {
+                    id: 1,
+                    pool: 10,
+                    players: 2
+                },
+                MachineInfo {
+                    id: 3,
+                    pool: 30,
+                    players: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshot_every_machine() {
+        let machines = machines();
+
+        let snapshot = machines.snapshot().unwrap();
+
+        assert_eq!(snapshot.keys().copied().collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(snapshot[&3], Machine::with(30).unwrap().snapshot());
+    }
+
+    fn configs(keys: &str) -> Vec<MachineConfig> {
+        let cursor = std::io::Cursor::new(format!(
+            "port=8080
+host=localhost
+initial_coins_count=10
+machines=3,1
+{}",
+            keys
+        ));
+        let config = crate::config::FileConfig::new_from_file(cursor).unwrap();
+        crate::server::traits::Config::machines(&config).to_vec()
+    }
+
+    #[test]
+    fn retune_seats_the_queue_when_more_players_fit() {
+        let machines = machines();
+        for port in 1..=4 {
+            machines.join(1, addr(port)).unwrap();
+        }
+
+        let seated = machines
+            .retune(&configs("machine.1.max_players=3\nmachine.3.capacity=1500"))
+            .unwrap();
+
+        assert_eq!(
+            seated,
+            vec![Vacated {
+                machine: 1,
+                promoted: Some(addr(3))
+            }]
+        );
+        assert_eq!(machines.join(1, addr(4)).unwrap().0, Join::Queued(1));
+        assert_eq!(machines.lock(3).unwrap().capacity(), 1500);
+        assert_eq!(machines.lock(3).unwrap().get_pool(), 30);
+    }
+
+    #[test]
+    fn retune_changes_nothing_if_a_machine_rejects_it() {
+        let machines = machines();
+
+        // En la máquina 3 hay 30 monedas
+        let err = machines
+            .retune(&configs("capacity=20\nthreshold=20"))
+            .unwrap_err();
+
+        assert!(err.to_string().starts_with("Machine 3:"));
+        assert_eq!(machines.lock(1).unwrap().capacity(), 1000);
+        assert_eq!(machines.lock(3).unwrap().capacity(), 1000);
+    }
+}
@@ -1,52 +1,193 @@
-use crate::server::network_connection::NetworkConnection;
+use crate::audit::{self, AuditLog, CoinEvent};
+use crate::server::clients::{Clients, Outbox};
+use crate::server::connections::{Connection, Connections, PlayerConnection, LISTENER, WAKER};
+use crate::server::fairness::Fairness;
+use crate::server::machines::{Join, MachineId, Machines, Vacated};
+use crate::server::sessions::{Session, Sessions};
+use crate::server::snapshotter::Snapshotter;
+use crate::server::workers::Workers;
+use crate::snapshot::{self, Snapshot};
+use mio::event::Event;
+use mio::net::TcpListener;
+use mio::{Events, Interest, Poll, Registry, Token, Waker};
 use std::net::SocketAddr;
-use std::net::{TcpListener, TcpStream};
+use std::net::TcpListener as StdTcpListener;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
-use std::sync::{mpsc, Arc, Mutex};
-use std::time::Duration;
+use std::sync::{mpsc, Arc, Mutex, PoisonError, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
 use std::{io, thread};
 
 use crate::server::server_controller::ServerController;
 use crate::server::server_error::{ServerError, ServerErrorKind};
 
-use crate::machine::Machine;
-use crate::server::traits::Config;
-use common::protocol::{ClientMessage, ServerMessage, StreamToClient};
-use thread_joiner::ThreadJoiner;
+use crate::accounts::{FileAccountStore, MemoryAccountStore};
+use crate::config::{self, Changes};
+use crate::machine::{CoinOutcome, Machine};
+use crate::server::traits::{AccountStore, Config};
+use common::protocol::{
+    BoardInfo, ClientMessage, Incoming, Item, Notification, ServerMessage, Wire,
+};
 
+mod clients;
+mod connections;
+mod fairness;
+mod machines;
 mod network_connection;
 mod server_controller;
 mod server_error;
-pub(crate) mod traits;
+mod sessions;
+mod snapshotter;
+pub mod traits;
+mod workers;
 
 pub type ServerResult<T> = Result<T, ServerError>;
 
 const CONNECTION_WAIT_TIMEOUT: Duration = Duration::from_secs(180);
-const ACCEPT_SLEEP_DUR: Duration = Duration::from_millis(100);
+const IDLE_CHECK_PERIOD: Duration = Duration::from_secs(1);
+const EVENTS_CAPACITY: usize = 1024;
+const READ_BUFFER_LEN: usize = 4096;
+/// Lo máximo que un pedido espera un worker libre; pasado ese tiempo se
+/// le responde que el servidor está ocupado.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Parámetros que se pueden cambiar sin reiniciar el servidor.
+struct Limits {
+    max_batch_size: u32,
+    max_queued_requests: usize,
+    shutdown_grace: Duration,
+}
+
+impl Limits {
+    fn new(config: &impl Config) -> Limits {
+        Limits {
+            max_batch_size: config.max_batch_size(),
+            max_queued_requests: config.max_queued_requests(),
+            shutdown_grace: config.shutdown_grace(),
+        }
+    }
+}
 
 pub struct Server<C: Config> {
+    // Con la que arrancó el servidor; lo que se recarga en vivo va en `limits`
     config: C,
-    coin_machine: Mutex<Machine>,
+    // La última que se aplicó, para avisar qué cambia en cada recarga
+    applied: Mutex<C>,
+    limits: RwLock<Limits>,
+    machines: Machines,
+    clients: Clients,
+    sessions: Sessions,
+    audit: Option<AuditLog>,
+    // Si una jugada no se pudo guardar, en el registro de auditoría o en la
+    // cuenta del jugador, no se juega más: el servidor se apaga
+    storage_failed: AtomicBool,
 }
 
 impl<C: Config> Server<C> {
-    pub fn new(config: C) -> Arc<Server<C>> {
-        Arc::new(Server {
-            coin_machine: Mutex::new(Machine::with(config.initial_coins_count()).unwrap()),
+    pub fn new(config: C) -> ServerResult<Arc<Server<C>>> {
+        let store: Box<dyn AccountStore> = match config.accounts_path() {
+            Some(path) => Box::new(FileAccountStore::open(path)?),
+            None => Box::new(MemoryAccountStore::default()),
+        };
+        Self::with_store(config, store)
+    }
+
+    fn with_store(config: C, store: Box<dyn AccountStore>) -> ServerResult<Arc<Server<C>>> {
+        Ok(Arc::new(Server {
+            machines: Self::initial_machines(&config)?,
+            clients: Clients::default(),
+            sessions: Sessions::new(store, config.initial_balance()),
+            audit: match config.audit_path() {
+                Some(path) => Some(AuditLog::open(path, config.audit_max_bytes())?),
+                None => None,
+            },
+            storage_failed: AtomicBool::new(false),
+            limits: RwLock::new(Limits::new(&config)),
+            applied: Mutex::new(config.clone()),
             config,
-        })
+        }))
+    }
+
+    /// Aplica `config` sin reiniciar: los parámetros de las máquinas, los
+    /// límites y el saldo de las cuentas nuevas. Si alguna máquina no acepta
+    /// sus nuevos parámetros no se cambia nada. Devuelve las claves que se
+    /// aplicaron y las que cambiaron pero recién se aplican al reiniciar,
+    /// como `host` o `port`.
+    pub fn reload(self: &Arc<Self>, config: C) -> ServerResult<Changes> {
+        let mut applied = self.applied.lock()?;
+        for machine in config.machines() {
+            machine
+                .check()
+                .map_err(|e| ServerError::new_msg(format!("Machine {}: {}", machine.id(), e)))?;
+        }
+
+        let machines: Vec<_> = config
+            .machines()
+            .iter()
+            .filter_map(|new| {
+                let running = self.config.machines().iter().find(|m| m.id() == new.id())?;
+                Some(new.live(running))
+            })
+            .collect();
+        for seated in self.machines.retune(&machines)? {
+            self.notify_vacated(Some(seated));
+        }
+        self.sessions.set_initial_balance(config.initial_balance());
+        *self.limits.write()? = Limits::new(&config);
+
+        let changes = Changes {
+            applied: config::live_keys(&*applied, &config),
+            restart: config::restart_keys(&self.config, &config),
+        };
+        *applied = config;
+        Ok(changes)
+    }
+
+    fn limits(&self) -> RwLockReadGuard<'_, Limits> {
+        self.limits.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Cada máquina arranca desde el último snapshot si así se configuró
+    /// y el snapshot la incluye; si no, con sus `initial_coins_count` monedas.
+    fn initial_machines(config: &C) -> ServerResult<Machines> {
+        let mut snapshot = None;
+        if let (true, Some(path)) = (config.resume_from_snapshot(), config.snapshot_path()) {
+            snapshot = snapshot::load(path)?;
+            if snapshot.is_some() {
+                println!("Resuming machines from snapshot {}", path);
+            }
+        }
+
+        let machines = config
+            .machines()
+            .iter()
+            .map(|machine_config| {
+                let id = machine_config.id();
+                let machine_snapshot = snapshot.as_ref().and_then(|s| s.machine(id));
+                Machine::from_config(machine_config, machine_snapshot)
+                    .map(|machine| (id, machine, machine_config.max_players()))
+                    .map_err(|e| ServerError::new_msg(format!("Machine {}: {}", id, e)))
+            })
+            .collect::<ServerResult<Vec<_>>>()?;
+        Machines::new(machines)
     }
 
     pub fn run(self: Arc<Self>) -> io::Result<ServerController> {
         let shutdown_bool = Arc::new(AtomicBool::new(false));
         let shutdown_bool_copy = shutdown_bool.clone();
         let (started_sender, started_receiver) = mpsc::channel();
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let waker_copy = waker.clone();
+        let (stopped_sender, stopped_receiver) = mpsc::channel::<()>();
 
         let server_handle = thread::Builder::new()
             .name("server_loop".to_owned())
             .spawn(move || {
-                if let Err(err) = self.server_loop(shutdown_bool, started_sender) {
+                // Al soltarlo avisa que el loop terminó, aunque sea con pánico
+                let _stopped = stopped_sender;
+                if let Err(err) = self.server_loop(poll, waker_copy, shutdown_bool, started_sender)
+                {
                     eprintln!(
                         "Unexpected server error: {} - Try shutting down the server and restarting it",
                         err
@@ -57,124 +198,1021 @@ impl<C: Config> Server<C> {
         started_receiver.recv().unwrap_or_else(|e| {
             eprintln!("Error starting up server: {}", e);
         });
-        let server_controller = ServerController::new(shutdown_bool_copy, server_handle);
+        let server_controller =
+            ServerController::new(shutdown_bool_copy, waker, server_handle, stopped_receiver);
         Ok(server_controller)
     }
 
-    fn accept_client(
-        self: &Arc<Self>,
-        listener: &TcpListener,
-    ) -> ServerResult<NetworkConnection<TcpStream, SocketAddr>> {
-        match listener.accept() {
-            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
-                Err(ServerError::new_kind("Idle", ServerErrorKind::Idle))
-            }
-            Err(error) => {
-                eprintln!("Error accepting TCP connection: {}", error);
-                Err(ServerError::from(error))
-            }
-            Ok((stream, socket_addr)) => {
-                stream.set_read_timeout(Some(CONNECTION_WAIT_TIMEOUT))?;
-                Ok(NetworkConnection::new(socket_addr, stream))
-            }
-        }
-    }
-
+    /// Un único thread atiende todas las conexiones: espera a que alguna
+    /// tenga algo para leer o lugar para escribir, y mientras tanto no
+    /// ocupa recursos por cada jugador conectado. Los pedidos se atienden
+    /// en los workers, que despiertan al loop con `waker` al terminar.
     fn server_loop(
         self: Arc<Self>,
+        mut poll: Poll,
+        waker: Arc<Waker>,
         shutdown_bool: Arc<AtomicBool>,
         started_sender: Sender<()>,
     ) -> ServerResult<()> {
-        let listener = TcpListener::bind(format!("{}:{}", self.config.host(), self.config.port()))?;
+        let listener =
+            StdTcpListener::bind(format!("{}:{}", self.config.host(), self.config.port()))?;
+        listener.set_nonblocking(true)?;
+        let mut listener = TcpListener::from_std(listener);
+        poll.registry()
+            .register(&mut listener, LISTENER, Interest::READABLE)?;
+        let mut listener = Some(listener);
         started_sender.send(())?;
 
-        let mut thread_joiner = ThreadJoiner::new();
-        listener.set_nonblocking(true)?;
-        while !shutdown_bool.load(Ordering::Relaxed) {
-            match self.accept_client(&listener) {
-                Ok(connection_stream) => {
-                    let socket_addr = *connection_stream.id();
-                    self.run_client(connection_stream, &mut thread_joiner)
-                        .unwrap_or_else(|e| eprintln!("{}: Error - {}", socket_addr, e));
-                }
-                Err(e) if e.kind() == ServerErrorKind::Idle => {
-                    thread::sleep(ACCEPT_SLEEP_DUR);
-                }
+        let snapshotter = self.spawn_snapshotter()?;
+        let mut connections = Connections::default();
+        let mut workers = Workers::new(
+            self.config.worker_threads(),
+            self.limits().max_queued_requests,
+            waker,
+        );
+        let mut events = Events::with_capacity(EVENTS_CAPACITY);
+        let mut last_idle_check = Instant::now();
+        // Al pedirse el apagado se sigue atendiendo a los clientes hasta
+        // que se vayan o venza el plazo que se les dio
+        let mut closing_at = None;
+        loop {
+            let stopping = shutdown_bool.load(Ordering::Relaxed)
+                || self.storage_failed.load(Ordering::Relaxed);
+            if closing_at.is_none() && stopping {
+                closing_at = Some(self.start_shutdown(listener.take(), poll.registry()));
+            }
+            workers.set_max_queued(self.limits().max_queued_requests);
+            let timeout = match closing_at {
+                Some(at) if connections.is_empty() || Instant::now() >= at => break,
+                Some(at) => at
+                    .saturating_duration_since(Instant::now())
+                    .min(IDLE_CHECK_PERIOD),
+                None => IDLE_CHECK_PERIOD,
+            };
+            match poll.poll(&mut events, Some(timeout)) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
                 Err(e) => {
-                    eprintln!("New connection error: {}", e);
+                    eprintln!("Error waiting for connections: {}", e);
                     break;
                 }
+                Ok(()) => (),
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    LISTENER => {
+                        if let Some(listener) = &listener {
+                            connections.accept(listener, poll.registry());
+                        }
+                    }
+                    WAKER => (),
+                    token => {
+                        let open = match connections.get_mut(token) {
+                            Some(connection) => {
+                                self.serve_event(token, connection, event, &mut workers)
+                                    && update_interest(token, connection, poll.registry())
+                            }
+                            None => continue,
+                        };
+                        if !open {
+                            self.disconnect(connections.remove(token));
+                        }
+                    }
+                }
+            }
+
+            for done in workers.finished() {
+                let open = match connections.get_mut(done.token) {
+                    Some(connection) => {
+                        connection.set_busy(false);
+                        done.open
+                            && self.resume_client(done.token, connection, &mut workers)
+                            && update_interest(done.token, connection, poll.registry())
+                    }
+                    None => continue,
+                };
+                if !open {
+                    self.disconnect(connections.remove(done.token));
+                }
+            }
+
+            if last_idle_check.elapsed() >= IDLE_CHECK_PERIOD {
+                for connection in connections.remove_idle(CONNECTION_WAIT_TIMEOUT) {
+                    println!("{}: Connection timed out", connection.id());
+                    self.disconnect(Some(connection));
+                }
+                last_idle_check = Instant::now();
             }
         }
+
+        // Primero se terminan los pedidos en curso y los encolados
+        drop(workers);
+        for connection in connections.drain() {
+            self.disconnect(Some(connection));
+        }
+        drop(snapshotter);
         self.shutdown()
     }
 
-    fn run_client(
-        self: &Arc<Self>,
-        network_connection: NetworkConnection<TcpStream, SocketAddr>,
-        thread_joiner: &mut ThreadJoiner,
-    ) -> ServerResult<()> {
+    fn spawn_snapshotter(self: &Arc<Self>) -> ServerResult<Option<Snapshotter>> {
+        if self.config.snapshot_path().is_none() {
+            return Ok(None);
+        }
+
         let sv_copy = self.clone();
-        thread_joiner.spawn(move || {
-            sv_copy._run_client(network_connection).unwrap_or_else(|e| {
-                if e.kind() != ServerErrorKind::ClientDisconnected {
-                    eprintln!("Unhandled error {}", e);
-                }
-            });
-        });
+        let snapshotter = Snapshotter::spawn(self.config.snapshot_interval(), move || {
+            sv_copy
+                .save_snapshot()
+                .unwrap_or_else(|e| eprintln!("Error while saving snapshot: {}", e));
+        })?;
+        Ok(Some(snapshotter))
+    }
+
+    fn save_snapshot(self: &Arc<Self>) -> ServerResult<()> {
+        if let Some(path) = self.config.snapshot_path() {
+            let machines = self.machines.snapshot()?;
+            snapshot::save(path, &Snapshot::new(machines))?;
+        }
         Ok(())
     }
 
+    /// Deja de aceptar conexiones y avisa a los clientes cuánto tiempo
+    /// tienen para terminar. Devuelve cuándo se cortan las conexiones.
+    fn start_shutdown(
+        self: &Arc<Self>,
+        listener: Option<TcpListener>,
+        registry: &Registry,
+    ) -> Instant {
+        if let Some(mut listener) = listener {
+            if let Err(e) = registry.deregister(&mut listener) {
+                eprintln!("Error closing the listener: {}", e);
+            }
+        }
+        let grace = self.limits().shutdown_grace;
+        println!(
+            "Shutting down server: closing connections in {} seconds...",
+            grace.as_secs()
+        );
+        let secs = u32::try_from(grace.as_secs()).unwrap_or(u32::MAX);
+        self.clients
+            .broadcast(ServerMessage::ShuttingDown(secs), None);
+        Instant::now() + grace
+    }
+
+    /// Las cuentas ya se guardaron al cerrar cada sesión; falta el snapshot.
     fn shutdown(self: &Arc<Self>) -> ServerResult<()> {
-        println!("Shutting down server...");
-        Ok(())
+        println!("Saving server state...");
+        self.save_snapshot()
     }
 
-    fn _run_client(
-        self: Arc<Self>,
-        mut network_connection: NetworkConnection<TcpStream, SocketAddr>,
-    ) -> ServerResult<()> {
-        println!("New connection from: {:?}", network_connection.id());
-        self.client_loop(&mut network_connection).unwrap_or(false);
-        println!("Connection closed: {:?}", network_connection.id());
-        Ok(())
+    /// Atiende un evento de la conexión. Devuelve si sigue abierta.
+    fn serve_event(
+        self: &Arc<Self>,
+        token: Token,
+        connection: &mut Connection,
+        event: &Event,
+        workers: &mut Workers,
+    ) -> bool {
+        if event.is_writable() {
+            if let Err(e) = connection.outbox().flush() {
+                eprintln!("{}: Error while writing - {}", connection.id(), e);
+                // Si hay un pedido en curso, se da de baja cuando termine
+                connection.outbox().close();
+                return connection.busy();
+            }
+        }
+        // Mientras se atiende un pedido no se lee: se retoma al terminar
+        if connection.busy() {
+            return true;
+        }
+        if event.is_readable() || event.is_read_closed() || event.is_error() {
+            return self.read_client(connection) && self.serve_client(token, connection, workers);
+        }
+        true
     }
 
-    fn client_loop(
+    /// Sigue con la conexión cuando un worker terminó su pedido.
+    fn resume_client(
         self: &Arc<Self>,
-        network_connection: &mut NetworkConnection<TcpStream, SocketAddr>,
-    ) -> ServerResult<bool> {
-        let mut stream_to_client = StreamToClient::new(network_connection.stream().try_clone()?);
+        token: Token,
+        connection: &mut Connection,
+        workers: &mut Workers,
+    ) -> bool {
+        (connection.eof() || self.read_client(connection))
+            && self.serve_client(token, connection, workers)
+    }
+
+    /// Lee lo que mandó el cliente, hasta llenar el buffer de la conexión.
+    /// Devuelve si la conexión sigue abierta.
+    fn read_client(self: &Arc<Self>, connection: &mut Connection) -> bool {
+        let mut buffer = [0u8; READ_BUFFER_LEN];
         loop {
-            match stream_to_client.recv_message() {
-                Ok(client_message) => {
-                    let response = self.process_message(client_message);
-                    match response {
-                        Some(response) => stream_to_client
-                            .send_message(response)
-                            .expect("Protocol error"),
-                        None => return Ok(true),
-                    }
+            match connection.receive(&mut buffer) {
+                Ok(0) => return true,
+                Ok(_) => (),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    eprintln!("{}: Unexpected error: {}", connection.id(), e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Manda a los workers el próximo mensaje completo que llegó, salvo que
+    /// estén atendiendo otro del mismo cliente. Devuelve si la conexión
+    /// sigue abierta: se cierra cuando el cliente se va o algo falla.
+    fn serve_client(
+        self: &Arc<Self>,
+        token: Token,
+        connection: &mut Connection,
+        workers: &mut Workers,
+    ) -> bool {
+        let id = *connection.id();
+        while !connection.busy() {
+            let incoming = match connection.next_incoming() {
+                Ok(Some(incoming)) => incoming,
+                Ok(None) => return !connection.eof(),
+                Err(err) if connection.wire().is_none() => {
+                    eprintln!("{}: Handshake failed - {}", id, err);
+                    return false;
                 }
                 Err(err) => {
-                    eprintln!("Unexpected error: {}", err);
-                    return Err(ServerError::from(err));
+                    eprintln!("{}: Unexpected error: {}", id, err);
+                    return false;
+                }
+            };
+            if !self.dispatch(token, connection, incoming, workers) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Encola el handshake o el mensaje en los workers. Si no hay lugar,
+    /// el cliente recibe `ServerBusy`: a los que recién llegan se los corta.
+    fn dispatch(
+        self: &Arc<Self>,
+        token: Token,
+        connection: &mut Connection,
+        incoming: Incoming,
+        workers: &mut Workers,
+    ) -> bool {
+        let id = *connection.id();
+        let server = self.clone();
+        let network = connection.network().clone();
+        let outbox = connection.outbox().clone();
+        let accepted = match incoming {
+            Incoming::Handshake { wire, reply } => {
+                if let Err(e) = connection.outbox().write(&reply) {
+                    return handled(Err(e));
+                }
+                let wire = match wire {
+                    Ok(wire) => wire,
+                    Err(err) => {
+                        eprintln!("{}: Handshake failed - {}", id, err);
+                        return false;
+                    }
+                };
+                if !workers.execute(token, move |waited| {
+                    server.serve_handshake(&network, &outbox, wire, waited)
+                }) {
+                    return handled(reject_client(&id, connection.outbox(), wire));
+                }
+                true
+            }
+            Incoming::Message(client_message) => workers.execute(token, move |waited| {
+                server.serve_message(&network, client_message, waited)
+            }),
+        };
+
+        if accepted {
+            connection.set_busy(true);
+            return true;
+        }
+        handled(
+            self.clients
+                .send_to(&id, ServerMessage::ServerBusy)
+                .map(|()| true),
+        )
+    }
+
+    /// Corre en un worker. Devuelve si la conexión sigue abierta.
+    fn serve_handshake(
+        self: &Arc<Self>,
+        network: &Mutex<PlayerConnection>,
+        outbox: &Arc<Outbox>,
+        wire: Wire,
+        waited: Duration,
+    ) -> bool {
+        let result = network
+            .lock()
+            .map_err(ServerError::from)
+            .and_then(|mut network| {
+                if waited > QUEUE_TIMEOUT {
+                    return reject_client(network.id(), outbox, wire);
+                }
+                self.start_client(&mut network, outbox, wire)
+            });
+        handled(result)
+    }
+
+    /// Corre en un worker. Devuelve si la conexión sigue abierta.
+    fn serve_message(
+        self: &Arc<Self>,
+        network: &Mutex<PlayerConnection>,
+        client_message: ClientMessage,
+        waited: Duration,
+    ) -> bool {
+        let result = network
+            .lock()
+            .map_err(ServerError::from)
+            .and_then(|mut network| {
+                let id = *network.id();
+                if waited > QUEUE_TIMEOUT {
+                    return self
+                        .clients
+                        .send_to(&id, ServerMessage::ServerBusy)
+                        .map(|()| true);
+                }
+                match self.process_message(&mut network, client_message) {
+                    Some(response) => self.clients.send_to(&id, response).map(|()| true),
+                    None => Ok(false),
+                }
+            });
+        handled(result)
+    }
+
+    /// Después del handshake el cliente ya puede recibir mensajes.
+    /// Devuelve si la conexión sigue abierta.
+    fn start_client(
+        self: &Arc<Self>,
+        network_connection: &mut PlayerConnection,
+        outbox: &Arc<Outbox>,
+        wire: Wire,
+    ) -> ServerResult<bool> {
+        let id = *network_connection.id();
+        println!("{}: Using protocol version {}", id, wire);
+        self.clients.add(id, outbox.clone(), wire)?;
+
+        // Los clientes sin framing no pueden mandar Hello
+        if wire == Wire::Legacy {
+            let session = self.sessions.guest(&id.to_string())?;
+            // ni entienden la fila de espera
+            if let Join::Queued(_) = self.start_session(network_connection, session)? {
+                println!("{}: Machine full, closing legacy connection", id);
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Da de baja la conexión: libera su lugar en la máquina y cierra su sesión.
+    fn disconnect(self: &Arc<Self>, connection: Option<Connection>) {
+        let Some(connection) = connection else {
+            return;
+        };
+        let id = *connection.id();
+        match connection.network().lock() {
+            Ok(mut network) => {
+                self.end_client(&network)
+                    .unwrap_or_else(|e| eprintln!("{}: Error - {}", id, e));
+                // Puede que el cliente ya la haya cerrado
+                let _ = network.close();
+            }
+            Err(e) => {
+                eprintln!("{}: Error - {}", id, e);
+                connection.outbox().close();
+            }
+        }
+        println!("Connection closed: {:?}", id);
+    }
+
+    fn end_client(self: &Arc<Self>, network_connection: &PlayerConnection) -> ServerResult<()> {
+        let id = *network_connection.id();
+        self.clients.remove(&id)?;
+        let vacated = self.machines.leave(&id)?;
+        self.notify_vacated(vacated);
+        if let Some(session) = network_connection.session() {
+            self.sessions.logout(session)?;
+            self.clients
+                .broadcast(Notification::PlayerLeft(session.player().to_string()), None);
+        }
+        Ok(())
+    }
+
+    fn process_message(
+        self: &Arc<Self>,
+        network_connection: &mut PlayerConnection,
+        client_message: ClientMessage,
+    ) -> Option<ServerMessage> {
+        let id = *network_connection.id();
+        match (client_message, network_connection.session().cloned()) {
+            (ClientMessage::Quit, _) => None,
+            (ClientMessage::Hello { name, token }, None) => {
+                self.login(network_connection, &name, &token)
+            }
+            (ClientMessage::Hello { .. }, Some(_)) => Some(ServerMessage::LoginRejected(
+                "Already logged in".to_string(),
+            )),
+            (_, None) => Some(ServerMessage::LoginRequired),
+            (ClientMessage::Insert, Some(session)) => {
+                let fairness = match fair_room(network_connection, 1) {
+                    Ok(fairness) => fairness,
+                    Err(reply) => return Some(reply),
+                };
+                let played = self.play(&id, &session, 1, fairness, |hand| {
+                    let fell = hand.throw(None).fell;
+                    (fell, fell)
+                })?;
+                match played {
+                    Ok(fell) => Some(ServerMessage::FellCoins(fell)),
+                    Err(reply) => Some(reply),
+                }
+            }
+            (ClientMessage::InsertMany(n), Some(session)) => {
+                let max = self.limits().max_batch_size;
+                if n > max {
+                    return Some(ServerMessage::BatchTooLarge(max));
+                }
+                let fairness = match fair_room(network_connection, n) {
+                    Ok(fairness) => fairness,
+                    Err(reply) => return Some(reply),
+                };
+                let played = self.play(&id, &session, n, fairness, |hand| {
+                    let outcomes: Vec<u32> = (0..n).map(|_| hand.throw(None).fell).collect();
+                    let total = outcomes.iter().sum();
+                    ((outcomes, total), total)
+                })?;
+                match played {
+                    Ok((outcomes, total)) => {
+                        Some(ServerMessage::FellCoinsBatch { outcomes, total })
+                    }
+                    Err(reply) => Some(reply),
+                }
+            }
+            (ClientMessage::InsertAt(lane), Some(session)) => {
+                // Las dimensiones del estante no cambian, así que alcanza con
+                // revisar el carril antes de cobrar la moneda
+                if let Some(reply) = self.check_lane(&id, lane)? {
+                    return Some(reply);
+                }
+                let fairness = match fair_room(network_connection, 1) {
+                    Ok(fairness) => fairness,
+                    Err(reply) => return Some(reply),
+                };
+                let played = self.play(&id, &session, 1, fairness, |hand| {
+                    let drop = hand.throw(Some(lane));
+                    (drop, drop.fell)
+                })?;
+                match played {
+                    Ok(drop) => Some(ServerMessage::Dropped {
+                        lane: drop.lane,
+                        fell: drop.fell,
+                    }),
+                    Err(reply) => Some(reply),
+                }
+            }
+            (ClientMessage::ConsultBoard, Some(_)) => match self.machines.seat_of(&id).ok()? {
+                Some(machine) => match self.machines.lock(machine).ok()?.board() {
+                    Some(board) => Some(ServerMessage::BoardState(BoardInfo {
+                        machine,
+                        width: board.config().width(),
+                        depth: board.config().depth(),
+                        cells: board.cells().to_vec(),
+                    })),
+                    None => Some(ServerMessage::NotABoard(machine)),
+                },
+                None => Some(ServerMessage::NotInMachine),
+            },
+            (ClientMessage::ConsultPool, Some(_)) => match self.machines.seat_of(&id).ok()? {
+                Some(machine) => {
+                    let coins = self.machines.lock(machine).ok()?.get_pool();
+                    Some(ServerMessage::PoolState(coins))
+                }
+                None => Some(ServerMessage::NotInMachine),
+            },
+            (ClientMessage::ConsultJackpot, Some(_)) => match self.machines.seat_of(&id).ok()? {
+                Some(machine) => {
+                    let machine = self.machines.lock(machine).ok()?;
+                    let coins = machine.jackpot().map_or(0, |jackpot| jackpot.coins());
+                    Some(ServerMessage::JackpotState(coins))
                 }
+                None => Some(ServerMessage::NotInMachine),
+            },
+            (ClientMessage::ConsultBalance, Some(session)) => {
+                let account = self.sessions.account(&session).ok()?;
+                Some(ServerMessage::Balance(account.wallet().balance()))
             }
+            (ClientMessage::ConsultInventory, Some(session)) => {
+                let account = self.sessions.account(&session).ok()?;
+                Some(ServerMessage::Inventory(account.inventory().items()))
+            }
+            (ClientMessage::ListMachines, Some(_)) => {
+                Some(ServerMessage::MachineList(self.machines.list().ok()?))
+            }
+            (ClientMessage::JoinMachine(machine), Some(_)) => {
+                if !self.machines.contains(machine) {
+                    return Some(ServerMessage::UnknownMachine(machine));
+                }
+                match self.join_machine(&id, machine).ok()? {
+                    Join::Seated => Some(ServerMessage::Joined(machine)),
+                    Join::Queued(position) => {
+                        Some(ServerMessage::MachineFull { machine, position })
+                    }
+                }
+            }
+            (ClientMessage::SetClientSeed(seed), Some(_)) => {
+                let fairness = network_connection.fairness_mut()?;
+                match fairness.set_client_seed(seed) {
+                    Ok(()) => {
+                        self.commit_payout(&id, fairness).ok()?;
+                        Some(fairness.commitment())
+                    }
+                    Err(reply) => Some(reply),
+                }
+            }
+            (ClientMessage::ConsultCommitment, Some(_)) => {
+                let fairness = network_connection.fairness_mut()?;
+                self.commit_payout(&id, fairness).ok()?;
+                Some(fairness.commitment())
+            }
+            (ClientMessage::RevealSeed, Some(_)) => {
+                Some(network_connection.fairness_mut()?.reveal())
+            }
+            (ClientMessage::LeaveMachine, Some(_)) => match self.machines.leave(&id).ok()? {
+                Some(vacated) => {
+                    self.notify_vacated(Some(vacated));
+                    Some(ServerMessage::Left(vacated.machine))
+                }
+                None => Some(ServerMessage::NotInMachine),
+            },
         }
     }
 
-    fn process_message(self: &Arc<Self>, client_message: ClientMessage) -> Option<ServerMessage> {
-        match client_message {
-            ClientMessage::Insert => {
-                let fell_coins = self.coin_machine.lock().ok()?.insert_coin();
-                Some(ServerMessage::FellCoins(fell_coins))
+    /// Compromete las semillas con el modelo de pago de la máquina en la
+    /// que está sentado el jugador, si su cliente lo recibe en el compromiso.
+    fn commit_payout(&self, id: &SocketAddr, fairness: &mut Fairness) -> ServerResult<()> {
+        if !self
+            .clients
+            .wire(id)?
+            .is_some_and(|wire| wire.commits_payout())
+        {
+            return Ok(());
+        }
+        let payout = match self.machines.seat_of(id)? {
+            Some(machine) => {
+                let machine = self.machines.lock(machine)?;
+                // En un estante el modelo de pago no decide nada
+                match machine.board() {
+                    Some(_) => None,
+                    None => machine.payout().cloned(),
+                }
             }
-            ClientMessage::ConsultPool => {
-                let coins = self.coin_machine.lock().ok()?.get_pool();
-                Some(ServerMessage::PoolState(coins))
+            None => None,
+        };
+        fairness.commit_payout(payout);
+        Ok(())
+    }
+
+    fn login(
+        self: &Arc<Self>,
+        network_connection: &mut PlayerConnection,
+        name: &str,
+        token: &str,
+    ) -> Option<ServerMessage> {
+        match self.sessions.login(name, token).ok()? {
+            Ok(session) => {
+                let session_id = session.id();
+                self.start_session(network_connection, session).ok()?;
+                Some(ServerMessage::Welcome(session_id))
+            }
+            Err(e) => {
+                println!("{}: Login rejected - {}", network_connection.id(), e);
+                Some(ServerMessage::LoginRejected(e.to_string()))
+            }
+        }
+    }
+
+    /// Al entrar, el jugador pide lugar en la máquina por defecto.
+    fn start_session(
+        self: &Arc<Self>,
+        network_connection: &mut PlayerConnection,
+        session: Session,
+    ) -> ServerResult<Join> {
+        let account = self.sessions.account(&session)?;
+        println!(
+            "{}: Logged in as {} (session {}, {} coins inserted and {} won so far)",
+            network_connection.id(),
+            session.player(),
+            session.id(),
+            account.coins_inserted(),
+            account.coins_won()
+        );
+        self.clients.broadcast(
+            Notification::PlayerJoined(session.player().to_string()),
+            Some(network_connection.id()),
+        );
+        network_connection.set_session(session);
+        self.join_machine(network_connection.id(), self.machines.default_id())
+    }
+
+    fn join_machine(self: &Arc<Self>, id: &SocketAddr, machine: MachineId) -> ServerResult<Join> {
+        let (join, vacated) = self.machines.join(machine, *id)?;
+        self.notify_vacated(vacated);
+        if let Join::Queued(position) = join {
+            println!(
+                "{}: Waiting for machine {} ({} in queue)",
+                id, machine, position
+            );
+        }
+        Ok(join)
+    }
+
+    /// Avisa al jugador que ocupó el lugar liberado, si lo hay.
+    fn notify_vacated(self: &Arc<Self>, vacated: Option<Vacated>) {
+        if let Some(Vacated {
+            machine,
+            promoted: Some(promoted),
+        }) = vacated
+        {
+            println!("{}: Seated at machine {}", promoted, machine);
+            self.clients
+                .notify(&promoted, Notification::Seated(machine));
+        }
+    }
+
+    /// Respuesta para una moneda arrojada en un carril que no se puede usar.
+    fn check_lane(&self, id: &SocketAddr, lane: u32) -> Option<Option<ServerMessage>> {
+        let machine_id = match self.machines.seat_of(id).ok()? {
+            Some(machine_id) => machine_id,
+            None => return Some(Some(ServerMessage::NotInMachine)),
+        };
+        let machine = self.machines.lock(machine_id).ok()?;
+        Some(match machine.board().map(|board| board.config().width()) {
+            None => Some(ServerMessage::NotABoard(machine_id)),
+            Some(width) if lane >= width => Some(ServerMessage::InvalidLane(width)),
+            Some(_) => None,
+        })
+    }
+
+    /// Descuenta `n` monedas de la billetera del jugador, las arroja en la
+    /// máquina donde está sentado con `insert` y le acredita las que caen.
+    /// `insert` devuelve su resultado y el total de monedas que cayeron.
+    /// Si no le alcanza, o no está sentado en ninguna, devuelve la respuesta.
+    ///
+    /// Las monedas gratis del jugador se usan antes que las de la billetera,
+    /// y sus fichas multiplicadoras se gastan en las monedas que pagan.
+    /// Los premios que caen van a su inventario, igual que las monedas que
+    /// rebalsan la máquina si su política se las da al jugador.
+    fn play<T>(
+        self: &Arc<Self>,
+        id: &SocketAddr,
+        session: &Session,
+        n: u32,
+        fairness: Option<&mut Fairness>,
+        insert: impl FnOnce(&mut Hand) -> (T, u32),
+    ) -> Option<Result<T, ServerMessage>> {
+        if self.storage_failed.load(Ordering::Relaxed) {
+            return Some(Err(ServerMessage::ServerBusy));
+        }
+        let machine_id = match self.machines.seat_of(id).ok()? {
+            Some(machine_id) => machine_id,
+            None => return Some(Err(ServerMessage::NotInMachine)),
+        };
+        let (played, saved) = self
+            .sessions
+            .with_account(session, |account| {
+                // La máquina queda bloqueada solo mientras caen las monedas
+                let (outcomes, total, coins, pool) = {
+                    let mut machine = self.machines.lock(machine_id)?;
+                    if n > machine.room() {
+                        return Ok(Err(ServerMessage::PoolFull(machine.room())));
+                    }
+                    if fairness
+                        .as_deref()
+                        .is_some_and(|fairness| !fairness.pays_as_committed(&machine))
+                    {
+                        return Ok(Err(ServerMessage::PayoutChanged));
+                    }
+                    let free = n.min(account.inventory().count(Item::FreeDrop));
+                    if account.wallet_mut().debit(n - free).is_err() {
+                        let balance = account.wallet().balance();
+                        return Ok(Err(ServerMessage::InsufficientFunds(balance)));
+                    }
+                    account.inventory_mut().take(Item::FreeDrop, free);
+
+                    let mut hand = Hand::new(&mut machine, fairness);
+                    let (outcomes, total) = insert(&mut hand);
+                    (outcomes, total, hand.coins, machine.get_pool())
+                };
+
+                let jackpots: Vec<u32> = jackpots(&coins).collect();
+                let items: Vec<Item> = items(&coins).collect();
+                let inventory = account.inventory_mut();
+                let bonus: u32 = coins.iter().map(|c| inventory.multiply(c.fell)).sum();
+                let spilled = spilled(&coins);
+                items.iter().for_each(|item| inventory.add(*item));
+                let won = total + bonus + spilled + jackpots.iter().sum::<u32>();
+                account.wallet_mut().credit(won);
+                account.record_play(n, won);
+                Ok(Ok((outcomes, total, coins, jackpots, items, pool)))
+            })
+            .map_err(|e| eprintln!("{}: Error while playing - {}", id, e))
+            .ok()?;
+
+        // Sin ningún lock tomado; `machine_seq` ordena las monedas en el registro.
+        // Las monedas ya cayeron, así que la jugada vale y se registra aunque
+        // la cuenta no se haya guardado, pero no se juega más
+        if let Err(e) = saved {
+            eprintln!(
+                "{}: Error while saving account - {} - shutting down the server",
+                id, e
+            );
+            self.storage_failed.store(true, Ordering::Relaxed);
+        }
+        if let Ok((_, _, coins, ..)) = &played {
+            if let Err(e) = self.audit(id, session, machine_id, coins) {
+                eprintln!(
+                    "{}: Error while writing audit log - {} - shutting down the server",
+                    id, e
+                );
+                self.storage_failed.store(true, Ordering::Relaxed);
             }
-            ClientMessage::Quit => None,
         }
+
+        Some(played.map(|(outcomes, total, _, jackpots, items, pool)| {
+            self.notify_insert(id, session, machine_id, total, pool);
+            if !items.is_empty() {
+                println!(
+                    "{}: {} won {:?} at machine {}",
+                    id,
+                    session.player(),
+                    items,
+                    machine_id
+                );
+                let won = ServerMessage::ItemsWon {
+                    machine: machine_id,
+                    items,
+                };
+                self.clients.notify(id, won);
+            }
+            for coins in jackpots {
+                println!(
+                    "{}: {} won the jackpot of machine {} ({} coins)",
+                    id,
+                    session.player(),
+                    machine_id,
+                    coins
+                );
+                let jackpot = ServerMessage::Jackpot {
+                    machine: machine_id,
+                    player: session.player().to_string(),
+                    coins,
+                };
+                self.clients.broadcast(jackpot, None);
+            }
+            outcomes
+        }))
+    }
+
+    fn audit(
+        &self,
+        id: &SocketAddr,
+        session: &Session,
+        machine: MachineId,
+        coins: &[Coin],
+    ) -> ServerResult<()> {
+        let Some(audit) = &self.audit else {
+            return Ok(());
+        };
+        let timestamp = audit::now_millis();
+        let events = coins.iter().map(|coin| CoinEvent {
+            timestamp,
+            connection: id.to_string(),
+            session: session.id(),
+            player: session.player().to_string(),
+            machine,
+            pool_before: coin.pool_before,
+            pool_after: coin.pool_after,
+            fell: coin.fell,
+            spilled: coin.spilled,
+            jackpot: coin.jackpot,
+            item: coin.item,
+            nonce: coin.nonce,
+            fair: coin.fair,
+            machine_seq: coin.seq,
+        });
+        audit.record(events)?;
+        Ok(())
+    }
+
+    fn notify_insert(
+        self: &Arc<Self>,
+        id: &SocketAddr,
+        session: &Session,
+        machine: MachineId,
+        fell_coins: u32,
+        pool: u32,
+    ) {
+        if fell_coins > 0 {
+            let notification = Notification::CoinsFell {
+                player: session.player().to_string(),
+                coins: fell_coins,
+            };
+            self.clients.broadcast(notification, Some(id));
+        }
+        self.clients
+            .broadcast(Notification::PoolChanged { machine, pool }, Some(id));
+    }
+}
+
+/// Ajusta qué eventos se esperan de la conexión. Devuelve si sigue abierta.
+fn update_interest(token: Token, connection: &mut Connection, registry: &Registry) -> bool {
+    match connection.update_interest(registry, token) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{}: Error registering connection - {}", connection.id(), e);
+            false
+        }
+    }
+}
+
+/// Si la conexión sigue abierta después de atender un pedido.
+fn handled(result: ServerResult<bool>) -> bool {
+    match result {
+        Ok(open) => open,
+        Err(e) => {
+            if e.kind() != ServerErrorKind::ClientDisconnected {
+                eprintln!("Unhandled error {}", e);
+            }
+            false
+        }
+    }
+}
+
+/// Corta a un cliente recién llegado porque el servidor no da abasto.
+/// Los clientes sin framing no entienden el aviso; solo se los corta.
+fn reject_client(id: &SocketAddr, outbox: &Outbox, wire: Wire) -> ServerResult<bool> {
+    if wire != Wire::Legacy {
+        outbox.write(&wire.encode(ServerMessage::ServerBusy)?)?;
+    }
+    println!("{}: Server busy, closing connection", id);
+    Ok(false)
+}
+
+/// Semillas de la conexión si juega de manera verificable y le quedan
+/// `n` jugadas antes de tener que revelar la semilla del servidor.
+fn fair_room(
+    network_connection: &mut PlayerConnection,
+    n: u32,
+) -> Result<Option<&mut Fairness>, ServerMessage> {
+    match network_connection.fairness_mut() {
+        Some(fairness) if fairness.is_active() => {
+            fairness.check_room(n)?;
+            Ok(Some(fairness))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Monedas arrojadas en una jugada, con lo necesario para auditarlas.
+struct Hand<'a> {
+    machine: &'a mut Machine,
+    fairness: Option<&'a mut Fairness>,
+    coins: Vec<Coin>,
+}
+
+struct Coin {
+    seq: u64,
+    pool_before: u32,
+    pool_after: u32,
+    fell: u32,
+    spilled: u32,
+    jackpot: u32,
+    item: Option<Item>,
+    nonce: u64,
+    fair: bool,
+}
+
+/// Premios del pozo que salieron en la jugada.
+fn jackpots(coins: &[Coin]) -> impl Iterator<Item = u32> + '_ {
+    coins
+        .iter()
+        .map(|coin| coin.jackpot)
+        .filter(|jackpot| *jackpot > 0)
+}
+
+/// Monedas que rebalsaron la máquina hacia el jugador en la jugada.
+fn spilled(coins: &[Coin]) -> u32 {
+    coins.iter().map(|coin| coin.spilled).sum()
+}
+
+/// Premios que cayeron del estante en la jugada.
+fn items(coins: &[Coin]) -> impl Iterator<Item = Item> + '_ {
+    coins.iter().filter_map(|coin| coin.item)
+}
+
+impl<'a> Hand<'a> {
+    fn new(machine: &'a mut Machine, fairness: Option<&'a mut Fairness>) -> Hand<'a> {
+        Hand {
+            machine,
+            fairness,
+            coins: Vec::new(),
+        }
+    }
+
+    /// Arroja una moneda con el azar de las semillas si el jugador juega de
+    /// manera verificable, o con el generador de la máquina si no.
+    fn throw(&mut self, lane: Option<u32>) -> CoinOutcome {
+        let seq = self.machine.thrown();
+        let pool_before = self.machine.get_pool();
+        let (nonce, outcome) = match (&mut self.fairness, lane) {
+            (Some(fairness), lane) => {
+                (fairness.nonce(), fairness.throw(self.machine, lane).outcome)
+            }
+            (None, Some(lane)) => (self.machine.nonce(), self.machine.insert_coin_at(lane)),
+            (None, None) => (self.machine.nonce(), self.machine.insert_coin()),
+        };
+        self.coins.push(Coin {
+            seq,
+            pool_before,
+            pool_after: self.machine.get_pool(),
+            fell: outcome.fell,
+            spilled: outcome.spilled,
+            jackpot: outcome.jackpot,
+            item: outcome.item,
+            nonce,
+            fair: self.fairness.is_some(),
+        });
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod server_tests {
+    use super::*;
+    use crate::accounts::Account;
+    use crate::config::FileConfig;
+    use std::io::Cursor;
+    use std::{env, fs};
+
+    /// Guarda las cuentas en memoria hasta que se le pide fallar.
+    #[derive(Default)]
+    struct FailingStore {
+        memory: MemoryAccountStore,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl AccountStore for FailingStore {
+        fn load(&self, name: &str) -> io::Result<Option<Account>> {
+            self.memory.load(name)
+        }
+
+        fn save(&self, account: &Account) -> io::Result<()> {
+            if self.failing.load(Ordering::Relaxed) {
+                return Err(io::Error::other("disk full"));
+            }
+            self.memory.save(account)
+        }
+    }
+
+    #[test]
+    fn plays_are_audited_when_the_account_cannot_be_saved() {
+        let audit_path = env::temp_dir().join(format!(
+            "coin_pusher_server_audit_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&audit_path);
+        let config = FileConfig::new_from_file(Cursor::new(format!(
+            "port=0\nhost=localhost\ninitial_coins_count=100\ninitial_balance=10\naudit_path={}",
+            audit_path.display()
+        )))
+        .unwrap();
+        let store = FailingStore::default();
+        let failing = store.failing.clone();
+        let server = Server::with_store(config, Box::new(store)).unwrap();
+        let id: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let session = server.sessions.login("ana", "").unwrap().unwrap();
+        let machine = server.machines.default_id();
+        server.join_machine(&id, machine).unwrap();
+
+        failing.store(true, Ordering::Relaxed);
+        let played = server.play(&id, &session, 3, None, |hand| {
+            let fell = (0..3).map(|_| hand.throw(None).fell).sum();
+            (fell, fell)
+        });
+
+        let fell = played.unwrap().unwrap();
+        assert_eq!(audit::entries(&audit_path).unwrap().len(), 3);
+        let account = server.sessions.account(&session).unwrap();
+        assert_eq!(account.wallet().balance(), 10 - 3 + fell);
+        // No se juega más hasta reiniciar
+        assert!(matches!(
+            server.play(&id, &session, 1, None, |hand| ((), hand.throw(None).fell)),
+            Some(Err(ServerMessage::ServerBusy))
+        ));
+        let _ = fs::remove_file(&audit_path);
     }
 }
@@ -1,30 +1,32 @@
-use std::{
-    io::{self},
-    time::Duration,
-};
+use std::io::{self};
 
-use crate::{
-    server::{server_error::ServerErrorKind, ServerError, ServerResult},
-    server::{traits::Close, traits::Interrupt, traits::TryClone},
-};
+use crate::{server::fairness::Fairness, server::sessions::Session, server::traits::Close};
 
 #[derive(Debug)]
 pub struct NetworkConnection<S, I> {
     id: I,
     stream: S,
+    session: Option<Session>,
+    fairness: Option<Fairness>,
 }
 
 impl<S, I> NetworkConnection<S, I> {
-    pub fn stream(&self) -> &S {
-        &self.stream
-    }
-    #[allow(dead_code)]
-    pub fn stream_mut(&mut self) -> &mut S {
-        &mut self.stream
-    }
     pub fn id(&self) -> &I {
         &self.id
     }
+    /// Sesión del jugador, si ya se identificó
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+    /// Cada sesión juega con una semilla del servidor nueva.
+    pub fn set_session(&mut self, session: Session) {
+        self.session = Some(session);
+        self.fairness = Some(Fairness::new());
+    }
+    /// Semillas para jugar de manera verificable, si ya se identificó
+    pub fn fairness_mut(&mut self) -> Option<&mut Fairness> {
+        self.fairness.as_mut()
+    }
 }
 
 impl<S: io::Read, I> io::Read for NetworkConnection<S, I> {
@@ -43,44 +45,20 @@ impl<S: io::Write, I> io::Write for NetworkConnection<S, I> {
     }
 }
 
-impl<S: Interrupt, I> Interrupt for NetworkConnection<S, I> {
-    fn alert(&mut self, when: Duration) -> io::Result<()> {
-        self.stream.alert(when)
-    }
-
-    fn sleep(&mut self) -> io::Result<()> {
-        self.stream.sleep()
-    }
-}
-
 impl<S, I> NetworkConnection<S, I> {
     pub fn new(id: I, stream: S) -> Self {
-        Self { id, stream }
+        Self {
+            id,
+            stream,
+            session: None,
+            fairness: None,
+        }
     }
 
-    #[allow(dead_code)]
     pub fn close(&mut self) -> io::Result<()>
     where
         S: Close,
     {
         self.stream.close()
     }
-
-    #[allow(dead_code)]
-    pub fn try_clone(&self) -> ServerResult<Self>
-    where
-        I: Clone + Copy,
-        S: TryClone,
-    {
-        let stream = self.stream.try_clone().map_err(|e| {
-            ServerError::new_kind(
-                format!("Error while cloning network_connection stream: {}", e),
-                ServerErrorKind::Irrecoverable,
-            )
-        })?;
-        Ok(NetworkConnection {
-            id: self.id,
-            stream,
-        })
-    }
 }
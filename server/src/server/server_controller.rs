@@ -1,28 +1,60 @@
 use std::{
+    io,
     sync::{
         atomic::{AtomicBool, Ordering},
+        mpsc::Receiver,
         Arc,
     },
-    thread::JoinHandle,
+    thread::{self, JoinHandle},
 };
 
+use mio::Waker;
+
 pub struct ServerController {
     shutdown_bool: Arc<AtomicBool>,
+    // Despierta al loop de eventos para que vea el pedido de apagado
+    waker: Arc<Waker>,
     handle: Option<JoinHandle<()>>,
+    // Se cierra cuando termina el loop del servidor
+    stopped: Option<Receiver<()>>,
 }
 
 impl ServerController {
-    pub fn new(shutdown_bool: Arc<AtomicBool>, handle: JoinHandle<()>) -> ServerController {
+    pub fn new(
+        shutdown_bool: Arc<AtomicBool>,
+        waker: Arc<Waker>,
+        handle: JoinHandle<()>,
+        stopped: Receiver<()>,
+    ) -> ServerController {
         ServerController {
             shutdown_bool,
+            waker,
             handle: Some(handle),
+            stopped: Some(stopped),
         }
     }
+
+    /// Llama a `notify` cuando el servidor termina, aunque nadie se lo haya
+    /// pedido; por ejemplo, si no puede seguir registrando las jugadas.
+    pub fn on_stop<F: FnOnce() + Send + 'static>(&mut self, notify: F) -> io::Result<()> {
+        if let Some(stopped) = self.stopped.take() {
+            thread::Builder::new()
+                .name("stop_watcher".to_owned())
+                .spawn(move || {
+                    let _ = stopped.recv();
+                    notify();
+                })?;
+        }
+        Ok(())
+    }
 }
 
 impl Drop for ServerController {
     fn drop(&mut self) {
         self.shutdown_bool.store(true, Ordering::Relaxed);
+        if let Err(e) = self.waker.wake() {
+            eprintln!("Could not wake up the server loop: {}", e);
+        }
         let handle = self
             .handle
             .take()
@@ -18,7 +18,6 @@ pub enum ServerErrorKind {
     Timeout,
     PoisonedLock,
     Irrecoverable,
-    Idle,
     Other,
 }
 
@@ -0,0 +1,339 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    accounts::Account,
+    server::{traits::AccountStore, ServerError, ServerErrorKind, ServerResult},
+};
+
+const MAX_NAME_LEN: usize = 32;
+
+/// Identidad de un jugador durante una conexión.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    id: u64,
+    player: String,
+    guest: bool,
+}
+
+impl Session {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn player(&self) -> &str {
+        &self.player
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginError {
+    InvalidName,
+    WrongToken,
+    AlreadyConnected,
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoginError::InvalidName => write!(
+                f,
+                "Names must have between 1 and {} letters, digits, '-' or '_'",
+                MAX_NAME_LEN
+            ),
+            LoginError::WrongToken => write!(f, "Wrong token for that name"),
+            LoginError::AlreadyConnected => write!(f, "That player is already connected"),
+        }
+    }
+}
+
+/// Jugadores conectados y sus cuentas.
+/// Un jugador que vuelve a conectarse con el mismo nombre y token
+/// recupera su cuenta desde el `AccountStore`.
+///
+/// Cada cuenta tiene su propio lock, así los jugadores no se esperan
+/// entre sí; `online` solo se bloquea para buscarlas.
+pub struct Sessions {
+    next_id: AtomicU64,
+    store: Box<dyn AccountStore>,
+    online: Mutex<HashMap<String, Arc<Mutex<Account>>>>,
+    initial_balance: AtomicU32,
+}
+
+impl Sessions {
+    pub fn new(store: Box<dyn AccountStore>, initial_balance: u32) -> Sessions {
+        Sessions {
+            next_id: AtomicU64::new(0),
+            store,
+            online: Mutex::new(HashMap::new()),
+            initial_balance: AtomicU32::new(initial_balance),
+        }
+    }
+
+    /// Monedas con las que empiezan las cuentas nuevas.
+    pub fn set_initial_balance(&self, initial_balance: u32) {
+        self.initial_balance
+            .store(initial_balance, Ordering::Relaxed);
+    }
+
+    fn initial_balance(&self) -> u32 {
+        self.initial_balance.load(Ordering::Relaxed)
+    }
+
+    pub fn login(&self, name: &str, token: &str) -> ServerResult<Result<Session, LoginError>> {
+        if !is_valid_name(name) {
+            return Ok(Err(LoginError::InvalidName));
+        }
+
+        let account = {
+            let mut online = self.online.lock()?;
+            if let Some(account) = online.get(name) {
+                if !account.lock()?.check_token(token) {
+                    return Ok(Err(LoginError::WrongToken));
+                }
+                return Ok(Err(LoginError::AlreadyConnected));
+            }
+            let account = match self.store.load(name)? {
+                Some(account) => account,
+                None => Account::new(name, token, self.initial_balance()),
+            };
+            if !account.check_token(token) {
+                return Ok(Err(LoginError::WrongToken));
+            }
+            let account = Arc::new(Mutex::new(account));
+            online.insert(name.to_string(), account.clone());
+            account
+        };
+
+        // Ya figura conectado, así que nadie más puede entrar con ese nombre
+        let saved = account
+            .lock()
+            .map_err(ServerError::from)
+            .and_then(|mut account| {
+                account.touch();
+                Ok(self.store.save(&account)?)
+            });
+        if let Err(e) = saved {
+            self.online.lock()?.remove(name);
+            return Err(e);
+        }
+
+        Ok(Ok(self.new_session(name.to_string(), false)))
+    }
+
+    /// Sesión para los clientes sin framing, que no pueden identificarse.
+    /// Su nombre incluye un caracter que los jugadores no pueden usar,
+    /// así que nunca coincide con el de un jugador registrado.
+    /// Las cuentas de invitados no se guardan.
+    pub fn guest(&self, address: &str) -> ServerResult<Session> {
+        let name = format!("guest@{}", address);
+        let account = Account::new(&name, "", self.initial_balance());
+        self.online
+            .lock()?
+            .insert(name.clone(), Arc::new(Mutex::new(account)));
+        Ok(self.new_session(name, true))
+    }
+
+    pub fn logout(&self, session: &Session) -> ServerResult<()> {
+        let account = self.online.lock()?.remove(&session.player);
+        if let (Some(account), false) = (account, session.guest) {
+            let mut account = account.lock()?;
+            account.touch();
+            self.store.save(&account)?;
+        }
+        Ok(())
+    }
+
+    /// Copia de la cuenta del jugador.
+    pub fn account(&self, session: &Session) -> ServerResult<Account> {
+        let account = self.online_account(session)?;
+        let account = account.lock()?.clone();
+        Ok(account)
+    }
+
+    /// Aplica `action` sobre la cuenta del jugador y guarda el resultado.
+    /// Mientras dura `action`, y hasta que se guarda, solo se bloquea la
+    /// cuenta de este jugador.
+    ///
+    /// Lo que hizo `action` no se deshace, así que su resultado se devuelve
+    /// aunque no se pueda guardar la cuenta, junto con el error al guardarla.
+    /// La cuenta en memoria queda como la dejó `action`.
+    pub fn with_account<T, F>(
+        &self,
+        session: &Session,
+        action: F,
+    ) -> ServerResult<(T, ServerResult<()>)>
+    where
+        F: FnOnce(&mut Account) -> ServerResult<T>,
+    {
+        let account = self.online_account(session)?;
+        let mut account = account.lock()?;
+
+        let result = action(&mut account)?;
+        let saved = match session.guest {
+            true => Ok(()),
+            false => self.store.save(&account).map_err(ServerError::from),
+        };
+        Ok((result, saved))
+    }
+
+    fn online_account(&self, session: &Session) -> ServerResult<Arc<Mutex<Account>>> {
+        self.online
+            .lock()?
+            .get(&session.player)
+            .cloned()
+            .ok_or_else(|| not_online(session))
+    }
+
+    fn new_session(&self, player: String, guest: bool) -> Session {
+        Session {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            player,
+            guest,
+        }
+    }
+}
+
+fn not_online(session: &Session) -> ServerError {
+    ServerError::new_kind(
+        format!("{} is not logged in", session.player),
+        ServerErrorKind::Irrecoverable,
+    )
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod sessions_tests {
+    use super::*;
+    use crate::accounts::MemoryAccountStore;
+
+    fn sessions() -> Sessions {
+        Sessions::new(Box::new(MemoryAccountStore::default()), 10)
+    }
+
+    #[test]
+    fn login_new_player() {
+        let sessions = sessions();
+
+        let session = sessions.login("nacho", "secret").unwrap().unwrap();
+
+        assert_eq!(session.player(), "nacho");
+        assert_eq!(sessions.account(&session).unwrap().wallet().balance(), 10);
+    }
+
+    #[test]
+    fn sessions_have_different_ids() {
+        let sessions = sessions();
+
+        let a = sessions.login("a", "").unwrap().unwrap();
+        let b = sessions.login("b", "").unwrap().unwrap();
+
+        assert_ne!(a.id(), b.id());
+    }
+
+    #[test]
+    fn reconnect_with_same_token() {
+        let sessions = sessions();
+        let first = sessions.login("nacho", "secret").unwrap().unwrap();
+        sessions
+            .with_account(&first, |account| {
+                account.wallet_mut().credit(5);
+                Ok(())
+            })
+            .unwrap()
+            .1
+            .unwrap();
+        sessions.logout(&first).unwrap();
+
+        let second = sessions.login("nacho", "secret").unwrap().unwrap();
+
+        assert_eq!(second.player(), first.player());
+        assert_ne!(second.id(), first.id());
+        assert_eq!(sessions.account(&second).unwrap().wallet().balance(), 15);
+    }
+
+    #[test]
+    fn accounts_are_locked_one_by_one() {
+        let sessions = sessions();
+        let a = sessions.login("a", "").unwrap().unwrap();
+        let b = sessions.login("b", "").unwrap().unwrap();
+
+        let ((_, inner), outer) = sessions
+            .with_account(&a, |account| {
+                account.wallet_mut().credit(1);
+                sessions.with_account(&b, |other| {
+                    other.wallet_mut().credit(2);
+                    Ok(())
+                })
+            })
+            .unwrap();
+        inner.unwrap();
+        outer.unwrap();
+
+        assert_eq!(sessions.account(&a).unwrap().wallet().balance(), 11);
+        assert_eq!(sessions.account(&b).unwrap().wallet().balance(), 12);
+    }
+
+    #[test]
+    fn login_with_wrong_token() {
+        let sessions = sessions();
+        let first = sessions.login("nacho", "secret").unwrap().unwrap();
+        sessions.logout(&first).unwrap();
+
+        let result = sessions.login("nacho", "other").unwrap();
+
+        assert_eq!(result, Err(LoginError::WrongToken));
+    }
+
+    #[test]
+    fn login_twice() {
+        let sessions = sessions();
+        sessions.login("nacho", "secret").unwrap().unwrap();
+
+        let result = sessions.login("nacho", "secret").unwrap();
+
+        assert_eq!(result, Err(LoginError::AlreadyConnected));
+    }
+
+    #[test]
+    fn login_with_invalid_names() {
+        let sessions = sessions();
+
+        for name in ["", "guest@127.0.0.1:80", "con espacios", &"x".repeat(33)] {
+            let result = sessions.login(name, "").unwrap();
+            assert_eq!(result, Err(LoginError::InvalidName));
+        }
+    }
+
+    #[test]
+    fn guests_are_not_valid_names() {
+        let sessions = sessions();
+
+        let guest = sessions.guest("127.0.0.1:80").unwrap();
+
+        assert!(!is_valid_name(guest.player()));
+    }
+
+    #[test]
+    fn guests_are_not_saved() {
+        let sessions = sessions();
+        let guest = sessions.guest("127.0.0.1:80").unwrap();
+
+        sessions.logout(&guest).unwrap();
+
+        assert_eq!(sessions.store.load(guest.player()).unwrap(), None);
+    }
+}
@@ -0,0 +1,44 @@
+use std::{
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Thread que ejecuta una acción cada `interval` hasta que se lo descarta.
+pub struct Snapshotter {
+    stop_sender: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Snapshotter {
+    pub fn spawn<F>(interval: Duration, action: F) -> std::io::Result<Snapshotter>
+    where
+        F: Fn() + Send + 'static,
+    {
+        let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+        let handle = thread::Builder::new()
+            .name("snapshotter".to_owned())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                    action();
+                }
+            })?;
+
+        Ok(Snapshotter {
+            stop_sender: Some(stop_sender),
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for Snapshotter {
+    fn drop(&mut self) {
+        // Al cerrarse el canal el thread sale del recv_timeout
+        drop(self.stop_sender.take());
+        if let Some(handle) = self.handle.take() {
+            if let Err(e) = handle.join() {
+                eprintln!("Snapshotter thread joined with panic: {:?}", e);
+            }
+        }
+    }
+}
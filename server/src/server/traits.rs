@@ -1,6 +1,8 @@
+use crate::{accounts::Account, config::MachineConfig};
 use std::{
     io,
     net::{Shutdown, TcpStream},
+    sync::Arc,
     time::Duration,
 };
 
@@ -8,43 +10,14 @@ pub trait Close {
     fn close(&mut self) -> io::Result<()>;
 }
 
-#[allow(dead_code)]
-pub trait TryClone {
-    fn try_clone(&self) -> io::Result<Self>
-    where
-        Self: Sized;
-}
-
-pub trait Interrupt {
-    fn alert(&mut self, when: Duration) -> io::Result<()>;
-
-    fn sleep(&mut self) -> io::Result<()>;
-}
-
-#[allow(dead_code)]
-impl TryClone for TcpStream {
-    fn try_clone(&self) -> io::Result<Self>
-    where
-        Self: Sized,
-    {
-        TcpStream::try_clone(self)
-    }
-}
-
-impl Interrupt for TcpStream {
-    #[inline(always)]
-    fn alert(&mut self, when: Duration) -> io::Result<()> {
-        self.set_nonblocking(false)?;
-        self.set_read_timeout(Some(when))
-    }
-    #[inline(always)]
-    fn sleep(&mut self) -> io::Result<()> {
-        self.set_nonblocking(true)?;
-        self.set_read_timeout(None)
+impl Close for TcpStream {
+    fn close(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
     }
 }
 
-impl Close for TcpStream {
+/// Las conexiones del loop de eventos comparten el socket con su `Outbox`.
+impl Close for Arc<mio::net::TcpStream> {
     fn close(&mut self) -> io::Result<()> {
         self.shutdown(Shutdown::Both)
     }
@@ -55,5 +28,41 @@ pub trait Config: Send + Sync + Clone + 'static {
 
     fn host(&self) -> &str;
 
-    fn initial_coins_count(&self) -> u32;
+    fn max_batch_size(&self) -> u32;
+
+    fn initial_balance(&self) -> u32;
+
+    fn accounts_path(&self) -> Option<&str>;
+
+    fn snapshot_path(&self) -> Option<&str>;
+
+    fn snapshot_interval(&self) -> Duration;
+
+    fn resume_from_snapshot(&self) -> bool;
+
+    /// Registro de auditoría de las monedas arrojadas. Sin ruta no se registran.
+    fn audit_path(&self) -> Option<&str>;
+
+    /// Tamaño a partir del cual se rota el registro de auditoría.
+    fn audit_max_bytes(&self) -> u64;
+
+    /// Threads que atienden los pedidos de los clientes.
+    fn worker_threads(&self) -> usize;
+
+    /// Pedidos que pueden esperar un thread libre. Los que no entran se
+    /// rechazan avisándole al cliente que el servidor está ocupado.
+    fn max_queued_requests(&self) -> usize;
+
+    /// Tiempo que tienen los clientes para terminar al apagarse el servidor.
+    fn shutdown_grace(&self) -> Duration;
+
+    /// Máquinas del servidor, en el orden en que se configuraron.
+    /// La primera es donde se sientan los jugadores al entrar.
+    fn machines(&self) -> &[MachineConfig];
+}
+
+pub trait AccountStore: Send + Sync {
+    fn load(&self, name: &str) -> io::Result<Option<Account>>;
+
+    fn save(&self, account: &Account) -> io::Result<()>;
 }
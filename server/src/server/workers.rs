@@ -0,0 +1,163 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use common::thread_pool::ThreadPool;
+use mio::{Token, Waker};
+
+/// Pedido de una conexión que terminó de atenderse.
+#[derive(Clone, Copy)]
+pub struct Done {
+    pub token: Token,
+    /// Si la conexión sigue abierta.
+    pub open: bool,
+}
+
+/// Threads que atienden los pedidos de los clientes. Se aceptan tantos
+/// pedidos como threads más lugares en la fila; el resto se rechaza.
+/// Cada pedido terminado se avisa al loop de eventos por `finished`.
+pub struct Workers {
+    pool: ThreadPool,
+    threads: usize,
+    capacity: usize,
+    in_flight: usize,
+    done_sender: Sender<Done>,
+    done_receiver: Receiver<Done>,
+    waker: Arc<Waker>,
+}
+
+/// Avisa que el pedido terminó aunque el thread entre en pánico;
+/// en ese caso la conexión se cierra.
+struct DoneGuard {
+    done: Done,
+    sender: Sender<Done>,
+    waker: Arc<Waker>,
+}
+
+impl Drop for DoneGuard {
+    fn drop(&mut self) {
+        if self.sender.send(self.done).is_ok() {
+            if let Err(e) = self.waker.wake() {
+                eprintln!("Could not wake up the server loop: {}", e);
+            }
+        }
+    }
+}
+
+impl Workers {
+    pub fn new(threads: usize, max_queued: usize, waker: Arc<Waker>) -> Workers {
+        let (done_sender, done_receiver) = mpsc::channel();
+        Workers {
+            pool: ThreadPool::new(threads),
+            threads,
+            capacity: threads + max_queued,
+            in_flight: 0,
+            done_sender,
+            done_receiver,
+            waker,
+        }
+    }
+
+    /// Cambia cuántos pedidos pueden esperar un thread libre. Los que ya
+    /// esperan no se rechazan aunque ahora sobren.
+    pub fn set_max_queued(&mut self, max_queued: usize) {
+        self.capacity = self.threads + max_queued;
+    }
+
+    /// Encola el pedido de la conexión `token`, si hay lugar. `job` recibe
+    /// cuánto esperó en la fila y devuelve si la conexión sigue abierta.
+    pub fn execute<F>(&mut self, token: Token, job: F) -> bool
+    where
+        F: FnOnce(Duration) -> bool + Send + 'static,
+    {
+        if self.in_flight >= self.capacity {
+            return false;
+        }
+
+        let queued = Instant::now();
+        let guard = DoneGuard {
+            done: Done { token, open: false },
+            sender: self.done_sender.clone(),
+            waker: self.waker.clone(),
+        };
+        // Si el pool no lo acepta, el guard igual avisa y la conexión se cierra
+        self.in_flight += 1;
+        if let Err(e) = self.pool.execute(move || {
+            let mut guard = guard;
+            guard.done.open = job(queued.elapsed());
+        }) {
+            eprintln!("Could not queue request: {}", e);
+        }
+        true
+    }
+
+    /// Pedidos que terminaron desde la última llamada.
+    pub fn finished(&mut self) -> Vec<Done> {
+        let done: Vec<Done> = self.done_receiver.try_iter().collect();
+        self.in_flight -= done.len();
+        done
+    }
+}
+
+#[cfg(test)]
+mod workers_tests {
+    use std::sync::{mpsc, Arc, Mutex};
+
+    use mio::{Events, Poll, Token, Waker};
+
+    use super::{Done, Workers};
+
+    fn wait_done(poll: &mut Poll, workers: &mut Workers, n: usize) -> Vec<Done> {
+        let mut done = Vec::new();
+        let mut events = Events::with_capacity(4);
+        while done.len() < n {
+            poll.poll(&mut events, None).unwrap();
+            done.extend(workers.finished());
+        }
+        done
+    }
+
+    #[test]
+    fn rejects_requests_beyond_threads_and_queue() {
+        let mut poll = Poll::new().unwrap();
+        let waker = Arc::new(Waker::new(poll.registry(), Token(0)).unwrap());
+        let mut workers = Workers::new(1, 1, waker);
+
+        let (release, released) = mpsc::channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        for token in 1..=2 {
+            let released = released.clone();
+            assert!(workers.execute(Token(token), move |_| {
+                released.lock().unwrap().recv().is_ok()
+            }));
+        }
+        assert!(!workers.execute(Token(3), |_| true));
+
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        let done = wait_done(&mut poll, &mut workers, 2);
+        assert!(done.iter().all(|done| done.open));
+
+        assert!(workers.execute(Token(3), |_| true));
+        assert_eq!(wait_done(&mut poll, &mut workers, 1)[0].token, Token(3));
+    }
+
+    #[test]
+    fn a_panicking_request_closes_its_connection() {
+        let mut poll = Poll::new().unwrap();
+        let waker = Arc::new(Waker::new(poll.registry(), Token(0)).unwrap());
+        let mut workers = Workers::new(1, 0, waker);
+
+        assert!(workers.execute(Token(7), |_| panic!("Test panic")));
+        let done = wait_done(&mut poll, &mut workers, 1);
+        assert_eq!(done[0].token, Token(7));
+        assert!(!done[0].open);
+
+        assert!(workers.execute(Token(8), |_| true));
+        assert!(wait_done(&mut poll, &mut workers, 1)[0].open);
+    }
+}
@@ -0,0 +1,297 @@
+use std::{collections::BTreeMap, thread};
+
+use crate::{
+    config::MachineConfig,
+    machine::{CoinOutcome, Machine},
+};
+
+/// Media y varianza calculadas de a un valor (Welford), combinables
+/// entre corridas distintas (Chan et al.).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RunningStats {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    pub fn push(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    pub fn merge(&mut self, other: &RunningStats) {
+        if other.count == 0 {
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.m2 +=
+            other.m2 + delta * delta * (self.count as f64 * other.count as f64) / count as f64;
+        self.count = count;
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Varianza poblacional. Es 0 si no hay valores.
+    pub fn variance(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.m2 / self.count as f64
+        }
+    }
+}
+
+/// Resultado de arrojar monedas en una o más máquinas iguales.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    inserted: u64,
+    paid: u64,
+    /// Monedas pagadas por el pozo acumulado, aparte de las que cayeron
+    jackpot_paid: u64,
+    jackpots: u64,
+    /// Monedas que rebalsaron la máquina hacia el jugador
+    spilled: u64,
+    /// Cuántas tiradas pagaron cada cantidad de monedas (0 incluido)
+    histogram: BTreeMap<u32, u64>,
+    pool: RunningStats,
+    /// Tiradas hasta el primer premio de cada corrida; `None` si nunca pagó
+    first_payouts: Vec<Option<u64>>,
+}
+
+impl Report {
+    pub fn inserted(&self) -> u64 {
+        self.inserted
+    }
+
+    pub fn paid(&self) -> u64 {
+        self.paid
+    }
+
+    pub fn jackpot_paid(&self) -> u64 {
+        self.jackpot_paid
+    }
+
+    /// Veces que se ganó el pozo.
+    pub fn jackpots(&self) -> u64 {
+        self.jackpots
+    }
+
+    pub fn spilled(&self) -> u64 {
+        self.spilled
+    }
+
+    /// Retorno al jugador: monedas ganadas por moneda arrojada,
+    /// contando las del pozo y las que rebalsaron.
+    pub fn rtp(&self) -> f64 {
+        if self.inserted == 0 {
+            0.0
+        } else {
+            (self.paid + self.jackpot_paid + self.spilled) as f64 / self.inserted as f64
+        }
+    }
+
+    pub fn histogram(&self) -> &BTreeMap<u32, u64> {
+        &self.histogram
+    }
+
+    /// Tamaño del pool después de cada tirada.
+    pub fn pool(&self) -> &RunningStats {
+        &self.pool
+    }
+
+    pub fn first_payouts(&self) -> &[Option<u64>] {
+        &self.first_payouts
+    }
+
+    pub fn merge(&mut self, other: &Report) {
+        self.inserted += other.inserted;
+        self.paid += other.paid;
+        self.jackpot_paid += other.jackpot_paid;
+        self.jackpots += other.jackpots;
+        self.spilled += other.spilled;
+        for (fell, count) in &other.histogram {
+            *self.histogram.entry(*fell).or_insert(0) += count;
+        }
+        self.pool.merge(&other.pool);
+        self.first_payouts.extend_from_slice(&other.first_payouts);
+    }
+}
+
+/// Arroja `coins` monedas de a una en una máquina configurada según
+/// `config`, con el generador aleatorio arrancando de `seed`.
+pub fn simulate(config: &MachineConfig, seed: u64, coins: u64) -> Result<Report, String> {
+    let mut machine = Machine::from_config(config, None)?.seeded(seed);
+    let mut report = Report {
+        first_payouts: vec![None],
+        ..Report::default()
+    };
+
+    for i in 1..=coins {
+        let CoinOutcome {
+            fell,
+            spilled,
+            jackpot,
+            ..
+        } = machine.insert_coin();
+        report.inserted += 1;
+        report.paid += u64::from(fell);
+        report.spilled += u64::from(spilled);
+        if jackpot > 0 {
+            report.jackpot_paid += u64::from(jackpot);
+            report.jackpots += 1;
+        }
+        *report.histogram.entry(fell).or_insert(0) += 1;
+        report.pool.push(f64::from(machine.get_pool()));
+        if fell > 0 && report.first_payouts[0].is_none() {
+            report.first_payouts[0] = Some(i);
+        }
+    }
+    Ok(report)
+}
+
+/// Corre una simulación por semilla, cada una en su thread, y junta los resultados.
+pub fn simulate_seeds(
+    config: &MachineConfig,
+    seeds: impl IntoIterator<Item = u64>,
+    coins: u64,
+) -> Result<Report, String> {
+    let reports = thread::scope(|scope| {
+        let handles: Vec<_> = seeds
+            .into_iter()
+            .map(|seed| scope.spawn(move || simulate(config, seed, coins)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or(Err("simulation thread panicked".to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+
+    let mut total = Report::default();
+    for report in &reports {
+        total.merge(report);
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod simulation_tests {
+    use super::*;
+    use crate::config::FileConfig;
+    use crate::server::traits::Config;
+    use std::io::Cursor;
+
+    fn machine_config(extra: &str) -> MachineConfig {
+        let cursor = Cursor::new(format!(
+            "port=8080
+host=localhost
+initial_coins_count=100
+{}",
+            extra
+        ));
+        FileConfig::new_from_file(cursor).unwrap().machines()[0].clone()
+    }
+
+    #[test]
+    fn running_stats() {
+        let mut stats = RunningStats::default();
+        for x in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            stats.push(x);
+        }
+
+        assert_eq!(stats.mean(), 5.0);
+        assert_eq!(stats.variance(), 4.0);
+    }
+
+    #[test]
+    fn merged_stats_match_single_pass() {
+        let values: Vec<f64> = (0..100).map(|i| f64::from(i * i % 37)).collect();
+        let mut all = RunningStats::default();
+        let mut first = RunningStats::default();
+        let mut second = RunningStats::default();
+        for (i, x) in values.iter().enumerate() {
+            all.push(*x);
+            if i < 30 {
+                first.push(*x);
+            } else {
+                second.push(*x);
+            }
+        }
+
+        first.merge(&second);
+
+        assert!((first.mean() - all.mean()).abs() < 1e-9);
+        assert!((first.variance() - all.variance()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn simulation_is_reproducible() {
+        let config = machine_config("");
+
+        let a = simulate(&config, 3, 5000).unwrap();
+        let b = simulate(&config, 3, 5000).unwrap();
+
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn report_adds_up() {
+        let config = machine_config("");
+
+        let report = simulate_seeds(&config, 0..3, 2000).unwrap();
+
+        assert_eq!(report.inserted(), 6000);
+        assert_eq!(report.histogram().values().sum::<u64>(), 6000);
+        let paid: u64 = report
+            .histogram()
+            .iter()
+            .map(|(fell, count)| u64::from(*fell) * count)
+            .sum();
+        assert_eq!(report.paid(), paid);
+        assert_eq!(report.first_payouts().len(), 3);
+    }
+
+    #[test]
+    fn fixed_rtp_converges() {
+        let config = machine_config("payout_model=fixed_rtp\nrtp=0.9\nprobability=0.25");
+
+        let report = simulate_seeds(&config, 0..4, 50_000).unwrap();
+
+        assert!((report.rtp() - 0.9).abs() < 0.02, "rtp {}", report.rtp());
+    }
+
+    #[test]
+    fn never_paying_machine() {
+        let config = machine_config("payout_model=step\nthreshold=100000\ncapacity=100000");
+
+        let report = simulate(&config, 0, 100).unwrap();
+
+        assert_eq!(report.rtp(), 0.0);
+        assert_eq!(report.first_payouts(), [None]);
+        assert_eq!(report.pool().mean(), 150.5);
+    }
+
+    #[test]
+    fn jackpot_counts_in_rtp() {
+        let config = machine_config(
+            "payout_model=step\nthreshold=1000000\ncapacity=1000000\njackpot_contribution=0.1\njackpot_probability=0.01",
+        );
+
+        let report = simulate(&config, 0, 100_000).unwrap();
+
+        assert_eq!(report.paid(), 0);
+        assert!(report.jackpots() > 0);
+        // Lo que quedó en el pozo al terminar no se pagó
+        assert!((report.rtp() - 0.1).abs() < 0.01, "rtp {}", report.rtp());
+    }
+}
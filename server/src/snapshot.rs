@@ -0,0 +1,88 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::machine::MachineSnapshot;
+
+/// Estado del servidor que sobrevive a un reinicio.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    taken_at: u64,
+    machines: BTreeMap<u32, MachineSnapshot>,
+}
+
+impl Snapshot {
+    pub fn new(machines: BTreeMap<u32, MachineSnapshot>) -> Snapshot {
+        let taken_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Snapshot { taken_at, machines }
+    }
+
+    pub fn machine(&self, id: u32) -> Option<&MachineSnapshot> {
+        self.machines.get(&id)
+    }
+}
+
+/// Escribe el snapshot en un archivo temporal y lo renombra sobre `path`,
+/// así nunca queda un snapshot a medio escribir.
+pub fn save<P: AsRef<Path>>(path: P, snapshot: &Snapshot) -> io::Result<()> {
+    let path = path.as_ref();
+    let tmp_path = path.with_extension("tmp");
+
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(&serde_json::to_vec_pretty(snapshot)?)?;
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Devuelve `None` si todavía no se tomó ningún snapshot.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Option<Snapshot>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use super::*;
+    use crate::machine::Machine;
+    use std::env;
+
+    #[test]
+    fn save_and_load() {
+        let path = env::temp_dir().join(format!("coin_pusher_snapshot_{}", std::process::id()));
+        let machines = BTreeMap::from([
+            (1, Machine::with(123).unwrap().snapshot()),
+            (4, Machine::with(7).unwrap().snapshot()),
+        ]);
+        let snapshot = Snapshot::new(machines);
+
+        save(&path, &snapshot).unwrap();
+
+        let loaded = load(&path).unwrap().unwrap();
+        assert_eq!(loaded, snapshot);
+        assert_eq!(
+            loaded.machine(4),
+            Some(&Machine::with(7).unwrap().snapshot())
+        );
+        assert_eq!(loaded.machine(2), None);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn load_missing_snapshot() {
+        let path = env::temp_dir().join("coin_pusher_snapshot_that_does_not_exist");
+
+        assert_eq!(load(path).unwrap(), None);
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+/// Monedas de un jugador.
+/// Cada moneda arrojada se descuenta y cada moneda ganada se acredita.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Wallet {
+    balance: u32,
+}
+
+impl Wallet {
+    pub fn with(balance: u32) -> Wallet {
+        Wallet { balance }
+    }
+
+    pub fn balance(&self) -> u32 {
+        self.balance
+    }
+
+    pub fn debit(&mut self, amount: u32) -> Result<(), &'static str> {
+        if amount > self.balance {
+            return Err("insufficient funds");
+        }
+
+        self.balance -= amount;
+        Ok(())
+    }
+
+    pub fn credit(&mut self, amount: u32) {
+        self.balance = self.balance.saturating_add(amount);
+    }
+}
+
+#[cfg(test)]
+mod wallet_tests {
+    use super::*;
+
+    #[test]
+    fn debit_and_credit() {
+        let mut w = Wallet::with(10);
+
+        assert!(w.debit(4).is_ok());
+        w.credit(7);
+
+        assert_eq!(w.balance(), 13);
+    }
+
+    #[test]
+    fn debit_whole_balance() {
+        let mut w = Wallet::with(3);
+
+        assert!(w.debit(3).is_ok());
+        assert_eq!(w.balance(), 0);
+    }
+
+    #[test]
+    fn debit_with_insufficient_funds() {
+        let mut w = Wallet::with(3);
+
+        assert!(w.debit(4).is_err());
+        assert_eq!(w.balance(), 3);
+    }
+
+    #[test]
+    fn credit_saturates() {
+        let mut w = Wallet::with(u32::MAX - 1);
+
+        w.credit(5);
+
+        assert_eq!(w.balance(), u32::MAX);
+    }
+}